@@ -0,0 +1,986 @@
+use anyhow::Result;
+use async_graphql::{Enum, Object};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Offset, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use rust_decimal::prelude::*;
+use rust_decimal_macros::*;
+use serde::de::DeserializeOwned;
+
+use crate::billing;
+use crate::dynamodb::{Client, Condition};
+use crate::models::{Compaction, DynamoItem, Electricity, PlaceCondition, SolarProduction, Tariff};
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Resolution {
+    Raw,
+    Minute5,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            Resolution::Raw => None,
+            Resolution::Minute5 => Some(Duration::minutes(5)),
+            Resolution::Hour => Some(Duration::hours(1)),
+            Resolution::Day => Some(Duration::days(1)),
+        }
+    }
+
+    // Buckets are aligned to local time in `tz`. Days start at local midnight
+    // and so are not always 24 hours long.
+    pub fn bucket_start(&self, timestamp: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+        match (self, self.duration()) {
+            (Resolution::Day, _) => local_midnight(local_date(timestamp, tz), tz),
+            (_, Some(duration)) => {
+                let secs = duration.num_seconds();
+                let offset = i64::from(
+                    tz.offset_from_utc_datetime(&timestamp.naive_utc())
+                        .fix()
+                        .local_minus_utc(),
+                );
+                Utc.timestamp(
+                    (timestamp.timestamp() + offset).div_euclid(secs) * secs - offset,
+                    0,
+                )
+            }
+            (_, None) => timestamp,
+        }
+    }
+
+    pub fn next_bucket(&self, start: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+        match (self, self.duration()) {
+            (Resolution::Day, _) => local_midnight(local_date(start, tz).succ(), tz),
+            (_, Some(duration)) => start + duration,
+            (_, None) => start,
+        }
+    }
+}
+
+fn local_date(timestamp: DateTime<Utc>, tz: Tz) -> NaiveDate {
+    tz.from_utc_datetime(&timestamp.naive_utc())
+        .naive_local()
+        .date()
+}
+
+// Some zones skip midnight on DST changes; the day then starts at the first
+// local hour that exists.
+fn local_midnight(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    (0..24)
+        .find_map(|h| tz.from_local_datetime(&date.and_hms(h, 0, 0)).earliest())
+        .map(|x| x.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&date.and_hms(0, 0, 0)))
+}
+
+pub fn format_time(timestamp: DateTime<Utc>, tz: Tz) -> String {
+    timestamp
+        .with_timezone(&tz)
+        .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+pub struct EnergyInterval {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub imported_kwh: Decimal,
+    pub exported_kwh: Decimal,
+    pub estimated: bool,
+    pub tz: Tz,
+}
+
+#[Object]
+impl EnergyInterval {
+    async fn start(&self) -> String {
+        format_time(self.start, self.tz)
+    }
+
+    async fn end(&self) -> String {
+        format_time(self.end, self.tz)
+    }
+
+    async fn imported_kwh(&self) -> String {
+        format!("{}", &self.imported_kwh)
+    }
+
+    async fn exported_kwh(&self) -> String {
+        format!("{}", &self.exported_kwh)
+    }
+
+    async fn estimated(&self) -> bool {
+        self.estimated
+    }
+}
+
+// None when the meter was reset, e.g. replaced, and the energy in between is
+// unknown.
+pub fn delta(prev: Decimal, cur: Decimal) -> Option<Decimal> {
+    if cur >= prev {
+        return Some(cur - prev);
+    }
+
+    // A meter that wraps around, assumed to count up to the next power of ten,
+    // drops by nearly its whole width. A smaller drop is a reset.
+    let digits = prev.trunc().to_string().trim_start_matches('-').len() as u32;
+    // Wider meters than u64 can hold are not expected and read as a reset.
+    let modulus = Decimal::from_u64(10_u64.checked_pow(digits)?)?;
+    let wrapped = modulus - prev + cur;
+    if wrapped < modulus / dec!(10) {
+        Some(wrapped)
+    } else {
+        None
+    }
+}
+
+// Summary tiers written by the compaction job. Each holds downsampled
+// readings under its own sk prefix once the raw ones are deleted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Tier {
+    Minute5,
+    Hour,
+}
+
+impl Tier {
+    pub const ALL: [Tier; 2] = [Tier::Minute5, Tier::Hour];
+
+    pub fn sk_prefix(self) -> String {
+        match self {
+            Tier::Minute5 => "AGG5M#TS#".to_owned(),
+            Tier::Hour => "AGG1H#TS#".to_owned(),
+        }
+    }
+
+    pub fn resolution(self) -> Resolution {
+        match self {
+            Tier::Minute5 => Resolution::Minute5,
+            Tier::Hour => Resolution::Hour,
+        }
+    }
+
+    // The coarsest tier that still has buckets no longer than `resolution`.
+    pub fn for_resolution(resolution: Resolution) -> Self {
+        match resolution {
+            Resolution::Raw | Resolution::Minute5 => Tier::Minute5,
+            Resolution::Hour | Resolution::Day => Tier::Hour,
+        }
+    }
+}
+
+// Raw readings of the device before this time have been compacted.
+pub async fn compacted_until(dynamodb: &Client, device: &str) -> Result<Option<DateTime<Utc>>> {
+    let compaction: Option<Compaction> = dynamodb.find_item("COMPACTION", device).await?;
+    Ok(compaction.map(|x| x.until))
+}
+
+fn sk_between(prefix: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Condition {
+    let from = from.map_or_else(String::new, |x| format!("{:?}", x));
+    let to = to.map_or_else(|| "~".to_owned(), |x| format!("{:?}", x));
+    Condition::Between(format!("{}{}", prefix, from), format!("{}{}", prefix, to))
+}
+
+// Electricity or PlaceCondition readings of a device, taken from the summary
+// tier for `resolution` where the raw readings have been compacted and from
+// the raw readings elsewhere. Readings that arrived for days already
+// compacted stay raw and are merged in.
+pub async fn tiered_range<D>(
+    dynamodb: &Client,
+    device: &str,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    resolution: Resolution,
+) -> Result<Vec<D>>
+where
+    D: DeserializeOwned + Bucketed,
+{
+    let raw = Electricity::sk_prefix();
+    let until = compacted_until(dynamodb, device)
+        .await?
+        .filter(|x| from.map_or(true, |from| from < *x));
+    let until = match until {
+        Some(x) => x,
+        None => {
+            return dynamodb
+                .get_range(device, Some(sk_between(&raw, from, to)))
+                .await
+        }
+    };
+
+    // The tier end is inclusive, so it stops just before the raw readings.
+    let tier = Tier::for_resolution(resolution).sk_prefix();
+    let tier_to = to.map_or(until, |x| x.min(until)) - Duration::milliseconds(1);
+    let mut items: Vec<D> = dynamodb
+        .get_range(device, Some(sk_between(&tier, from, Some(tier_to))))
+        .await?;
+    let late: Vec<D> = dynamodb
+        .get_range(device, Some(sk_between(&raw, from, Some(tier_to))))
+        .await?;
+    if !late.is_empty() {
+        items.extend(late);
+        items.sort_by_key(|x| x.timestamp());
+    }
+    if to.map_or(true, |x| x >= until) {
+        let rest: Vec<D> = dynamodb
+            .get_range(device, Some(sk_between(&raw, Some(until), to)))
+            .await?;
+        items.extend(rest);
+    }
+
+    Ok(items)
+}
+
+// The sk prefix holding the device's readings at `at`, given the time its raw
+// readings are compacted until.
+fn prefix_at(until: Option<DateTime<Utc>>, at: DateTime<Utc>) -> String {
+    match until {
+        Some(until) if at < until => Tier::Minute5.sk_prefix(),
+        _ => Electricity::sk_prefix(),
+    }
+}
+
+// The last reading at or before `at`, raw or from the summary tier where the
+// raw readings have been compacted.
+pub async fn reading_at<D>(dynamodb: &Client, device: &str, at: DateTime<Utc>) -> Result<Option<D>>
+where
+    D: DeserializeOwned,
+{
+    let until = compacted_until(dynamodb, device).await?;
+    dynamodb
+        .get_last_item(device, sk_between(&prefix_at(until, at), None, Some(at)))
+        .await
+}
+
+// Readings covering `from`..`to`, with the last one before and the first one
+// after the range so that energy can be split at its ends.
+pub async fn electricity_readings(
+    dynamodb: &Client,
+    device: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<Electricity>> {
+    let until = compacted_until(dynamodb, device).await?;
+    let prefix = |t: DateTime<Utc>| prefix_at(until, t);
+
+    let before: Option<Electricity> = dynamodb
+        .get_last_item(device, sk_between(&prefix(from), None, Some(from)))
+        .await?;
+    let mut readings: Vec<Electricity> =
+        tiered_range(dynamodb, device, Some(from), Some(to), Resolution::Minute5).await?;
+    let (after, _): (Vec<Electricity>, _) = dynamodb
+        .get_items(
+            device,
+            Some(sk_between(&prefix(to), Some(to), None)),
+            Vec::new(),
+            None,
+            None,
+            Some(1),
+            None,
+        )
+        .await?;
+
+    if let Some(before) = before {
+        if readings.first().map(|x| x.timestamp) != Some(before.timestamp) {
+            readings.insert(0, before);
+        }
+    }
+    for x in after {
+        if readings.last().map(|y| y.timestamp) != Some(x.timestamp) {
+            readings.push(x);
+        }
+    }
+
+    Ok(readings)
+}
+
+// Solar readings from the last one before `from`, so that the production
+// at the start of the range is not lost.
+pub async fn solar_readings(
+    dynamodb: &Client,
+    device: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<SolarProduction>> {
+    let prefix = SolarProduction::sk_prefix();
+    let sk = |x: DateTime<Utc>| format!("{}{:?}", prefix, x);
+
+    let before: Option<SolarProduction> = dynamodb
+        .get_last_item(device, Condition::Between(prefix.clone(), sk(from)))
+        .await?;
+    let mut readings: Vec<SolarProduction> = dynamodb
+        .get_range(device, Some(Condition::Between(sk(from), sk(to))))
+        .await?;
+
+    if let Some(before) = before {
+        if readings.first().map(|x| x.timestamp) != Some(before.timestamp) {
+            readings.insert(0, before);
+        }
+    }
+
+    Ok(readings)
+}
+
+pub fn energy_intervals(
+    readings: &[Electricity],
+    resolution: Resolution,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    tz: Tz,
+) -> Vec<EnergyInterval> {
+    let mut intervals: Vec<EnergyInterval> = Vec::new();
+
+    for pair in readings.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        let imported = delta(a.cumulative_kwh_p, b.cumulative_kwh_p);
+        let exported = delta(a.cumulative_kwh_n, b.cumulative_kwh_n);
+        // Energy across a reset is left out and the interval marked estimated.
+        let reset = imported.is_none() || exported.is_none();
+        let (imported, exported) = (imported.unwrap_or_default(), exported.unwrap_or_default());
+
+        if resolution == Resolution::Raw {
+            if a.timestamp >= from && b.timestamp <= to {
+                intervals.push(EnergyInterval {
+                    start: a.timestamp,
+                    end: b.timestamp,
+                    imported_kwh: imported,
+                    exported_kwh: exported,
+                    estimated: reset,
+                    tz,
+                });
+            }
+            continue;
+        }
+
+        let span = (b.timestamp - a.timestamp).num_milliseconds() as f64;
+        if span <= 0.0 {
+            continue;
+        }
+
+        let mut start = resolution.bucket_start(a.timestamp, tz);
+        let estimated = reset || b.timestamp > resolution.next_bucket(start, tz);
+        while start < b.timestamp {
+            let end = resolution.next_bucket(start, tz);
+            let overlap = (end.min(b.timestamp) - start.max(a.timestamp)).num_milliseconds() as f64;
+            let ratio = Decimal::from_f64(overlap / span).unwrap_or_default();
+
+            if start >= resolution.bucket_start(from, tz) && start < to {
+                match intervals.last_mut().filter(|x| x.start == start) {
+                    Some(x) => {
+                        x.imported_kwh += imported * ratio;
+                        x.exported_kwh += exported * ratio;
+                        x.estimated |= estimated;
+                    }
+                    None => intervals.push(EnergyInterval {
+                        start,
+                        end,
+                        imported_kwh: imported * ratio,
+                        exported_kwh: exported * ratio,
+                        estimated,
+                        tz,
+                    }),
+                }
+            }
+
+            start = end;
+        }
+    }
+
+    for x in intervals.iter_mut() {
+        x.imported_kwh = x.imported_kwh.round_dp(6);
+        x.exported_kwh = x.exported_kwh.round_dp(6);
+    }
+
+    intervals
+}
+
+pub struct EnergySummary {
+    pub imported_kwh: Decimal,
+    pub exported_kwh: Decimal,
+    pub peak_w: Option<u32>,
+    pub peak_at: Option<DateTime<Utc>>,
+    pub average_w: Option<f64>,
+}
+
+#[Object]
+impl EnergySummary {
+    async fn imported_kwh(&self) -> String {
+        format!("{}", &self.imported_kwh)
+    }
+
+    async fn exported_kwh(&self) -> String {
+        format!("{}", &self.exported_kwh)
+    }
+
+    async fn peak_w(&self) -> Option<String> {
+        self.peak_w.map(|x| format!("{}", &x))
+    }
+
+    async fn peak_at(&self) -> Option<String> {
+        self.peak_at.map(|x| format!("{:?}", &x))
+    }
+
+    async fn average_w(&self) -> Option<String> {
+        self.average_w.map(|x| format!("{:.1}", &x))
+    }
+}
+
+pub fn energy_summary(
+    readings: &[Electricity],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> EnergySummary {
+    let mut imported_kwh = Decimal::zero();
+    let mut exported_kwh = Decimal::zero();
+    let mut weighted_w = 0.0;
+    let mut covered_ms = 0.0;
+
+    for pair in readings.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        let span = (b.timestamp - a.timestamp).num_milliseconds() as f64;
+        let overlap = (b.timestamp.min(to) - a.timestamp.max(from)).num_milliseconds() as f64;
+        if span <= 0.0 || overlap <= 0.0 {
+            continue;
+        }
+
+        let ratio = Decimal::from_f64(overlap / span).unwrap_or_default();
+        imported_kwh += delta(a.cumulative_kwh_p, b.cumulative_kwh_p).unwrap_or_default() * ratio;
+        exported_kwh += delta(a.cumulative_kwh_n, b.cumulative_kwh_n).unwrap_or_default() * ratio;
+        weighted_w += a.current_w as f64 * overlap;
+        covered_ms += overlap;
+    }
+
+    let peak = readings
+        .iter()
+        .filter(|x| x.timestamp >= from && x.timestamp <= to)
+        .max_by_key(|x| x.current_w);
+
+    EnergySummary {
+        imported_kwh: imported_kwh.round_dp(6),
+        exported_kwh: exported_kwh.round_dp(6),
+        peak_w: peak.map(|x| x.current_w),
+        peak_at: peak.map(|x| x.timestamp),
+        average_w: Some(weighted_w / covered_ms).filter(|_| covered_ms > 0.0),
+    }
+}
+
+pub struct SolarAnalytics {
+    pub produced_kwh: Decimal,
+    pub imported_kwh: Decimal,
+    pub exported_kwh: Decimal,
+    pub self_consumed_kwh: Decimal,
+    pub export_revenue: Option<Decimal>,
+    pub self_consumption_savings: Option<Decimal>,
+    pub battery_kwh: Decimal,
+    pub battery_shifted_kwh: Decimal,
+    pub battery_savings: Option<Decimal>,
+}
+
+#[Object]
+impl SolarAnalytics {
+    async fn produced_kwh(&self) -> String {
+        format!("{}", &self.produced_kwh)
+    }
+
+    async fn imported_kwh(&self) -> String {
+        format!("{}", &self.imported_kwh)
+    }
+
+    async fn exported_kwh(&self) -> String {
+        format!("{}", &self.exported_kwh)
+    }
+
+    async fn self_consumed_kwh(&self) -> String {
+        format!("{}", &self.self_consumed_kwh)
+    }
+
+    // Share of the production used in the house.
+    async fn self_consumption_ratio(&self) -> Option<String> {
+        fraction(self.self_consumed_kwh, self.produced_kwh)
+    }
+
+    // Share of the consumption covered by the production.
+    async fn self_sufficiency_ratio(&self) -> Option<String> {
+        fraction(
+            self.self_consumed_kwh,
+            self.self_consumed_kwh + self.imported_kwh,
+        )
+    }
+
+    async fn export_revenue(&self) -> Option<String> {
+        self.export_revenue.map(|x| format!("{}", &x))
+    }
+
+    async fn self_consumption_savings(&self) -> Option<String> {
+        self.self_consumption_savings.map(|x| format!("{}", &x))
+    }
+
+    async fn battery_kwh(&self) -> String {
+        format!("{}", &self.battery_kwh)
+    }
+
+    async fn battery_shifted_kwh(&self) -> String {
+        format!("{}", &self.battery_shifted_kwh)
+    }
+
+    async fn battery_savings(&self) -> Option<String> {
+        self.battery_savings.map(|x| format!("{}", &x))
+    }
+}
+
+fn fraction(numerator: Decimal, denominator: Decimal) -> Option<String> {
+    if denominator <= Decimal::zero() {
+        return None;
+    }
+    Some(format!("{:.3}", numerator / denominator))
+}
+
+// Price of the last band the import reaches; that is what each kWh not
+// imported would have cost.
+fn marginal_price(tariff: &Tariff, imported_kwh: Decimal) -> Option<Decimal> {
+    billing::band_costs(tariff, imported_kwh)
+        .last()
+        .map(|x| x.price_per_kwh)
+        .or_else(|| tariff.bands.first().map(|x| x.price_per_kwh))
+}
+
+// Everything the inverter produced and the meter did not export was used in
+// the house. A battery of `battery_kwh` is assumed to charge from each day's
+// export and to cover that day's import, so one cycle a day at most.
+pub fn solar_analytics(
+    solar: &[SolarProduction],
+    readings: &[Electricity],
+    tariff: Option<&Tariff>,
+    battery_kwh: Decimal,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    tz: Tz,
+) -> SolarAnalytics {
+    let mut produced_kwh = Decimal::zero();
+    for pair in solar.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        let span = (b.timestamp - a.timestamp).num_milliseconds() as f64;
+        let overlap = (b.timestamp.min(to) - a.timestamp.max(from)).num_milliseconds() as f64;
+        if span <= 0.0 || overlap <= 0.0 {
+            continue;
+        }
+
+        let ratio = Decimal::from_f64(overlap / span).unwrap_or_default();
+        produced_kwh += delta(a.cumulative_kwh, b.cumulative_kwh).unwrap_or_default() * ratio;
+    }
+
+    let summary = energy_summary(readings, from, to);
+    let imported_kwh = summary.imported_kwh.round_dp(3);
+    let exported_kwh = summary.exported_kwh.round_dp(3);
+    let produced_kwh = produced_kwh.round_dp(3);
+    let self_consumed_kwh = (produced_kwh - exported_kwh).max(Decimal::zero());
+
+    // Round-trip efficiency of a typical home battery.
+    let efficiency = dec!(0.9);
+    let mut battery_shifted_kwh = Decimal::zero();
+    for day in energy_intervals(readings, Resolution::Day, from, to, tz) {
+        let charged = day.exported_kwh.min(battery_kwh);
+        battery_shifted_kwh += (charged * efficiency).min(day.imported_kwh);
+    }
+    let battery_shifted_kwh = battery_shifted_kwh.round_dp(3);
+
+    let import_price = tariff.and_then(|x| marginal_price(x, imported_kwh));
+    let export_price = tariff.map(|x| x.export_price_per_kwh.unwrap_or_default());
+
+    SolarAnalytics {
+        produced_kwh,
+        imported_kwh,
+        exported_kwh,
+        self_consumed_kwh,
+        export_revenue: tariff.map(|x| billing::export_credit(x, exported_kwh)),
+        self_consumption_savings: import_price.map(|x| (self_consumed_kwh * x).round_dp(2)),
+        battery_kwh,
+        battery_shifted_kwh,
+        // Each kWh shifted is no longer imported, but took more than a kWh of
+        // export to store.
+        battery_savings: import_price.zip(export_price).map(|(import, export)| {
+            let lost = battery_shifted_kwh / efficiency * export;
+            (battery_shifted_kwh * import - lost).round_dp(2)
+        }),
+    }
+}
+
+fn buckets<T, F>(
+    items: Vec<T>,
+    resolution: Resolution,
+    tz: Tz,
+    timestamp: F,
+) -> Vec<(DateTime<Utc>, Vec<T>)>
+where
+    F: Fn(&T) -> DateTime<Utc>,
+{
+    let mut buckets: Vec<(DateTime<Utc>, Vec<T>)> = Vec::new();
+
+    for item in items {
+        let start = resolution.bucket_start(timestamp(&item), tz);
+        match buckets.last_mut().filter(|x| x.0 == start) {
+            Some(bucket) => bucket.1.push(item),
+            None => buckets.push((start, vec![item])),
+        }
+    }
+
+    buckets
+}
+
+fn average<I>(values: I) -> Option<f64>
+where
+    I: Iterator<Item = f64>,
+{
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), x| (sum + x, count + 1));
+    Some(sum / count as f64).filter(|_| count > 0)
+}
+
+pub fn downsample_electricity(
+    items: Vec<Electricity>,
+    resolution: Resolution,
+    tz: Tz,
+) -> Vec<Electricity> {
+    if resolution == Resolution::Raw {
+        return items;
+    }
+
+    buckets(items, resolution, tz, |x| x.timestamp)
+        .into_iter()
+        .filter_map(|(start, xs)| {
+            let current_w = average(xs.iter().map(|x| x.current_w as f64))?;
+            let last = xs.into_iter().last()?;
+            Some(Electricity {
+                timestamp: start,
+                current_w: current_w.round() as u32,
+                ..last
+            })
+        })
+        .collect()
+}
+
+pub fn downsample_place_conditions(
+    items: Vec<PlaceCondition>,
+    resolution: Resolution,
+    tz: Tz,
+) -> Vec<PlaceCondition> {
+    if resolution == Resolution::Raw {
+        return items;
+    }
+
+    buckets(items, resolution, tz, |x| x.timestamp)
+        .into_iter()
+        .filter_map(|(start, xs)| {
+            let temperature = average(xs.iter().filter_map(|x| x.temperature));
+            let humidity = average(xs.iter().filter_map(|x| x.humidity.map(|x| x as f64)));
+            let illuminance = average(xs.iter().filter_map(|x| x.illuminance.map(|x| x as f64)));
+            let motion = xs.iter().filter_map(|x| x.motion).last();
+            let battery_percent = xs.iter().filter_map(|x| x.battery_percent).last();
+            let last = xs.into_iter().last()?;
+            Some(PlaceCondition {
+                timestamp: start,
+                temperature: temperature.map(|x| (x * 100.0).round() / 100.0),
+                humidity: humidity.map(|x| x.round() as i64),
+                illuminance: illuminance.map(|x| x.round() as i64),
+                motion,
+                battery_percent,
+                ..last
+            })
+        })
+        .collect()
+}
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Fill {
+    None,
+    Previous,
+    Linear,
+}
+
+pub trait Bucketed: Clone {
+    fn timestamp(&self) -> DateTime<Utc>;
+
+    fn at(&self, timestamp: DateTime<Utc>) -> Self;
+
+    fn lerp(&self, other: &Self, ratio: f64, timestamp: DateTime<Utc>) -> Self;
+}
+
+fn lerp(a: f64, b: f64, ratio: f64) -> f64 {
+    a + (b - a) * ratio
+}
+
+fn lerp_option(a: Option<f64>, b: Option<f64>, ratio: f64) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(lerp(a, b, ratio)),
+        (a, _) => a,
+    }
+}
+
+impl Bucketed for Electricity {
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn at(&self, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            timestamp,
+            ..self.clone()
+        }
+    }
+
+    fn lerp(&self, other: &Self, ratio: f64, timestamp: DateTime<Utc>) -> Self {
+        let ratio_dec = Decimal::from_f64(ratio).unwrap_or_default();
+        Self {
+            timestamp,
+            cumulative_kwh_p: (self.cumulative_kwh_p
+                + (other.cumulative_kwh_p - self.cumulative_kwh_p) * ratio_dec)
+                .round_dp(6),
+            cumulative_kwh_n: (self.cumulative_kwh_n
+                + (other.cumulative_kwh_n - self.cumulative_kwh_n) * ratio_dec)
+                .round_dp(6),
+            current_w: lerp(self.current_w as f64, other.current_w as f64, ratio).round() as u32,
+            ..self.clone()
+        }
+    }
+}
+
+impl Bucketed for PlaceCondition {
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn at(&self, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            timestamp,
+            ..self.clone()
+        }
+    }
+
+    fn lerp(&self, other: &Self, ratio: f64, timestamp: DateTime<Utc>) -> Self {
+        let as_f64 = |x: Option<i64>| x.map(|x| x as f64);
+        Self {
+            timestamp,
+            temperature: lerp_option(self.temperature, other.temperature, ratio)
+                .map(|x| (x * 100.0).round() / 100.0),
+            humidity: lerp_option(as_f64(self.humidity), as_f64(other.humidity), ratio)
+                .map(|x| x.round() as i64),
+            illuminance: lerp_option(as_f64(self.illuminance), as_f64(other.illuminance), ratio)
+                .map(|x| x.round() as i64),
+            ..self.clone()
+        }
+    }
+}
+
+// The range to fill comes from the caller, so a wide one at a fine resolution
+// stops here instead of growing without bound. A year of hourly buckets fits.
+const MAX_FILLED_POINTS: usize = 10000;
+
+pub fn fill_gaps<T>(
+    items: Vec<T>,
+    resolution: Resolution,
+    fill: Fill,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    tz: Tz,
+) -> Vec<T>
+where
+    T: Bucketed,
+{
+    if resolution == Resolution::Raw || fill == Fill::None {
+        return items;
+    }
+    let (first, last) = match (items.first(), items.last()) {
+        (Some(first), Some(last)) => (first.timestamp(), last.timestamp()),
+        _ => return items,
+    };
+    let start = resolution
+        .bucket_start(from.unwrap_or(first), tz)
+        .max(first);
+    let end = to.map(|x| resolution.bucket_start(x, tz)).unwrap_or(last);
+
+    let mut result = Vec::new();
+    let mut known = items.into_iter().peekable();
+    let mut prev: Option<T> = None;
+    let mut timestamp = start;
+
+    while timestamp <= end && result.len() < MAX_FILLED_POINTS {
+        while let Some(x) = known.peek() {
+            if x.timestamp() > timestamp {
+                break;
+            }
+            let x = known.next().unwrap();
+            if x.timestamp() == timestamp {
+                result.push(x.clone());
+            }
+            prev = Some(x);
+        }
+
+        if result.last().map(|x| x.timestamp()) != Some(timestamp) {
+            let filled = match (fill, &prev, known.peek()) {
+                (Fill::Linear, Some(a), Some(b)) => {
+                    let span = (b.timestamp() - a.timestamp()).num_seconds() as f64;
+                    let ratio = (timestamp - a.timestamp()).num_seconds() as f64 / span;
+                    Some(a.lerp(b, ratio, timestamp))
+                }
+                (_, Some(a), _) => Some(a.at(timestamp)),
+                _ => None,
+            };
+            result.extend(filled);
+        }
+
+        timestamp = resolution.next_bucket(timestamp, tz);
+    }
+
+    result
+}
+
+pub struct DailyForecast {
+    pub date: DateTime<Utc>,
+    pub kwh: f64,
+    pub tz: Tz,
+}
+
+#[Object]
+impl DailyForecast {
+    async fn date(&self) -> String {
+        format_time(self.date, self.tz)
+    }
+
+    async fn kwh(&self) -> String {
+        format!("{:.3}", &self.kwh)
+    }
+}
+
+pub struct UsageForecast {
+    pub daily: Vec<DailyForecast>,
+    pub month_to_date_kwh: f64,
+    pub projected_month_kwh: f64,
+}
+
+#[Object]
+impl UsageForecast {
+    async fn daily(&self) -> &[DailyForecast] {
+        &self.daily
+    }
+
+    async fn total_kwh(&self) -> String {
+        format!("{:.3}", self.daily.iter().map(|x| x.kwh).sum::<f64>())
+    }
+
+    async fn month_to_date_kwh(&self) -> String {
+        format!("{:.3}", &self.month_to_date_kwh)
+    }
+
+    async fn projected_month_kwh(&self) -> String {
+        format!("{:.3}", &self.projected_month_kwh)
+    }
+}
+
+fn linear_regression(ys: &[f64]) -> (f64, f64) {
+    let n = ys.len() as f64;
+    if ys.len() < 2 {
+        return (ys.first().cloned().unwrap_or(0.0), 0.0);
+    }
+
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = ys.iter().sum::<f64>() / n;
+    let (mut sxy, mut sxx) = (0.0, 0.0);
+    for (x, y) in ys.iter().enumerate() {
+        let dx = x as f64 - mean_x;
+        sxy += dx * (y - mean_y);
+        sxx += dx * dx;
+    }
+
+    let slope = sxy / sxx;
+    (mean_y - slope * mean_x, slope)
+}
+
+fn month_start(timestamp: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+    let date = local_date(timestamp, tz);
+    local_midnight(NaiveDate::from_ymd(date.year(), date.month(), 1), tz)
+}
+
+fn next_month(timestamp: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+    let date = local_date(timestamp, tz);
+    let (year, month) = match date.month() {
+        12 => (date.year() + 1, 1),
+        month => (date.year(), month + 1),
+    };
+    local_midnight(NaiveDate::from_ymd(year, month, 1), tz)
+}
+
+// The trend comes from four weeks of readings and says little beyond a season.
+pub const MAX_FORECAST_DAYS: u32 = 90;
+
+pub fn usage_forecast(
+    daily: &[EnergyInterval],
+    now: DateTime<Utc>,
+    horizon_days: u32,
+    tz: Tz,
+) -> UsageForecast {
+    let today = Resolution::Day.bucket_start(now, tz);
+    let complete: Vec<&EnergyInterval> = daily.iter().filter(|x| x.start < today).collect();
+    let ys: Vec<f64> = complete
+        .iter()
+        .map(|x| x.imported_kwh.to_f64().unwrap_or(0.0))
+        .collect();
+    let (intercept, slope) = linear_regression(&ys);
+    let predict = |i: usize| (intercept + slope * (ys.len() + i) as f64).max(0.0);
+
+    let month_start = month_start(now, tz);
+    let remaining_days =
+        (local_date(next_month(now, tz), tz) - local_date(today, tz)).num_days() as usize;
+    let month_to_date_kwh: f64 = complete
+        .iter()
+        .filter(|x| x.start >= month_start)
+        .map(|x| x.imported_kwh.to_f64().unwrap_or(0.0))
+        .sum();
+    let projected_month_kwh = month_to_date_kwh + (0..remaining_days).map(predict).sum::<f64>();
+
+    let mut date = today;
+    let mut daily = Vec::new();
+    for i in 0..horizon_days as usize {
+        daily.push(DailyForecast {
+            date,
+            kwh: predict(i),
+            tz,
+        });
+        date = Resolution::Day.next_bucket(date, tz);
+    }
+
+    UsageForecast {
+        daily,
+        month_to_date_kwh,
+        projected_month_kwh,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_of_increasing_readings() {
+        assert_eq!(delta(dec!(100.5), dec!(101.25)), Some(dec!(0.75)));
+    }
+
+    #[test]
+    fn delta_of_equal_readings() {
+        assert_eq!(delta(dec!(12345.6), dec!(12345.6)), Some(dec!(0)));
+    }
+
+    #[test]
+    fn delta_across_rollover() {
+        assert_eq!(delta(dec!(99990), dec!(5)), Some(dec!(15)));
+        assert_eq!(delta(dec!(99999.9), dec!(0.1)), Some(dec!(0.2)));
+    }
+
+    #[test]
+    fn delta_across_reset() {
+        assert_eq!(delta(dec!(12345), dec!(10)), None);
+        assert_eq!(delta(dec!(5), dec!(0)), None);
+    }
+
+    #[test]
+    fn delta_of_wide_readings() {
+        let prev: Decimal = "9999999999999999990".parse().unwrap();
+        assert_eq!(delta(prev, dec!(5)), Some(dec!(15)));
+        let prev: Decimal = "123456789012345678901".parse().unwrap();
+        assert_eq!(delta(prev, dec!(1)), None);
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::automation;
+use crate::delivery;
+use crate::dynamodb::{attr_number, Client, Condition};
+use crate::error::{ApiError, Code};
+use crate::models::{live, Alert, AlertRule, Device, EventKind, HomeMode, Metric};
+use crate::notify;
+
+const ATTEMPTS: usize = 3;
+
+#[derive(Debug, Deserialize)]
+struct Reading {
+    sk: String,
+    current_w: Option<u32>,
+    temperature: Option<f64>,
+    humidity: Option<i64>,
+    illuminance: Option<i64>,
+    motion: Option<i64>,
+    battery_percent: Option<f64>,
+}
+
+impl Reading {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.sk.strip_prefix("TS#").and_then(|x| x.parse().ok())
+    }
+
+    fn value(&self, metric: Metric) -> Option<f64> {
+        match metric {
+            Metric::Temperature => self.temperature,
+            Metric::Humidity => self.humidity.map(|x| x as f64),
+            Metric::Illuminance => self.illuminance.map(|x| x as f64),
+            Metric::Motion => self.motion.map(|x| x as f64),
+            Metric::CurrentW => self.current_w.map(|x| x as f64),
+            Metric::BatteryPercent => self.battery_percent,
+            Metric::Staleness => None,
+        }
+    }
+}
+
+async fn check(
+    dynamodb: &Client,
+    rule: &AlertRule,
+    device: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<f64>> {
+    let latest: Option<Reading> = dynamodb
+        .get_last_item(device, Condition::BeginsWith("TS#".to_owned()))
+        .await?;
+
+    if rule.metric == Metric::Staleness {
+        let value = match latest.as_ref().and_then(|x| x.timestamp()) {
+            Some(timestamp) => (now - timestamp).num_seconds() as f64,
+            None => f64::INFINITY,
+        };
+        return Ok(Some(value).filter(|x| rule.comparison.test(*x, rule.threshold)));
+    }
+
+    let readings = if rule.duration_seconds > 0 {
+        let from = now - Duration::seconds(rule.duration_seconds);
+        let sk = Condition::Between(format!("TS#{:?}", from), format!("TS#{:?}", now));
+        dynamodb.get_range(device, Some(sk)).await?
+    } else {
+        latest.into_iter().collect::<Vec<Reading>>()
+    };
+
+    let values: Vec<f64> = readings
+        .iter()
+        .filter_map(|x| x.value(rule.metric))
+        .collect();
+
+    if values.is_empty()
+        || !values
+            .iter()
+            .all(|x| rule.comparison.test(*x, rule.threshold))
+    {
+        return Ok(None);
+    }
+
+    Ok(values.last().cloned())
+}
+
+fn targets(rule: &AlertRule, devices: &[Device]) -> Vec<String> {
+    match (&rule.device, &rule.place) {
+        (Some(device), _) => vec![device.clone()],
+        (None, Some(place)) => devices
+            .iter()
+            .filter(|x| &x.place == place)
+            .map(|x| x.id.clone())
+            .collect(),
+        (None, None) => devices.iter().map(|x| x.id.clone()).collect(),
+    }
+}
+
+fn message(rule: &AlertRule, value: f64) -> String {
+    format!(
+        "{}: {:?} {:?} {} (value: {})",
+        rule.name, rule.metric, rule.comparison, rule.threshold, value
+    )
+}
+
+// Only firing is changed, on the latest version, so that edits made during the
+// sweep are kept and a rule deleted meanwhile is not written back.
+async fn set_firing(dynamodb: &Client, mut rule: AlertRule, firing: Vec<String>) -> Result<()> {
+    let condition =
+        "attribute_exists(sk) AND (attribute_not_exists(version) OR version = :version)";
+    let mut attempt = 1;
+    loop {
+        let mut params = HashMap::new();
+        params.insert(":version".to_owned(), attr_number(rule.version));
+        rule.firing = firing.clone();
+        rule.version += 1;
+        if dynamodb.put_item_if(&rule, condition, params).await? {
+            return Ok(());
+        }
+        if attempt == ATTEMPTS {
+            let message = format!("alert rule {} changed during the sweep", rule.id);
+            return Err(ApiError::new(Code::Conflict, message).into());
+        }
+        attempt += 1;
+        rule = match dynamodb.find_item("ALERT_RULE", &rule.id).await? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+    }
+}
+
+pub async fn sweep(dynamodb: &Client, now: DateTime<Utc>) -> Result<Vec<Alert>> {
+    let rules: Vec<AlertRule> = dynamodb.get_range("ALERT_RULE", None).await?;
+    let devices: Vec<Device> = live(dynamodb.get_range("DEVICE", None).await?);
+    let mode = dynamodb
+        .find_item::<HomeMode>("HOME_MODE", "current")
+        .await?
+        .map_or_else(Default::default, |x| x.at(now));
+    let mut alerts = Vec::new();
+
+    for rule in rules.into_iter().filter(|x| x.enabled) {
+        let mut firing = Vec::new();
+        // Rules limited to other modes are not evaluated and stop firing.
+        let targets = match &rule.modes {
+            Some(modes) if !modes.contains(&mode) => Vec::new(),
+            _ => targets(&rule, &devices),
+        };
+
+        for device in targets {
+            let value = match check(dynamodb, &rule, &device, now).await? {
+                Some(value) => value,
+                None => continue,
+            };
+
+            if !rule.firing.contains(&device) {
+                let alert = Alert::new(&rule, now, device.clone(), value, message(&rule, value));
+                dynamodb.put_item(&alert).await?;
+                if let Err(e) = notify::notify(&rule.channel, &alert).await {
+                    println!("{:?}", e);
+                }
+                if let Err(e) = automation::on_alert(dynamodb, &alert).await {
+                    println!("{:?}", e);
+                }
+                let data = json!({
+                    "id": alert.id,
+                    "rule": alert.rule_id,
+                    "device": alert.device,
+                    "metric": alert.metric,
+                    "value": alert.value,
+                    "message": alert.message,
+                });
+                if let Err(e) = delivery::publish(dynamodb, EventKind::Alert, data).await {
+                    println!("{:?}", e);
+                }
+                alerts.push(alert);
+            }
+            firing.push(device);
+        }
+
+        if firing != rule.firing {
+            set_firing(dynamodb, rule, firing).await?;
+        }
+    }
+
+    Ok(alerts)
+}
@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::dynamodb::{Client, Condition};
+use crate::models::{live, Device, Place};
+
+// Power is not an Alexa sensor type, so it is reported through a read-only
+// RangeController instance.
+const POWER_INSTANCE: &str = "Power.Watts";
+
+// Meters and environment sensors share the TS# prefix, so both kinds of
+// fields are optional.
+#[derive(Debug, Deserialize)]
+struct Reading {
+    sk: String,
+    current_w: Option<u32>,
+    temperature: Option<f64>,
+}
+
+impl Reading {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.sk.strip_prefix("TS#").and_then(|x| x.parse().ok())
+    }
+}
+
+async fn latest(dynamodb: &Client, device: &str) -> Result<Option<Reading>> {
+    dynamodb
+        .get_last_item(device, Condition::BeginsWith("TS#".to_owned()))
+        .await
+}
+
+fn header(namespace: &str, name: &str, directive: &Value) -> Value {
+    let mut header = json!({
+        "namespace": namespace,
+        "name": name,
+        "payloadVersion": "3",
+        "messageId": uuid::Uuid::new_v4().to_string(),
+    });
+    if let Some(token) = directive["header"].get("correlationToken") {
+        header["correlationToken"] = token.clone();
+    }
+    header
+}
+
+fn error(directive: &Value, kind: &str, message: &str) -> Value {
+    json!({
+        "event": {
+            "header": header("Alexa", "ErrorResponse", directive),
+            "endpoint": directive["endpoint"],
+            "payload": { "type": kind, "message": message },
+        }
+    })
+}
+
+fn capability(interface: &str) -> Value {
+    json!({ "type": "AlexaInterface", "interface": interface, "version": "3" })
+}
+
+fn temperature_sensor() -> Value {
+    let mut x = capability("Alexa.TemperatureSensor");
+    x["properties"] = json!({
+        "supported": [{ "name": "temperature" }],
+        "proactivelyReported": false,
+        "retrievable": true,
+    });
+    x
+}
+
+fn power_meter() -> Value {
+    let mut x = capability("Alexa.RangeController");
+    x["instance"] = json!(POWER_INSTANCE);
+    x["properties"] = json!({
+        "supported": [{ "name": "rangeValue" }],
+        "proactivelyReported": false,
+        "retrievable": true,
+        "nonControllable": true,
+    });
+    x["capabilityResources"] = json!({
+        "friendlyNames": [
+            { "@type": "text", "value": { "text": "power", "locale": "en-US" } },
+            { "@type": "text", "value": { "text": "電力", "locale": "ja-JP" } },
+        ]
+    });
+    x["configuration"] = json!({
+        "supportedRange": { "minimumValue": 0, "maximumValue": 100000, "precision": 1 },
+        "unitOfMeasure": "Alexa.Unit.Power.Watt",
+    });
+    x
+}
+
+// Alexa requires friendly names to be unique. Where a place has several
+// endpoints, or two places share a name, the kind and then the device id are
+// added until the name is.
+fn unique_names(candidates: &[[String; 3]]) -> Vec<String> {
+    let mut taken = HashSet::new();
+    candidates
+        .iter()
+        .map(|x| {
+            let shared = |level: usize| candidates.iter().filter(|y| y[level] == x[level]).count();
+            let name = (0..2)
+                .find(|&level| shared(level) == 1 && !taken.contains(&x[level]))
+                .map_or(&x[2], |level| &x[level]);
+            taken.insert(name.clone());
+            name.clone()
+        })
+        .collect()
+}
+
+// Devices are discovered with the capabilities their latest reading has
+// values for, and named after their place so that "the living room
+// temperature" resolves to the sensor there.
+async fn discover(dynamodb: &Client, directive: &Value) -> Result<Value> {
+    let devices: Vec<Device> = live(dynamodb.get_range("DEVICE", None).await?);
+    let places: HashMap<String, String> = dynamodb
+        .get_range::<Place>("PLACE", None)
+        .await?
+        .into_iter()
+        .map(|x| (x.id, x.name))
+        .collect();
+    let mut endpoints = Vec::new();
+    let mut names = Vec::new();
+
+    for device in devices {
+        let reading = match latest(dynamodb, &device.id).await? {
+            Some(x) => x,
+            None => continue,
+        };
+        let mut capabilities = vec![capability("Alexa")];
+        if reading.temperature.is_some() {
+            capabilities.push(temperature_sensor());
+        }
+        if reading.current_w.is_some() {
+            capabilities.push(power_meter());
+        }
+        if capabilities.len() == 1 {
+            continue;
+        }
+
+        let (category, kind) = match (reading.temperature, reading.current_w) {
+            (Some(_), None) => ("TEMPERATURE_SENSOR", "temperature"),
+            (None, Some(_)) => ("ENERGY_METER", "power"),
+            _ => ("OTHER", "sensor"),
+        };
+        let name = places
+            .get(&device.place)
+            .filter(|x| !x.is_empty())
+            .unwrap_or(&device.id);
+        names.push([
+            name.clone(),
+            format!("{} {}", name, kind),
+            format!("{} {} {}", name, kind, device.id),
+        ]);
+        let model = device.model.as_deref().unwrap_or("sensor");
+        endpoints.push(json!({
+            "endpointId": device.id,
+            "manufacturerName": device.manufacturer.as_deref().unwrap_or("homeapi"),
+            "description": format!("{} ({})", model, device.id),
+            "displayCategories": [category],
+            "capabilities": capabilities,
+        }));
+    }
+    for (endpoint, name) in endpoints.iter_mut().zip(unique_names(&names)) {
+        endpoint["friendlyName"] = json!(name);
+    }
+
+    Ok(json!({
+        "event": {
+            "header": header("Alexa.Discovery", "Discover.Response", directive),
+            "payload": { "endpoints": endpoints },
+        }
+    }))
+}
+
+async fn report_state(dynamodb: &Client, directive: &Value) -> Result<Value> {
+    let id = directive["endpoint"]["endpointId"].as_str().unwrap_or("");
+    let device: Option<Device> = dynamodb.find_item("DEVICE", id).await?;
+    if device.filter(|x| x.deleted_at.is_none()).is_none() {
+        return Ok(error(directive, "NO_SUCH_ENDPOINT", "unknown device"));
+    }
+    let reading = match latest(dynamodb, id).await? {
+        Some(x) => x,
+        None => return Ok(error(directive, "ENDPOINT_UNREACHABLE", "no readings")),
+    };
+
+    let sampled = reading
+        .timestamp()
+        .map_or_else(|| format!("{:?}", Utc::now()), |x| format!("{:?}", x));
+    let uncertainty = reading
+        .timestamp()
+        .map_or(0, |x| (Utc::now() - x).num_milliseconds().max(0));
+    let mut properties = Vec::new();
+    if let Some(x) = reading.temperature {
+        properties.push(json!({
+            "namespace": "Alexa.TemperatureSensor",
+            "name": "temperature",
+            "value": { "value": x, "scale": "CELSIUS" },
+            "timeOfSample": sampled,
+            "uncertaintyInMilliseconds": uncertainty,
+        }));
+    }
+    if let Some(x) = reading.current_w {
+        properties.push(json!({
+            "namespace": "Alexa.RangeController",
+            "instance": POWER_INSTANCE,
+            "name": "rangeValue",
+            "value": x,
+            "timeOfSample": sampled,
+            "uncertaintyInMilliseconds": uncertainty,
+        }));
+    }
+
+    Ok(json!({
+        "event": {
+            "header": header("Alexa", "StateReport", directive),
+            "endpoint": directive["endpoint"],
+            "payload": {},
+        },
+        "context": { "properties": properties },
+    }))
+}
+
+// Handles a Smart Home directive and returns the response event. The skill
+// is read-only, so control directives are rejected. The Lambda trigger is
+// expected to be restricted to the skill's id; the linked account token is
+// not checked since homeapi has no users.
+pub async fn handle(dynamodb: &Client, event: &Value) -> Result<Value> {
+    let directive = &event["directive"];
+    let namespace = directive["header"]["namespace"].as_str().unwrap_or("");
+    let name = directive["header"]["name"].as_str().unwrap_or("");
+
+    match (namespace, name) {
+        ("Alexa.Discovery", "Discover") => discover(dynamodb, directive).await,
+        ("Alexa", "ReportState") => report_state(dynamodb, directive).await,
+        ("Alexa.Authorization", "AcceptGrant") => Ok(json!({
+            "event": {
+                "header": header("Alexa.Authorization", "AcceptGrant.Response", directive),
+                "payload": {},
+            }
+        })),
+        ("Alexa.RangeController", _) => Ok(error(
+            directive,
+            "NOT_SUPPORTED_IN_CURRENT_MODE",
+            "power is read-only",
+        )),
+        _ => Ok(error(
+            directive,
+            "INVALID_DIRECTIVE",
+            &format!("unsupported directive: {}.{}", namespace, name),
+        )),
+    }
+}
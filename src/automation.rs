@@ -0,0 +1,199 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+
+use crate::delivery;
+use crate::dynamodb::Client;
+use crate::error::is_conflict;
+use crate::models::{redact_url, Alert, Automation, AutomationAction, EventKind};
+
+const ATTEMPTS: usize = 3;
+
+// Hosts that actions and webhook targets may call although they are not
+// public, such as devices on the home network, e.g. "192.168.1.10,hass.local".
+static ALLOWED_HOSTS: Lazy<Vec<String>> = Lazy::new(|| {
+    std::env::var("AUTOMATION_ALLOWED_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|x| x.trim().to_lowercase())
+        .filter(|x| !x.is_empty())
+        .collect()
+});
+
+// Redirects are not followed, as they could lead past check_url.
+static REQWEST: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::ClientBuilder::new()
+        .timeout(Duration::from_secs(5))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+});
+
+fn internal(host: &str) -> bool {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(x)) => {
+            let [a, b, ..] = x.octets();
+            x.is_loopback()
+                || x.is_private()
+                || x.is_link_local()
+                || x.is_broadcast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+        }
+        Ok(IpAddr::V6(x)) => {
+            let first = x.segments()[0];
+            x.is_loopback()
+                || x.is_unspecified()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+                || x.to_ipv4().map_or(false, |x| internal(&x.to_string()))
+        }
+        Err(_) => {
+            host.is_empty()
+                || host == "localhost"
+                || host.ends_with(".localhost")
+                || host.ends_with(".local")
+                || host.ends_with(".internal")
+        }
+    }
+}
+
+// Actions and webhook targets call public HTTPS URLs or hosts in
+// AUTOMATION_ALLOWED_HOSTS, so that they can't be used to reach the server or
+// the network it runs in.
+// Names are not resolved here; a public name for a private address has to be
+// stopped by the network.
+pub fn check_url(url: &str) -> Result<(), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("invalid URL: {}", e))?;
+    let host = url.host_str().unwrap_or_default().to_lowercase();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let allowed = ALLOWED_HOSTS.iter().any(|x| x == host);
+
+    match url.scheme() {
+        "https" => (),
+        "http" if allowed => (),
+        x => return Err(format!("URL scheme not allowed: {}", x)),
+    }
+    if !allowed && internal(host) {
+        return Err(format!("URL host not allowed: {}", host));
+    }
+    Ok(())
+}
+
+async fn call(action: &AutomationAction, trigger: &Value) -> Result<()> {
+    // Also checked here for actions stored before the check existed.
+    check_url(&action.url).map_err(|e| anyhow!(e))?;
+    let request = match action.method.as_str() {
+        "GET" => REQWEST.get(&action.url),
+        "PUT" => REQWEST.put(&action.url),
+        _ => REQWEST.post(&action.url),
+    };
+    let request = match &action.body {
+        Some(body) => request.body(body.clone()),
+        None if action.method == "GET" => request,
+        None => request.json(trigger),
+    };
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+// Runs every action in order. A failed action is logged and does not stop
+// the ones after it.
+pub async fn run(
+    dynamodb: &Client,
+    automation: &mut Automation,
+    trigger: Value,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let trigger = json!({ "automation": automation.id, "trigger": trigger });
+    for action in automation.actions.iter() {
+        if let Err(e) = call(action, &trigger).await {
+            println!(
+                "automation {}: {} {}: {:?}",
+                automation.id,
+                action.method,
+                redact_url(&action.url),
+                e
+            );
+        }
+    }
+
+    // Only last_run_at is set, on the latest version, so that edits made
+    // while the actions ran are kept.
+    for attempt in 1..=ATTEMPTS {
+        let version = automation.version;
+        automation.last_run_at = Some(now);
+        automation.version += 1;
+        match dynamodb.put_item_versioned(automation, version).await {
+            Ok(()) => break,
+            Err(e) if is_conflict(&e) && attempt < ATTEMPTS => {
+                *automation = dynamodb.get_item("AUTOMATION", &automation.id).await?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let data =
+        json!({ "id": automation.id, "name": automation.name, "trigger": trigger["trigger"] });
+    if let Err(e) = delivery::publish(dynamodb, EventKind::AutomationRun, data).await {
+        println!("{:?}", e);
+    }
+    Ok(())
+}
+
+// Runs the automations triggered by an alert rule that started firing.
+pub async fn on_alert(dynamodb: &Client, alert: &Alert) -> Result<usize> {
+    let automations: Vec<Automation> = dynamodb.get_range("AUTOMATION", None).await?;
+    let mut count = 0;
+
+    for mut automation in automations.into_iter().filter(|x| x.enabled) {
+        if automation.rule.as_ref() != Some(&alert.rule_id) {
+            continue;
+        }
+        let trigger = json!({
+            "rule": alert.rule_id,
+            "device": alert.device,
+            "value": alert.value,
+            "message": alert.message,
+        });
+        // A failure is logged so that the automations after it still run.
+        match run(dynamodb, &mut automation, trigger, alert.timestamp).await {
+            Ok(()) => count += 1,
+            Err(e) => println!("automation {}: {:?}", automation.id, e),
+        }
+    }
+
+    Ok(count)
+}
+
+// Runs scheduled automations whose time has come since their last run. Meant
+// to be called from the periodic sweep.
+pub async fn sweep(dynamodb: &Client, now: DateTime<Utc>) -> Result<usize> {
+    let automations: Vec<Automation> = dynamodb.get_range("AUTOMATION", None).await?;
+    let mut count = 0;
+
+    for mut automation in automations.into_iter().filter(|x| x.enabled) {
+        let at = match automation.scheduled_at(now) {
+            Some(x) => x,
+            None => continue,
+        };
+        if automation
+            .last_run_at
+            .max(automation.armed_at)
+            .map_or(false, |x| x >= at)
+        {
+            continue;
+        }
+        let trigger = json!({ "schedule": automation.schedule, "at": format!("{:?}", at) });
+        match run(dynamodb, &mut automation, trigger, now).await {
+            Ok(()) => count += 1,
+            Err(e) => println!("automation {}: {:?}", automation.id, e),
+        }
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,33 @@
+use anyhow::{anyhow, Result};
+use chrono::{TimeZone, Utc};
+use rusoto_dynamodb::CreateBackupInput;
+
+use crate::dynamodb::Client;
+use crate::models::Backup;
+use crate::storage::Storage;
+
+pub async fn create_backup(dynamodb: &Client, name: &str, table: Option<&str>) -> Result<Backup> {
+    let table = table.unwrap_or(&dynamodb.table).to_owned();
+    let input = CreateBackupInput {
+        backup_name: name.to_owned(),
+        table_name: table.clone(),
+    };
+    let details = dynamodb
+        .dynamodb
+        .create_backup(input)
+        .await?
+        .backup_details
+        .ok_or_else(|| anyhow!("no backup details"))?;
+
+    let created_at = details.backup_creation_date_time;
+    let backup = Backup::new(
+        details.backup_name,
+        table,
+        details.backup_arn,
+        details.backup_status,
+        Utc.timestamp_millis((created_at * 1000.0) as i64),
+    );
+    dynamodb.put_item(&backup).await?;
+
+    Ok(backup)
+}
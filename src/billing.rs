@@ -0,0 +1,329 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use once_cell::sync::Lazy;
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+
+use crate::aggregation;
+use crate::dynamodb::{Client, Condition};
+use crate::error::ApiError;
+use crate::models::{
+    live, BandCost, BillingAnchor, BillingStatement, Device, Place, PlaceCost, Tariff,
+    TariffScenario,
+};
+
+// Devices without their own anchor follow BILLING_DAY and BILLING_UTC_OFFSET
+// (whole hours), which the rollup job used before anchors were per device.
+static DEFAULT_ANCHOR: Lazy<BillingAnchor> = Lazy::new(|| {
+    let day = std::env::var("BILLING_DAY")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(1)
+        .max(1)
+        .min(28);
+    let offset: i32 = std::env::var("BILLING_UTC_OFFSET")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(0);
+    // Etc zones have the sign reversed: UTC+9 is Etc/GMT-9.
+    let timezone = match offset {
+        0 => "UTC".to_owned(),
+        x => format!("Etc/GMT{:+}", -x),
+    };
+    BillingAnchor {
+        day,
+        hour: 0,
+        timezone,
+    }
+});
+
+// Meters and environment sensors share the TS# prefix; a device is a meter
+// if its latest reading has a cumulative value.
+#[derive(Debug, Deserialize)]
+struct Reading {
+    cumulative_kwh_p: Option<Decimal>,
+}
+
+pub fn anchor(device: &Device) -> BillingAnchor {
+    device
+        .billing_anchor
+        .clone()
+        .unwrap_or_else(|| DEFAULT_ANCHOR.clone())
+}
+
+fn next_month(year: i32, month: u32) -> (i32, u32) {
+    match month {
+        12 => (year + 1, 1),
+        x => (year, x + 1),
+    }
+}
+
+fn prev_month(year: i32, month: u32) -> (i32, u32) {
+    match month {
+        1 => (year - 1, 12),
+        x => (year, x - 1),
+    }
+}
+
+// The start of the period in the given month. A start that falls in a DST
+// gap moves to the first local hour that exists.
+fn period_start(anchor: &BillingAnchor, tz: Tz, (year, month): (i32, u32)) -> DateTime<Utc> {
+    let (next_year, next_month) = next_month(year, month);
+    let last_day = NaiveDate::from_ymd(next_year, next_month, 1).pred().day();
+    let date = NaiveDate::from_ymd(year, month, anchor.day.min(last_day));
+
+    (anchor.hour..24)
+        .find_map(|h| tz.from_local_datetime(&date.and_hms(h, 0, 0)).earliest())
+        .map(|x| x.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&date.and_hms(anchor.hour, 0, 0)))
+}
+
+// The billing period containing `at`, as start (inclusive) and end.
+pub fn billing_period(anchor: &BillingAnchor, at: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let tz = anchor.tz();
+    let local = at.with_timezone(&tz);
+    let month = (local.year(), local.month());
+    let start = period_start(anchor, tz, month);
+
+    if at >= start {
+        (
+            start,
+            period_start(anchor, tz, next_month(month.0, month.1)),
+        )
+    } else {
+        (
+            period_start(anchor, tz, prev_month(month.0, month.1)),
+            start,
+        )
+    }
+}
+
+pub fn band_costs(tariff: &Tariff, imported_kwh: Decimal) -> Vec<BandCost> {
+    let mut bands = Vec::new();
+    let mut billed = Decimal::zero();
+
+    for band in tariff.bands.iter() {
+        if billed >= imported_kwh {
+            break;
+        }
+
+        let upper = band.up_to_kwh.map_or(imported_kwh, |x| x.min(imported_kwh));
+        let kwh = (upper - billed).max(Decimal::zero());
+        bands.push(BandCost {
+            up_to_kwh: band.up_to_kwh,
+            kwh,
+            price_per_kwh: band.price_per_kwh,
+            cost: (kwh * band.price_per_kwh).round_dp(2),
+        });
+        billed += kwh;
+    }
+
+    bands
+}
+
+pub fn export_credit(tariff: &Tariff, exported_kwh: Decimal) -> Decimal {
+    tariff
+        .export_price_per_kwh
+        .map_or(Decimal::zero(), |x| (exported_kwh * x).round_dp(2))
+}
+
+pub fn cost(tariff: &Tariff, imported_kwh: Decimal, exported_kwh: Decimal) -> Decimal {
+    let bands: Decimal = band_costs(tariff, imported_kwh)
+        .iter()
+        .map(|x| x.cost)
+        .sum();
+    tariff.base_charge + bands - export_credit(tariff, exported_kwh)
+}
+
+pub async fn generate_statement(
+    dynamodb: &Client,
+    device: &str,
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+) -> Result<BillingStatement> {
+    let device: Device = dynamodb.get_item("DEVICE", device).await?;
+    let tariffs: Vec<Tariff> = dynamodb.get_range("TARIFF", None).await?;
+    let readings =
+        aggregation::electricity_readings(dynamodb, &device.id, period_start, period_end).await?;
+    let summary = aggregation::energy_summary(&readings, period_start, period_end);
+    let imported_kwh = summary.imported_kwh.round_dp(3);
+    let exported_kwh = summary.exported_kwh.round_dp(3);
+
+    let current = device
+        .tariff
+        .as_ref()
+        .and_then(|id| tariffs.iter().find(|x| &x.id == id));
+    let (base_charge, bands, credit) = match current {
+        Some(tariff) => (
+            tariff.base_charge,
+            band_costs(tariff, imported_kwh),
+            export_credit(tariff, exported_kwh),
+        ),
+        None => (Decimal::zero(), Vec::new(), Decimal::zero()),
+    };
+    let total = base_charge + bands.iter().map(|x| x.cost).sum::<Decimal>() - credit;
+
+    let scenarios = tariffs
+        .iter()
+        .filter(|x| Some(&x.id) != device.tariff.as_ref())
+        .map(|x| TariffScenario {
+            tariff: x.id.clone(),
+            name: x.name.clone(),
+            cost: cost(x, imported_kwh, exported_kwh),
+        })
+        .collect();
+
+    let statement = BillingStatement {
+        id: device.id,
+        period_start,
+        period_end,
+        place: device.place,
+        tariff: device.tariff,
+        imported_kwh,
+        exported_kwh,
+        base_charge,
+        bands,
+        export_credit: credit,
+        cost: total,
+        scenarios,
+    };
+    dynamodb.put_item(&statement).await?;
+
+    Ok(statement)
+}
+
+// cost_by_place reads every billing month it touches in full.
+const MAX_COST_RANGE_DAYS: i64 = 366;
+
+async fn energy(
+    dynamodb: &Client,
+    device: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<(Decimal, Decimal)> {
+    let readings = aggregation::electricity_readings(dynamodb, device, from, to).await?;
+    let summary = aggregation::energy_summary(&readings, from, to);
+    Ok((
+        summary.imported_kwh.round_dp(3),
+        summary.exported_kwh.round_dp(3),
+    ))
+}
+
+// A contract meter's bill for `from`..`to`. Each billing month is priced in
+// full, so that tiers and the base charge apply as on the real bill, and
+// counted with the part of its imported energy inside the range, or the part
+// of its time when nothing was imported.
+async fn contract_cost(
+    dynamodb: &Client,
+    device: &Device,
+    tariff: &Tariff,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Decimal> {
+    let anchor = anchor(device);
+    let mut total = Decimal::zero();
+    let mut at = from;
+
+    while at < to {
+        let (start, end) = billing_period(&anchor, at);
+        let until = end.min(to);
+        let (imported, exported) = energy(dynamodb, &device.id, start, end).await?;
+        let month = cost(tariff, imported, exported);
+        let part = if at == start && until == end {
+            Decimal::one()
+        } else if imported > Decimal::zero() {
+            energy(dynamodb, &device.id, at, until).await?.0 / imported
+        } else {
+            Decimal::from((until - at).num_seconds()) / Decimal::from((end - start).num_seconds())
+        };
+        total += month * part;
+        at = end;
+    }
+
+    Ok(total.round_dp(2))
+}
+
+// Splits the bill of the period by place, e.g. to divide a shared bill by
+// room. Meters with a tariff are the contract meters that are billed; the bill
+// is shared out by each place's imported energy on the other meters, or on the
+// contract meters themselves when there are no others.
+pub async fn cost_by_place(
+    dynamodb: &Client,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<PlaceCost>> {
+    if to <= from || to - from > chrono::Duration::days(MAX_COST_RANGE_DAYS) {
+        let message = format!("must be after from and within {} days", MAX_COST_RANGE_DAYS);
+        return Err(ApiError::invalid("to", message).into());
+    }
+
+    let devices: Vec<Device> = live(dynamodb.get_range("DEVICE", None).await?);
+    let tariffs: Vec<Tariff> = dynamodb.get_range("TARIFF", None).await?;
+    let names: HashMap<String, String> = dynamodb
+        .get_range::<Place>("PLACE", None)
+        .await?
+        .into_iter()
+        .map(|x| (x.id, x.name))
+        .collect();
+
+    let mut meters = Vec::new();
+    for device in devices {
+        let latest: Option<Reading> = dynamodb
+            .get_last_item(&device.id, Condition::BeginsWith("TS#".to_owned()))
+            .await?;
+        if latest.and_then(|x| x.cumulative_kwh_p).is_some() {
+            meters.push(device);
+        }
+    }
+    let tariff = |device: &Device| {
+        device
+            .tariff
+            .as_ref()
+            .and_then(|id| tariffs.iter().find(|x| &x.id == id))
+    };
+
+    let mut bill = Decimal::zero();
+    for device in meters.iter() {
+        if let Some(tariff) = tariff(device) {
+            bill += contract_cost(dynamodb, device, tariff, from, to).await?;
+        }
+    }
+
+    let submetered = meters.iter().any(|x| tariff(x).is_none());
+    let mut places: BTreeMap<String, PlaceCost> = BTreeMap::new();
+    for device in meters {
+        if submetered && tariff(&device).is_some() {
+            continue;
+        }
+        let (imported_kwh, exported_kwh) = energy(dynamodb, &device.id, from, to).await?;
+        let x = places
+            .entry(device.place.clone())
+            .or_insert_with(|| PlaceCost {
+                place: device.place.clone(),
+                name: names.get(&device.place).cloned(),
+                devices: Vec::new(),
+                imported_kwh: Decimal::zero(),
+                exported_kwh: Decimal::zero(),
+                cost: Decimal::zero(),
+                share_percent: None,
+            });
+        x.devices.push(device.id);
+        x.imported_kwh += imported_kwh;
+        x.exported_kwh += exported_kwh;
+    }
+
+    let mut places: Vec<PlaceCost> = places.into_values().collect();
+    let total: Decimal = places.iter().map(|x| x.imported_kwh).sum();
+    if total > Decimal::zero() {
+        for x in places.iter_mut() {
+            let share = x.imported_kwh / total;
+            x.cost = (bill * share).round_dp(2);
+            x.share_percent = Some((share * Decimal::from(100)).round_dp(1));
+        }
+    }
+
+    Ok(places)
+}
@@ -0,0 +1,39 @@
+use anyhow::Result;
+use chrono::Utc;
+use lambda_runtime::{handler_fn, Context, Error};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use homeapi::dynamodb::Client;
+use homeapi::{alerts, automation, delivery, health, occupancy};
+
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+
+async fn evaluate() -> Result<()> {
+    let now = Utc::now();
+    let alerts = alerts::sweep(&DB, now).await?;
+    println!("{} alert(s) fired", alerts.len());
+    let devices = health::refresh(&DB, now).await?;
+    println!("{} device(s) need attention", devices);
+    let intervals = occupancy::analyze(&DB, now).await?;
+    println!("{} occupancy interval(s) updated", intervals);
+    let automations = automation::sweep(&DB, now).await?;
+    println!("{} automation(s) ran", automations);
+    let deliveries = delivery::retry(&DB, now).await?;
+    println!("{} webhook delivery attempt(s) retried", deliveries);
+    Ok(())
+}
+
+async fn handler(_: Value, _: Context) -> Result<(), Error> {
+    evaluate().await.map_err(|e| {
+        println!("{:?}", e);
+        Error::from("error")
+    })?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    lambda_runtime::run(handler_fn(handler)).await?;
+    Ok(())
+}
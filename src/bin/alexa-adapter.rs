@@ -0,0 +1,22 @@
+use lambda_runtime::{handler_fn, Context, Error};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use homeapi::alexa;
+use homeapi::dynamodb::Client;
+
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+
+// Invoked directly by an Alexa Smart Home skill.
+async fn handler(event: Value, _: Context) -> Result<Value, Error> {
+    alexa::handle(&DB, &event).await.map_err(|e| {
+        println!("{:?}", e);
+        Error::from("error")
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    lambda_runtime::run(handler_fn(handler)).await?;
+    Ok(())
+}
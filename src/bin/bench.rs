@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_graphql::{Request, Variables};
+use futures::stream::{self, StreamExt};
+use rusoto_core::{Region, RusotoError};
+use rusoto_dynamodb::{
+    AttributeDefinition, CreateTableError, CreateTableInput, DynamoDb, DynamoDbClient,
+    GlobalSecondaryIndex, KeySchemaElement, Projection,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use structopt::StructOpt;
+
+use homeapi::dynamodb::Client;
+use homeapi::graphql::schema;
+
+/// Replays a mix of GraphQL requests against the schema and reports latency
+/// percentiles. Meant to be run against DynamoDB Local; any non-empty AWS
+/// credentials are accepted there.
+#[derive(Debug, StructOpt)]
+struct Args {
+    /// DynamoDB endpoint
+    #[structopt(long, default_value = "http://localhost:8000")]
+    endpoint: String,
+
+    /// Table to run against
+    #[structopt(long, default_value = "homeapi-bench")]
+    table: String,
+
+    /// Create the table first if it does not exist
+    #[structopt(long)]
+    create_table: bool,
+
+    /// Number of requests in flight at once
+    #[structopt(long, default_value = "8")]
+    concurrency: usize,
+
+    /// Total number of requests to send
+    #[structopt(long, default_value = "1000")]
+    requests: usize,
+
+    /// JSON lines of {"name", "query", "variables", "weight"}
+    #[structopt(parse(from_os_str))]
+    mix: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    name: Option<String>,
+    query: String,
+    #[serde(default)]
+    variables: Value,
+    #[serde(default = "default_weight")]
+    weight: usize,
+}
+
+fn default_weight() -> usize {
+    1
+}
+
+async fn create_table(dynamodb: &DynamoDbClient, table: &str) -> Result<()> {
+    let key = |name: &str, key_type: &str| KeySchemaElement {
+        attribute_name: name.to_owned(),
+        key_type: key_type.to_owned(),
+    };
+    let attribute = |name: &str| AttributeDefinition {
+        attribute_name: name.to_owned(),
+        attribute_type: "S".to_owned(),
+    };
+    // The same indexes as production, so that writes pay for them here too.
+    let index = |name: &str| GlobalSecondaryIndex {
+        index_name: name.to_owned(),
+        key_schema: vec![
+            key(&format!("{}pk", name), "HASH"),
+            key(&format!("{}sk", name), "RANGE"),
+        ],
+        projection: Projection {
+            projection_type: Some("ALL".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let input = CreateTableInput {
+        table_name: table.to_owned(),
+        key_schema: vec![key("pk", "HASH"), key("sk", "RANGE")],
+        attribute_definitions: ["pk", "sk", "gsi1pk", "gsi1sk", "gsi2pk", "gsi2sk"]
+            .iter()
+            .map(|x| attribute(x))
+            .collect(),
+        global_secondary_indexes: Some(vec![index("gsi1"), index("gsi2")]),
+        billing_mode: Some("PAY_PER_REQUEST".to_owned()),
+        ..Default::default()
+    };
+
+    match dynamodb.create_table(input).await {
+        Ok(_) | Err(RusotoError::Service(CreateTableError::ResourceInUse(_))) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    let i = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[i]
+}
+
+fn report(name: &str, latencies: &mut Vec<Duration>, errors: usize) {
+    latencies.sort();
+    println!(
+        "{:<24} {:>6} {:>6} {:>9.1?} {:>9.1?} {:>9.1?} {:>9.1?}",
+        name,
+        latencies.len(),
+        errors,
+        percentile(latencies, 0.5),
+        percentile(latencies, 0.9),
+        percentile(latencies, 0.99),
+        latencies.last().copied().unwrap_or_default(),
+    );
+}
+
+async fn bench(args: Args) -> Result<()> {
+    let dynamodb = DynamoDbClient::new(Region::Custom {
+        name: "local".to_owned(),
+        endpoint: args.endpoint.clone(),
+    });
+    if args.create_table {
+        create_table(&dynamodb, &args.table).await?;
+    }
+    let schema = schema(Client::new(dynamodb, args.table.clone()), true);
+
+    let entries = std::fs::read_to_string(&args.mix)?
+        .lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<Entry>, _>>()?;
+    // Requests are picked round-robin from a list where each entry appears
+    // `weight` times, so runs with the same mix are repeatable.
+    let order = entries
+        .iter()
+        .enumerate()
+        .flat_map(|(i, x)| std::iter::repeat(i).take(x.weight))
+        .collect::<Vec<_>>();
+    if order.is_empty() {
+        println!("no requests in {:?}", args.mix);
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let results = stream::iter(0..args.requests)
+        .map(|n| {
+            let i = order[n % order.len()];
+            let entry = &entries[i];
+            let request = Request::new(entry.query.as_str())
+                .variables(Variables::from_json(entry.variables.clone()));
+            let schema = &schema;
+            async move {
+                let at = Instant::now();
+                let response = schema.execute(request).await;
+                (i, at.elapsed(), response.is_err())
+            }
+        })
+        .buffer_unordered(args.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    let elapsed = started.elapsed();
+
+    let mut by_entry: BTreeMap<usize, (Vec<Duration>, usize)> = BTreeMap::new();
+    let mut total = (Vec::new(), 0);
+    for (i, latency, error) in results {
+        let x = by_entry.entry(i).or_default();
+        x.0.push(latency);
+        total.0.push(latency);
+        if error {
+            x.1 += 1;
+            total.1 += 1;
+        }
+    }
+
+    println!(
+        "{:<24} {:>6} {:>6} {:>9} {:>9} {:>9} {:>9}",
+        "request", "count", "errors", "p50", "p90", "p99", "max"
+    );
+    for (i, (latencies, errors)) in by_entry.iter_mut() {
+        let name = entries[*i]
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("#{}", i + 1));
+        report(&name, latencies, *errors);
+    }
+    report("total", &mut total.0, total.1);
+    println!(
+        "{} requests in {:.2?} ({:.1} req/s)",
+        args.requests,
+        elapsed,
+        args.requests as f64 / elapsed.as_secs_f64()
+    );
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = bench(Args::from_args()).await {
+        println!("{:?}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,34 +1,91 @@
 use anyhow::Result;
-use async_graphql::Request;
-use lambda_runtime::{handler_fn, Error};
-use once_cell::sync::Lazy;
-use rusoto_core::Region;
-use rusoto_dynamodb::DynamoDbClient;
-use serde::{Deserialize, Serialize};
+use async_graphql::BatchRequest;
+use http::{Method, StatusCode};
+use lambda_http::{handler, Body, IntoResponse, Response};
+use lambda_runtime::{Context, Error};
+use once_cell::sync::OnceCell;
 
 use homeapi::dynamodb::Client;
 use homeapi::graphql::{schema, HomeAPI};
+use homeapi::idempotency::{self, Claim};
+use homeapi::request_id;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Event {
-    body: Option<String>,
+static DB: OnceCell<Client> = OnceCell::new();
+static SCHEMA: OnceCell<HomeAPI> = OnceCell::new();
+
+fn response(status: StatusCode, body: String, id: &str) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .header("x-request-id", id)
+        .body(Body::from(body))?)
 }
 
-static SCHEMA: Lazy<HomeAPI> = Lazy::new(|| {
-    schema(Client::new(
-        DynamoDbClient::new(Region::default()),
-        std::env::var("TABLE_NAME").unwrap(),
-    ))
-});
-
-async fn handler(event: Event, _context: lambda_runtime::Context) -> Result<String, Error> {
-    let req: Request = serde_json::from_str(&event.body.unwrap())?;
-    let res = SCHEMA.execute(req).await;
-    Ok(serde_json::to_string(&res)?)
+async fn graphql(event: lambda_http::Request, ctx: Context) -> Result<impl IntoResponse, Error> {
+    // Without an X-Request-Id the Lambda invocation id is used, which ties the
+    // response to the invocation's CloudWatch logs.
+    let id = event
+        .headers()
+        .get("x-request-id")
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or(&ctx.request_id);
+    let id = request_id::from_header(Some(id));
+
+    // ALB target group health checks are plain GETs without a body.
+    if event.method() == Method::GET && event.body().as_ref().is_empty() {
+        return response(StatusCode::OK, r#"{"status":"ok"}"#.to_owned(), &id);
+    }
+
+    let req: BatchRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(req) => req,
+        Err(e) => {
+            let body = serde_json::json!({ "errors": [{ "message": e.to_string() }] });
+            return response(StatusCode::BAD_REQUEST, body.to_string(), &id);
+        }
+    };
+
+    // Retries carrying the same Idempotency-Key get the stored response, or a
+    // conflict while the first run is still going.
+    let key = event
+        .headers()
+        .get("idempotency-key")
+        .and_then(|x| x.to_str().ok());
+    if let Some(key) = key {
+        match idempotency::claim(DB.get().unwrap(), key).await? {
+            Claim::Done(body) => return response(StatusCode::OK, body, &id),
+            Claim::InProgress => {
+                let message = "request with this idempotency key in progress";
+                let body = serde_json::json!({ "errors": [{ "message": message }] });
+                return response(StatusCode::CONFLICT, body.to_string(), &id);
+            }
+            Claim::Claimed => (),
+        }
+    }
+
+    let res = request_id::execute(SCHEMA.get().unwrap(), req, &id).await;
+    let body = serde_json::to_string(&res)?;
+    if let Some(key) = key {
+        // The request has run, so its response is returned even if it can't be
+        // stored for replay.
+        let stored = if res.is_ok() {
+            idempotency::record(DB.get().unwrap(), key, &body).await
+        } else {
+            idempotency::release(DB.get().unwrap(), key).await
+        };
+        if let Err(e) = stored {
+            println!("request {}: idempotency key {}: {:?}", id, key, e);
+        }
+    }
+    response(StatusCode::OK, body, &id)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    lambda_runtime::run(handler_fn(handler)).await?;
+    let dynamodb = Client::from_env()?;
+    let introspection = std::env::var("DISABLE_INTROSPECTION").is_err();
+    let _ = DB.set(dynamodb.clone());
+    let _ = SCHEMA.set(schema(dynamodb, introspection));
+
+    lambda_runtime::run(handler(graphql)).await?;
     Ok(())
 }
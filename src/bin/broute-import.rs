@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serialport::SerialPort;
+
+use homeapi::demand;
+use homeapi::dynamodb::Client;
+use homeapi::echonet;
+use homeapi::models::{Device, Electricity};
+
+const ECHONET_PORT: &str = "0E1A";
+
+static BROUTE_SERIAL: Lazy<String> =
+    Lazy::new(|| std::env::var("BROUTE_SERIAL").unwrap_or_else(|_| "/dev/ttyUSB0".to_owned()));
+static BROUTE_ID: Lazy<String> = Lazy::new(|| std::env::var("BROUTE_ID").unwrap());
+static BROUTE_PASSWORD: Lazy<String> = Lazy::new(|| std::env::var("BROUTE_PASSWORD").unwrap());
+static BROUTE_INTERVAL: Lazy<u64> = Lazy::new(|| {
+    std::env::var("BROUTE_INTERVAL")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(60)
+});
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+
+struct WiSun {
+    reader: BufReader<Box<dyn SerialPort>>,
+    meter: String,
+    meter_addr: String,
+    tid: u16,
+}
+
+impl WiSun {
+    fn open(path: &str) -> Result<BufReader<Box<dyn SerialPort>>> {
+        let port = serialport::new(path, 115_200)
+            .timeout(Duration::from_secs(1))
+            .open()?;
+        Ok(BufReader::new(port))
+    }
+
+    fn send(reader: &mut BufReader<Box<dyn SerialPort>>, command: &[u8]) -> Result<()> {
+        let port = reader.get_mut();
+        port.write_all(command)?;
+        port.flush()?;
+        Ok(())
+    }
+
+    fn read_line(reader: &mut BufReader<Box<dyn SerialPort>>) -> Result<Option<String>> {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(_) => Ok(Some(line.trim_end().to_owned())),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn wait_for<F>(
+        reader: &mut BufReader<Box<dyn SerialPort>>,
+        timeout: Duration,
+        mut f: F,
+    ) -> Result<String>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let deadline = std::time::Instant::now() + timeout;
+        while std::time::Instant::now() < deadline {
+            if let Some(line) = Self::read_line(reader)? {
+                if line.starts_with("FAIL") {
+                    return Err(anyhow!("command failed: {}", line));
+                }
+                if f(&line) {
+                    return Ok(line);
+                }
+            }
+        }
+        Err(anyhow!("timed out"))
+    }
+
+    fn command(reader: &mut BufReader<Box<dyn SerialPort>>, command: &str) -> Result<()> {
+        Self::send(reader, format!("{}\r\n", command).as_bytes())?;
+        Self::wait_for(reader, Duration::from_secs(5), |x| x == "OK")?;
+        Ok(())
+    }
+
+    fn connect(path: &str, id: &str, password: &str) -> Result<Self> {
+        let mut reader = Self::open(path)?;
+
+        Self::command(&mut reader, &format!("SKSETPWD C {}", password))?;
+        Self::command(&mut reader, &format!("SKSETRBID {}", id))?;
+
+        let mut channel = None;
+        let mut pan_id = None;
+        let mut addr = None;
+        for duration in 6..=8 {
+            Self::command(&mut reader, &format!("SKSCAN 2 FFFFFFFF {}", duration))?;
+            Self::wait_for(&mut reader, Duration::from_secs(60), |line| {
+                let line = line.trim();
+                if let Some(x) = line.strip_prefix("Channel:") {
+                    channel = Some(x.to_owned());
+                } else if let Some(x) = line.strip_prefix("Pan ID:") {
+                    pan_id = Some(x.to_owned());
+                } else if let Some(x) = line.strip_prefix("Addr:") {
+                    addr = Some(x.to_owned());
+                }
+                line.starts_with("EVENT 22")
+            })?;
+            if addr.is_some() {
+                break;
+            }
+        }
+
+        let (channel, pan_id, addr) = match (channel, pan_id, addr) {
+            (Some(channel), Some(pan_id), Some(addr)) => (channel, pan_id, addr),
+            _ => return Err(anyhow!("smart meter not found")),
+        };
+
+        Self::command(&mut reader, &format!("SKSREG S2 {}", channel))?;
+        Self::command(&mut reader, &format!("SKSREG S3 {}", pan_id))?;
+
+        Self::send(&mut reader, format!("SKLL64 {}\r\n", addr).as_bytes())?;
+        let meter_addr = Self::wait_for(&mut reader, Duration::from_secs(5), |x| x.contains(':'))?;
+
+        Self::command(&mut reader, &format!("SKJOIN {}", meter_addr))?;
+        let event = Self::wait_for(&mut reader, Duration::from_secs(60), |x| {
+            x.starts_with("EVENT 24") || x.starts_with("EVENT 25")
+        })?;
+        if event.starts_with("EVENT 24") {
+            return Err(anyhow!("PANA authentication failed"));
+        }
+
+        Ok(Self {
+            reader,
+            meter: format!("broute-{}", addr),
+            meter_addr,
+            tid: 0,
+        })
+    }
+
+    fn get(&mut self, epcs: &[u32]) -> Result<HashMap<u32, u32>> {
+        self.tid = self.tid.wrapping_add(1);
+        let frame = echonet::get_request(self.tid, epcs);
+
+        let mut command = format!(
+            "SKSENDTO 1 {} {} 1 {:04X} ",
+            self.meter_addr,
+            ECHONET_PORT,
+            frame.len()
+        )
+        .into_bytes();
+        command.extend_from_slice(&frame);
+        Self::send(&mut self.reader, &command)?;
+
+        let tid = self.tid;
+        let mut props = None;
+        Self::wait_for(&mut self.reader, Duration::from_secs(10), |line| {
+            if !line.starts_with("ERXUDP") {
+                return false;
+            }
+            let data = line.split_whitespace().last().unwrap_or("");
+            let bytes = (0..data.len() / 2)
+                .map(|i| u8::from_str_radix(&data[i * 2..i * 2 + 2], 16))
+                .collect::<Result<Vec<_>, _>>();
+            match bytes.map(|x| echonet::parse_get_response(tid, &x)) {
+                Ok(Ok(x)) => {
+                    props = Some(x);
+                    true
+                }
+                _ => false,
+            }
+        })?;
+
+        props.ok_or_else(|| anyhow!("no response from smart meter"))
+    }
+}
+
+async fn import(wisun: &mut WiSun) -> Result<()> {
+    let epcs = tokio::task::block_in_place(|| wisun.get(&echonet::METER_EPCS))?;
+    let timestamp = Utc::now();
+    let reading = echonet::meter_reading(&epcs);
+
+    let place = match DB.find_item::<Device>("DEVICE", &wisun.meter).await? {
+        Some(device) if device.deleted_at.is_some() => return Ok(()),
+        Some(device) => device.place,
+        None => {
+            let mut device = Device::new(wisun.meter.to_string());
+            device.place = "unknown".to_owned();
+            if !DB.put_item_if_absent(&device).await? {
+                // Created in the meantime, possibly with a place.
+                device = DB.get_item("DEVICE", &wisun.meter).await?;
+            }
+            device.place
+        }
+    };
+
+    let electricity = Electricity {
+        id: wisun.meter.to_string(),
+        timestamp,
+        place,
+        cumulative_kwh_p: reading.cumulative_kwh_p,
+        cumulative_kwh_n: reading.cumulative_kwh_n,
+        current_w: reading.current_w,
+    };
+    DB.put_item(&electricity).await?;
+    demand::track(&DB, &[electricity]).await?;
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(*BROUTE_INTERVAL));
+    let mut wisun = None;
+
+    loop {
+        interval.tick().await;
+
+        if wisun.is_none() {
+            match tokio::task::block_in_place(|| {
+                WiSun::connect(&BROUTE_SERIAL, &BROUTE_ID, &BROUTE_PASSWORD)
+            }) {
+                Ok(x) => wisun = Some(x),
+                Err(e) => {
+                    println!("{:?}", e);
+                    continue;
+                }
+            }
+        }
+
+        if let Err(e) = import(wisun.as_mut().unwrap()).await {
+            println!("{:?}", e);
+            wisun = None;
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use lambda_runtime::{handler_fn, Context, Error};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use homeapi::dynamodb::Client;
+use homeapi::models::CarbonIntensity;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Intensity {
+    forecast: Option<f64>,
+    actual: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Period {
+    from: String,
+    to: String,
+    intensity: Intensity,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NationalResponse {
+    data: Vec<Period>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Region {
+    data: Vec<Period>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RegionalResponse {
+    data: Region,
+}
+
+static REQWEST: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::ClientBuilder::new()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap()
+});
+// The GB Carbon Intensity API. Regional values are forecasts only; the
+// national series also has actual values.
+static CARBON_API_URL: Lazy<String> = Lazy::new(|| {
+    std::env::var("CARBON_API_URL")
+        .unwrap_or_else(|_| "https://api.carbonintensity.org.uk".to_owned())
+});
+static CARBON_REGION_ID: Lazy<Option<String>> =
+    Lazy::new(|| std::env::var("CARBON_REGION_ID").ok());
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+
+// The API writes times as "2021-06-01T12:30Z".
+fn parse_time(s: &str) -> Result<DateTime<Utc>> {
+    let t = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%MZ")?;
+    Ok(DateTime::from_utc(t, Utc))
+}
+
+async fn fetch(from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Period>> {
+    let range = format!(
+        "{}/{}",
+        from.format("%Y-%m-%dT%H:%MZ"),
+        to.format("%Y-%m-%dT%H:%MZ")
+    );
+    let base = CARBON_API_URL.trim_end_matches('/');
+    let periods = match &*CARBON_REGION_ID {
+        Some(region) => {
+            let url = format!("{}/regional/intensity/{}/regionid/{}", base, range, region);
+            let res: RegionalResponse = REQWEST
+                .get(&url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            res.data.data
+        }
+        None => {
+            let url = format!("{}/intensity/{}", base, range);
+            let res: NationalResponse = REQWEST
+                .get(&url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            res.data
+        }
+    };
+    Ok(periods)
+}
+
+// Runs hourly: refreshes the past day with actual values where published
+// and stores forecasts for the next day.
+async fn import() -> Result<()> {
+    let now = Utc::now();
+    let periods = fetch(
+        now - chrono::Duration::days(1),
+        now + chrono::Duration::days(1),
+    )
+    .await?;
+    let mut items = Vec::new();
+
+    for period in periods {
+        let (from, to) = (parse_time(&period.from)?, parse_time(&period.to)?);
+        let item = match (period.intensity.actual, period.intensity.forecast) {
+            (Some(x), _) => CarbonIntensity::new(from, to, x, false),
+            (None, Some(x)) => CarbonIntensity::new(from, to, x, true),
+            (None, None) => continue,
+        };
+        items.push(item);
+    }
+
+    println!("{} intensity period(s)", items.len());
+    DB.put_items(items).await
+}
+
+async fn handler(_: Value, _: Context) -> Result<(), Error> {
+    import().await.map_err(|e| {
+        println!("{:?}", e);
+        Error::from("error")
+    })?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    lambda_runtime::run(handler_fn(handler)).await?;
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use lambda_runtime::{handler_fn, Context, Error};
+use once_cell::sync::Lazy;
+use rusoto_dynamodb::AttributeValue;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use homeapi::aggregation::{self, Tier};
+use homeapi::dynamodb::{attr_string, Client, Condition};
+use homeapi::models::{live, Compaction, Device, DynamoItem, Electricity, PlaceCondition};
+
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+// Raw readings are kept for this long.
+static COMPACT_AFTER_DAYS: Lazy<i64> = Lazy::new(|| {
+    std::env::var("COMPACT_AFTER_DAYS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(90)
+});
+// Days compacted per device in one run, so that a first run over a long
+// history fits in the Lambda timeout. The rest is picked up next time.
+static COMPACT_BATCH_DAYS: Lazy<i64> = Lazy::new(|| {
+    std::env::var("COMPACT_BATCH_DAYS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(31)
+});
+
+// Electricity and PlaceCondition share the TS# prefix; a device is a meter
+// if its readings have a cumulative value.
+#[derive(Debug, Deserialize)]
+struct Reading {
+    sk: String,
+    cumulative_kwh_p: Option<Decimal>,
+}
+
+impl Reading {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.sk.strip_prefix("TS#").and_then(|x| x.parse().ok())
+    }
+}
+
+fn raw_range(from: DateTime<Utc>, to: DateTime<Utc>) -> Condition {
+    let prefix = Electricity::sk_prefix();
+    Condition::Between(
+        format!("{}{:?}", prefix, from),
+        format!("{}{:?}", prefix, to - Duration::milliseconds(1)),
+    )
+}
+
+fn midnight(timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    timestamp.date().and_hms(0, 0, 0)
+}
+
+// Moves serialized readings from TS# to the tier's prefix.
+fn to_tier<S: Serialize>(items: &[S], tier: Tier) -> Result<Vec<HashMap<String, AttributeValue>>> {
+    let mut tiered = Vec::new();
+    for item in items {
+        let mut item = serde_dynamodb::to_hashmap(item)?;
+        let sk = item
+            .get("sk")
+            .and_then(|x| x.s.as_deref())
+            .and_then(|x| x.strip_prefix(&Electricity::sk_prefix()))
+            .map(|x| format!("{}{}", tier.sk_prefix(), x));
+        if let Some(sk) = sk {
+            item.insert("sk".to_owned(), attr_string(sk));
+            tiered.push(item);
+        }
+    }
+    Ok(tiered)
+}
+
+// Moves the compaction point forward only, so that a run finishing late can't
+// hide days compacted since.
+async fn advance(device: &str, until: DateTime<Utc>) -> Result<()> {
+    let mut params = HashMap::new();
+    params.insert(":until".to_owned(), attr_string(format!("{:?}", until)));
+    DB.put_item_if(
+        &Compaction::new(device.to_owned(), until),
+        "attribute_not_exists(sk) OR #until < :until",
+        params,
+    )
+    .await?;
+    Ok(())
+}
+
+// Writes both summary tiers of one day before its raw readings are deleted,
+// so a failure in between leaves the raw readings in place. Buckets already
+// written are kept: a day redone from rows a failed run left behind must not
+// replace a full summary with a partial one.
+async fn compact_day(device: &str, meter: bool, day: DateTime<Utc>) -> Result<usize> {
+    let end = day + Duration::days(1);
+    let mut items = Vec::new();
+
+    if meter {
+        let raw: Vec<Electricity> = DB.get_range(device, Some(raw_range(day, end))).await?;
+        for tier in Tier::ALL.iter() {
+            let summary =
+                aggregation::downsample_electricity(raw.clone(), tier.resolution(), Tz::UTC);
+            items.extend(to_tier(&summary, *tier)?);
+        }
+    } else {
+        let raw: Vec<PlaceCondition> = DB.get_range(device, Some(raw_range(day, end))).await?;
+        for tier in Tier::ALL.iter() {
+            let summary =
+                aggregation::downsample_place_conditions(raw.clone(), tier.resolution(), Tz::UTC);
+            items.extend(to_tier(&summary, *tier)?);
+        }
+    }
+
+    for item in items {
+        DB.put_attributes_if_absent(item).await?;
+    }
+    let deleted = DB.delete_range(device, raw_range(day, end)).await?;
+    advance(device, end).await?;
+    Ok(deleted)
+}
+
+// Resumes at the first raw reading from the compaction point on. Days before
+// it are never compacted again; readings arriving late for them stay raw.
+async fn compact_device(device: &Device, cutoff: DateTime<Utc>) -> Result<usize> {
+    let prefix = Electricity::sk_prefix();
+    let until = aggregation::compacted_until(&DB, &device.id).await?;
+    let from = until.map_or_else(String::new, |x| format!("{:?}", x));
+    let (first, _): (Vec<Reading>, _) = DB
+        .get_items(
+            &device.id,
+            Some(Condition::Between(
+                format!("{}{}", prefix, from),
+                format!("{}{:?}", prefix, cutoff),
+            )),
+            Vec::new(),
+            None,
+            None,
+            Some(1),
+            None,
+        )
+        .await?;
+    let first = match first.into_iter().next() {
+        Some(x) => x,
+        None => return Ok(0),
+    };
+    let meter = first.cumulative_kwh_p.is_some();
+    let start = match first.timestamp() {
+        Some(x) => midnight(x),
+        None => return Ok(0),
+    };
+    let mut deleted = 0;
+
+    for i in 0..*COMPACT_BATCH_DAYS {
+        let day = start + Duration::days(i);
+        if day >= cutoff {
+            break;
+        }
+        deleted += compact_day(&device.id, meter, day).await?;
+    }
+
+    Ok(deleted)
+}
+
+async fn compact() -> Result<()> {
+    let cutoff = midnight(Utc::now() - Duration::days(*COMPACT_AFTER_DAYS));
+    let devices: Vec<Device> = live(DB.get_range("DEVICE", None).await?);
+    let mut deleted = 0;
+
+    for device in devices.iter() {
+        deleted += compact_device(device, cutoff).await?;
+    }
+
+    println!("{} raw reading(s) compacted before {:?}", deleted, cutoff);
+    Ok(())
+}
+
+async fn handler(_: Value, _: Context) -> Result<(), Error> {
+    compact().await.map_err(|e| {
+        println!("{:?}", e);
+        Error::from("error")
+    })?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    lambda_runtime::run(handler_fn(handler)).await?;
+    Ok(())
+}
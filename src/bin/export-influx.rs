@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use homeapi::dynamodb::{Client, Condition};
+use homeapi::models::{live, ApplianceState, BatteryState, Device, DynamoItem, SolarProduction};
+
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+static REQWEST: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::ClientBuilder::new()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap()
+});
+// Full write URL, e.g. http://influxdb:8086/write?db=home or
+// http://victoriametrics:8428/write.
+static INFLUX_URL: Lazy<String> = Lazy::new(|| std::env::var("INFLUX_URL").unwrap());
+static INFLUX_TOKEN: Lazy<Option<String>> = Lazy::new(|| std::env::var("INFLUX_TOKEN").ok());
+static EXPORT_INTERVAL: Lazy<u64> = Lazy::new(|| {
+    std::env::var("EXPORT_INTERVAL")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(60)
+});
+// Each round re-exports this window so that late writes are picked up.
+// Points with the same series and timestamp overwrite each other in Influx,
+// so the overlap is harmless.
+static EXPORT_LOOKBACK_MINUTES: Lazy<i64> = Lazy::new(|| {
+    std::env::var("EXPORT_LOOKBACK_MINUTES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(10)
+});
+const LINES_PER_REQUEST: usize = 5000;
+
+// Electricity and PlaceCondition share the TS# prefix, so both sets of
+// fields are optional here and the measurement is picked per item.
+#[derive(Debug, Serialize, Deserialize)]
+struct Reading {
+    pk: String,
+    sk: String,
+    #[serde(default)]
+    place: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cumulative_kwh_p: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cumulative_kwh_n: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_w: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    humidity: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    illuminance: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    motion: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    battery_percent: Option<f64>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn field(value: &Value) -> Option<String> {
+    match value {
+        Value::Bool(x) => Some(x.to_string()),
+        Value::Number(x) if x.is_f64() => Some(x.to_string()),
+        Value::Number(x) => Some(format!("{}i", x)),
+        Value::String(x) => Some(format!(
+            "\"{}\"",
+            x.replace('\\', "\\\\").replace('"', "\\\"")
+        )),
+        _ => None,
+    }
+}
+
+// Measurement names match the kinds accepted by /write, so exported data can
+// be written back unchanged.
+fn line<S: Serialize>(measurement: &str, prefix: &str, item: &S) -> Result<Option<String>> {
+    let item = match serde_json::to_value(item)? {
+        Value::Object(x) => x,
+        _ => return Ok(None),
+    };
+    let device = item.get("pk").and_then(Value::as_str).unwrap_or_default();
+    let timestamp = item
+        .get("sk")
+        .and_then(Value::as_str)
+        .and_then(|x| x.strip_prefix(prefix))
+        .and_then(|x| x.parse::<DateTime<Utc>>().ok());
+    let timestamp = match timestamp {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let mut tags = format!("{},device={}", measurement, escape(device));
+    if let Some(place) = item.get("place").and_then(Value::as_str) {
+        if !place.is_empty() {
+            tags += &format!(",place={}", escape(place));
+        }
+    }
+    let fields = item
+        .iter()
+        .filter(|(k, _)| !matches!(k.as_str(), "pk" | "sk" | "place"))
+        .filter_map(|(k, v)| field(v).map(|v| format!("{}={}", escape(k), v)))
+        .collect::<Vec<_>>();
+    if fields.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(format!(
+        "{} {} {}",
+        tags,
+        fields.join(","),
+        timestamp.timestamp_nanos()
+    )))
+}
+
+async fn range<D>(
+    device: &Device,
+    prefix: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<D>>
+where
+    D: DeserializeOwned,
+{
+    DB.get_range(
+        &device.id,
+        Some(Condition::Between(
+            format!("{}{:?}", prefix, from),
+            format!("{}{:?}", prefix, to),
+        )),
+    )
+    .await
+}
+
+async fn export_device(
+    device: &Device,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+
+    let prefix = "TS#";
+    for x in range::<Reading>(device, prefix, from, to).await? {
+        let measurement = if x.cumulative_kwh_p.is_some() {
+            "electricity"
+        } else {
+            "place_condition"
+        };
+        lines.extend(line(measurement, prefix, &x)?);
+    }
+    let prefix = ApplianceState::sk_prefix();
+    for x in range::<ApplianceState>(device, &prefix, from, to).await? {
+        lines.extend(line("appliance_state", &prefix, &x)?);
+    }
+    let prefix = SolarProduction::sk_prefix();
+    for x in range::<SolarProduction>(device, &prefix, from, to).await? {
+        lines.extend(line("solar_production", &prefix, &x)?);
+    }
+    let prefix = BatteryState::sk_prefix();
+    for x in range::<BatteryState>(device, &prefix, from, to).await? {
+        lines.extend(line("battery_state", &prefix, &x)?);
+    }
+
+    Ok(lines)
+}
+
+async fn send(lines: &[String]) -> Result<()> {
+    let mut request = REQWEST.post(INFLUX_URL.as_str()).body(lines.join("\n"));
+    if let Some(token) = INFLUX_TOKEN.as_ref() {
+        request = request.header("Authorization", format!("Token {}", token));
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+async fn export() -> Result<()> {
+    let to = Utc::now();
+    let from = to - chrono::Duration::minutes(*EXPORT_LOOKBACK_MINUTES);
+    let devices: Vec<Device> = live(DB.get_range("DEVICE", None).await?);
+    let mut lines = Vec::new();
+
+    for device in devices.iter() {
+        lines.extend(export_device(device, from, to).await?);
+    }
+    for chunk in lines.chunks(LINES_PER_REQUEST) {
+        send(chunk).await?;
+    }
+
+    println!("{} point(s) exported since {:?}", lines.len(), from);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(*EXPORT_INTERVAL));
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = export().await {
+            println!("{:?}", e);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use http::StatusCode;
+use lambda_http::{handler, Body, IntoResponse, Response};
+use lambda_runtime::{Context, Error};
+use once_cell::sync::{Lazy, OnceCell};
+use serde_json::{json, Value};
+
+use homeapi::dynamodb::Client;
+use homeapi::google;
+
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+// The access token issued to Google through account linking. Requests
+// without it are rejected.
+static GOOGLE_ACCESS_TOKEN: OnceCell<String> = OnceCell::new();
+
+// Takes as long for any wrong token of the right length, so the token can't be
+// guessed byte by byte from response times.
+fn authorized(token: Option<&str>, expected: &str) -> bool {
+    token.map_or(false, |x| {
+        x.len() == expected.len()
+            && x.bytes()
+                .zip(expected.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    })
+}
+
+fn response(status: StatusCode, body: Value) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))?)
+}
+
+// The fulfillment URL of a Smart Home action, e.g. behind a Lambda function
+// URL or API Gateway.
+async fn fulfillment(event: lambda_http::Request, _: Context) -> Result<impl IntoResponse, Error> {
+    let token = event
+        .headers()
+        .get("authorization")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+    if !authorized(token, GOOGLE_ACCESS_TOKEN.get().unwrap()) {
+        return response(StatusCode::UNAUTHORIZED, json!({ "error": "unauthorized" }));
+    }
+
+    let request: Value = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(x) => x,
+        Err(e) => return response(StatusCode::BAD_REQUEST, json!({ "error": e.to_string() })),
+    };
+    let body = google::fulfill(&DB, &request).await.map_err(|e| {
+        println!("{:?}", e);
+        Error::from("error")
+    })?;
+    response(StatusCode::OK, body)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let token = std::env::var("GOOGLE_ACCESS_TOKEN")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .ok_or("GOOGLE_ACCESS_TOKEN is not set")?;
+    let _ = GOOGLE_ACCESS_TOKEN.set(token);
+
+    lambda_runtime::run(handler(fulfillment)).await?;
+    Ok(())
+}
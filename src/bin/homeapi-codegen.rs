@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use structopt::StructOpt;
+
+use homeapi::codegen;
+use homeapi::graphql::sdl;
+
+/// Writes the GraphQL schema in SDL and TypeScript types generated from it,
+/// for the frontend to build against.
+#[derive(Debug, StructOpt)]
+struct Args {
+    /// Directory to write schema.graphql and schema.ts to
+    #[structopt(long, default_value = ".", parse(from_os_str))]
+    out_dir: PathBuf,
+
+    /// Fail if the files differ from the schema instead of writing them
+    #[structopt(long)]
+    check: bool,
+}
+
+fn generate(args: Args) -> Result<()> {
+    let sdl = sdl();
+    let typescript = codegen::typescript(&sdl)?;
+    let files = [("schema.graphql", sdl), ("schema.ts", typescript)];
+
+    if args.check {
+        let stale = files
+            .iter()
+            .filter(|(name, content)| {
+                std::fs::read_to_string(args.out_dir.join(name))
+                    .ok()
+                    .as_ref()
+                    != Some(content)
+            })
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        if !stale.is_empty() {
+            return Err(anyhow!("out of date: {}", stale.join(", ")));
+        }
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(&args.out_dir)?;
+    for (name, content) in files.iter() {
+        std::fs::write(args.out_dir.join(name), content)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = generate(Args::from_args()) {
+        println!("{:?}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,52 +1,680 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
-use async_graphql_warp::{BadRequest, Response};
-use http::StatusCode;
+use async_graphql_warp::{BadRequest, BatchResponse};
+use http::{HeaderValue, StatusCode};
 use once_cell::sync::Lazy;
-use rusoto_core::Region;
-use rusoto_dynamodb::DynamoDbClient;
-use warp::{http::Response as HttpResponse, Filter, Rejection};
+use rustls::internal::pemfile;
+use rustls::{NoClientAuth, ServerConfig};
+use structopt::StructOpt;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{oneshot, watch};
+use warp::{http::Response as HttpResponse, Filter, Rejection, Reply};
 
+use homeapi::cors::{self, OriginPattern};
 use homeapi::dynamodb::Client;
-use homeapi::graphql::{schema, HomeAPI};
+use homeapi::error::{ApiError, Code};
+use homeapi::graphiql::graphiql_source;
+use homeapi::graphql::{schema, sdl, HomeAPI};
+use homeapi::idempotency::{self, Claim};
+use homeapi::models::Device;
+use homeapi::signature::Signature;
+use homeapi::{backup, homeassistant, influx, metrics, openapi, prometheus, request_id, webhook};
 
-static SCHEMA: Lazy<HomeAPI> = Lazy::new(|| {
-    schema(Client::new(
-        DynamoDbClient::new(Region::default()),
-        std::env::var("TABLE_NAME").unwrap(),
-    ))
-});
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Ui {
+    Graphiql,
+    Playground,
+    None,
+}
+
+impl FromStr for Ui {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "graphiql" => Ok(Ui::Graphiql),
+            "playground" => Ok(Ui::Playground),
+            "none" => Ok(Ui::None),
+            _ => Err(format!("unknown ui: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct Args {
+    /// Storage backend: dynamodb, memory or sqlite://<path> (default: STORAGE)
+    #[structopt(long, value_name = "url")]
+    storage: Option<String>,
+
+    /// Print the GraphQL schema in SDL and exit
+    #[structopt(long)]
+    print_schema: bool,
+
+    /// Take an on-demand DynamoDB backup with the given name and exit
+    #[structopt(long, value_name = "name")]
+    create_backup: Option<String>,
+
+    /// Serve the GraphQL schema in SDL at /schema.graphql
+    #[structopt(long)]
+    schema_route: bool,
+
+    /// Reject introspection queries
+    #[structopt(long)]
+    disable_introspection: bool,
+
+    /// GraphQL IDE served at / (graphiql, playground or none)
+    #[structopt(long, default_value = "playground")]
+    ui: Ui,
+
+    /// Allowed CORS origin: exact, wildcard (https://*.example.com) or ~regex
+    #[structopt(long = "cors-origin")]
+    cors_origins: Vec<OriginPattern>,
+
+    /// PEM certificate chain to serve HTTPS with
+    #[structopt(long, requires = "tls-key", parse(from_os_str))]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key to serve HTTPS with
+    #[structopt(long, requires = "tls-cert", parse(from_os_str))]
+    tls_key: Option<PathBuf>,
+
+    /// Seconds to let in-flight requests finish after SIGTERM/SIGINT
+    #[structopt(long, default_value = "10")]
+    shutdown_grace: u64,
+
+    /// Seconds a GraphQL or webhook request may run before it is aborted
+    #[structopt(long, default_value = "30")]
+    request_timeout: u64,
+
+    /// Maximum request body size in bytes
+    #[structopt(long, default_value = "1048576")]
+    max_body_size: u64,
+}
+
+#[derive(Debug)]
+struct ServerError(anyhow::Error);
+
+impl warp::reject::Reject for ServerError {}
+
+#[derive(Debug)]
+struct Timeout;
+
+impl warp::reject::Reject for Timeout {}
+
+const TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+
+fn with_cors<R: Reply>(
+    origins: &[OriginPattern],
+    origin: Option<String>,
+    reply: R,
+) -> warp::reply::Response {
+    let mut res = reply.into_response();
+    let headers = res.headers_mut();
+    // Responses to other origins lack the header, so they vary by origin too.
+    headers.append("vary", HeaderValue::from_static("origin"));
+    if let Some(origin) = origin.filter(|x| cors::allow_origin(origins, x)) {
+        if let Ok(value) = origin.parse() {
+            headers.insert("access-control-allow-origin", value);
+        }
+    }
+    res
+}
+
+// Whether a response is compressed depends on Accept-Encoding, so caches must
+// not serve it to clients that sent another one.
+fn vary_encoding<R: Reply>(reply: R) -> warp::reply::Response {
+    let mut res = reply.into_response();
+    res.headers_mut()
+        .append("vary", HeaderValue::from_static("accept-encoding"));
+    res
+}
+
+fn accepts(encoding: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("accept-encoding")
+        .and_then(move |accept: Option<String>| async move {
+            let accepted = accept.unwrap_or_default().split(',').any(|x| {
+                let mut params = x.split(';').map(str::trim);
+                params.next() == Some(encoding) && params.all(|x| x != "q=0")
+            });
+            if accepted {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+fn graphql_error(message: &str, status: StatusCode) -> warp::reply::Response {
+    let body = serde_json::json!({ "errors": [{ "message": message }] });
+    warp::reply::with_status(warp::reply::json(&body), status).into_response()
+}
+
+// With an Idempotency-Key header the first successful response is stored and
+// replayed to retries instead of running the request again. A replay carries
+// the request id of the run that produced it in its errors. A retry arriving
+// while the first run is still going gets a conflict.
+async fn execute(
+    schema: HomeAPI,
+    request: async_graphql::BatchRequest,
+    key: Option<String>,
+    id: &str,
+) -> anyhow::Result<warp::reply::Response> {
+    let key = match key {
+        Some(x) => x,
+        None => {
+            let res = request_id::execute(&schema, request, id).await;
+            let mut res = BatchResponse::from(res).into_response();
+            res.headers_mut().insert("x-request-id", id.parse()?);
+            return Ok(res);
+        }
+    };
+
+    let body = match idempotency::claim(&DB, &key).await? {
+        Claim::Done(x) => x,
+        Claim::InProgress => {
+            return Ok(graphql_error(
+                "request with this idempotency key in progress",
+                StatusCode::CONFLICT,
+            ))
+        }
+        Claim::Claimed => {
+            let res = request_id::execute(&schema, request, id).await;
+            let body = serde_json::to_string(&res)?;
+            // The request has run, so its response is returned even if it
+            // can't be stored for replay.
+            let stored = if res.is_ok() {
+                idempotency::record(&DB, &key, &body).await
+            } else {
+                idempotency::release(&DB, &key).await
+            };
+            if let Err(e) = stored {
+                println!("request {}: idempotency key {}: {:?}", id, key, e);
+            }
+            body
+        }
+    };
+    Ok(HttpResponse::builder()
+        .header("content-type", "application/json")
+        .header("x-request-id", id)
+        .body(body)
+        .into_response())
+}
+
+fn api_error(e: &ApiError, status: StatusCode) -> warp::reply::Response {
+    let body = serde_json::json!({
+        "errors": [{
+            "message": e.message,
+            "extensions": { "code": e.code.as_str(), "field": e.field },
+        }]
+    });
+    warp::reply::with_status(warp::reply::json(&body), status).into_response()
+}
+
+// Signed requests carry all three headers; anything less is unsigned.
+fn signature_headers(
+    device: Option<String>,
+    timestamp: Option<i64>,
+    signature: Option<String>,
+) -> Option<Signature> {
+    match (device, timestamp, signature) {
+        (Some(device), Some(timestamp), Some(signature)) => Some(Signature {
+            device,
+            timestamp,
+            signature,
+        }),
+        _ => None,
+    }
+}
+
+fn modified(paths: &[&Path]) -> Option<SystemTime> {
+    paths
+        .iter()
+        .filter_map(|x| std::fs::metadata(x).and_then(|x| x.modified()).ok())
+        .max()
+}
+
+async fn changed(cert: PathBuf, key: PathBuf, since: Option<SystemTime>) {
+    loop {
+        tokio::time::sleep(TLS_RELOAD_INTERVAL).await;
+        if modified(&[&cert, &key]) != since {
+            return;
+        }
+    }
+}
+
+type Pem = (Vec<u8>, Vec<u8>);
+
+// Checks the certificate and key the way warp builds its TLS config, since
+// warp panics on one it can't build.
+fn load_tls(cert: &Path, key: &Path) -> anyhow::Result<Pem> {
+    let cert_pem = std::fs::read(cert)?;
+    let key_pem = std::fs::read(key)?;
+
+    let certs = pemfile::certs(&mut cert_pem.as_slice())
+        .map_err(|_| anyhow::anyhow!("invalid certificate {}", cert.display()))?;
+    let invalid_key = |_| anyhow::anyhow!("invalid key {}", key.display());
+    let mut keys = pemfile::pkcs8_private_keys(&mut key_pem.as_slice()).map_err(invalid_key)?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut key_pem.as_slice()).map_err(invalid_key)?;
+    }
+    let private_key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no key in {}", key.display()))?;
+    ServerConfig::new(NoClientAuth::new()).set_single_cert(certs, private_key)?;
+
+    Ok((cert_pem, key_pem))
+}
+
+// Waits for the files to change to a certificate and key that load. Until
+// then the server keeps the ones it has.
+async fn reloaded(
+    cert: PathBuf,
+    key: PathBuf,
+    mut since: Option<SystemTime>,
+) -> (Pem, Option<SystemTime>) {
+    loop {
+        changed(cert.clone(), key.clone(), since).await;
+        since = modified(&[&cert, &key]);
+        match load_tls(&cert, &key) {
+            Ok(pem) => return (pem, since),
+            Err(e) => log::error!("failed to load certificate: {:?}", e),
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate.recv() => (),
+    }
+}
+
+async fn stopped(mut shutdown: watch::Receiver<bool>) {
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn serve_tls<F>(
+    routes: F,
+    addr: SocketAddr,
+    cert: PathBuf,
+    key: PathBuf,
+    shutdown: watch::Receiver<bool>,
+) where
+    F: Filter<Error = Infallible> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let since = modified(&[&cert, &key]);
+    let (mut pem, mut since) = match load_tls(&cert, &key) {
+        Ok(pem) => (pem, since),
+        Err(e) => {
+            log::error!("failed to load certificate: {:?}", e);
+            tokio::select! {
+                x = reloaded(cert.clone(), key.clone(), since) => x,
+                _ = stopped(shutdown.clone()) => return,
+            }
+        }
+    };
+
+    loop {
+        let (tx, next) = oneshot::channel();
+        let reload = reloaded(cert.clone(), key.clone(), since);
+        let stop = stopped(shutdown.clone());
+        let (_, server) = warp::serve(routes.clone())
+            .tls()
+            .cert(&pem.0)
+            .key(&pem.1)
+            .bind_with_graceful_shutdown(addr, async move {
+                tokio::select! {
+                    x = reload => {
+                        let _ = tx.send(x);
+                    }
+                    _ = stop => (),
+                }
+            });
+        server.await;
+
+        match next.await {
+            Ok(x) => {
+                pem = x.0;
+                since = x.1;
+            }
+            Err(_) => return,
+        }
+        log::info!("certificate changed, reloading");
+    }
+}
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let graphql_post = async_graphql_warp::graphql(SCHEMA.clone()).and_then(
-        |(schema, request): (HomeAPI, async_graphql::Request)| async move {
-            Ok::<_, Infallible>(Response::from(schema.execute(request).await))
-        },
-    );
+    let args = Args::from_args();
+    if let Some(url) = &args.storage {
+        std::env::set_var("STORAGE", url);
+    }
+    if args.print_schema {
+        print!("{}", sdl());
+        return;
+    }
+    if let Some(name) = &args.create_backup {
+        match backup::create_backup(&DB, name, None).await {
+            Ok(backup) => println!("{}", backup.arn),
+            Err(e) => {
+                println!("{:?}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let graphql_playbround = warp::path::end().and(warp::get()).map(|| {
-        HttpResponse::builder()
-            .header("content-type", "text/html")
-            .body(playground_source(GraphQLPlaygroundConfig::new("/")))
-    });
+    let timeout = Duration::from_secs(args.request_timeout);
+    // Bodies without a content-length are refused, so chunked uploads can't
+    // get around the limit.
+    let body_limit = warp::body::content_length_limit(args.max_body_size);
+    let graphql_post = warp::get()
+        .or(body_limit)
+        .unify()
+        .and(async_graphql_warp::graphql_batch(schema(
+            DB.clone(),
+            !args.disable_introspection,
+        )))
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and(warp::header::optional::<String>("x-request-id"))
+        .and_then(
+            move |(schema, request): (HomeAPI, async_graphql::BatchRequest),
+                  key: Option<String>,
+                  id: Option<String>| async move {
+                let id = request_id::from_header(id.as_deref());
+                tokio::time::timeout(timeout, execute(schema, request, key, &id))
+                    .await
+                    .map_err(|_| warp::reject::custom(Timeout))?
+                    .map_err(|e| {
+                        let e = e.context(format!("request {}", id));
+                        warp::reject::custom(ServerError(e))
+                    })
+            },
+        );
 
-    let routes = graphql_playbround
-        .or(graphql_post)
-        .recover(|err: Rejection| async move {
-            if let Some(BadRequest(err)) = err.find() {
-                return Ok::<_, Infallible>(warp::reply::with_status(
-                    err.to_string(),
-                    StatusCode::BAD_REQUEST,
-                ));
+    let ui = args.ui;
+    let graphql_ui = warp::path::end()
+        .and(warp::get())
+        .and_then(move || async move {
+            let source = match ui {
+                Ui::Graphiql => graphiql_source("/", "/"),
+                Ui::Playground => playground_source(GraphQLPlaygroundConfig::new("/")),
+                Ui::None => return Err(warp::reject::not_found()),
+            };
+            Ok(HttpResponse::builder()
+                .header("content-type", "text/html")
+                .body(source))
+        });
+
+    let schema_route = args.schema_route;
+    let graphql_schema =
+        warp::path!("schema.graphql")
+            .and(warp::get())
+            .and_then(move || async move {
+                if !schema_route {
+                    return Err(warp::reject::not_found());
+                }
+                Ok(HttpResponse::builder()
+                    .header("content-type", "text/plain; charset=utf-8")
+                    .body(sdl()))
+            });
+
+    let openapi = warp::path!("openapi.json")
+        .and(warp::get())
+        .map(|| warp::reply::json(&openapi::document()));
+
+    let ha_sensors = warp::path!("ha" / "sensors")
+        .and(warp::get())
+        .and_then(|| async move {
+            homeassistant::sensors(&DB)
+                .await
+                .map(|x| warp::reply::json(&x))
+                .map_err(|e| warp::reject::custom(ServerError(e)))
+        });
+
+    let ha_sensor = warp::path!("ha" / "sensors" / String / String)
+        .and(warp::get())
+        .and_then(|device: String, metric: String| async move {
+            let device = DB
+                .find_item("DEVICE", &device)
+                .await
+                .ok()
+                .flatten()
+                .filter(|x: &Device| x.deleted_at.is_none())
+                .ok_or_else(warp::reject::not_found)?;
+            homeassistant::device_sensors(&DB, &device)
+                .await
+                .map_err(|e| warp::reject::custom(ServerError(e)))?
+                .into_iter()
+                .find(|x| x.metric == metric)
+                .map(|x| x.state)
+                .ok_or_else(warp::reject::not_found)
+        });
+
+    let metrics_sensors = warp::path!("metrics" / "sensors")
+        .and(warp::get())
+        .and_then(|| async move {
+            prometheus::sensors(&DB)
+                .await
+                .map(|x| warp::reply::with_header(x, "content-type", "text/plain; version=0.0.4"))
+                .map_err(|e| warp::reject::custom(ServerError(e)))
+        });
+
+    let metrics_dynamodb = warp::path!("metrics" / "dynamodb")
+        .and(warp::get())
+        .map(|| {
+            warp::reply::with_header(
+                metrics::render(),
+                "content-type",
+                "text/plain; version=0.0.4",
+            )
+        });
+
+    let webhook = warp::path!("webhook" / String)
+        .and(warp::post())
+        .and(warp::header::optional::<String>("x-device-id"))
+        .and(warp::header::optional::<i64>("x-timestamp"))
+        .and(warp::header::optional::<String>("x-signature"))
+        .and(body_limit)
+        .and(warp::body::bytes())
+        .and_then(
+            move |source: String,
+                  device: Option<String>,
+                  timestamp: Option<i64>,
+                  signature: Option<String>,
+                  body: warp::hyper::body::Bytes| async move {
+                let signature = signature_headers(device, timestamp, signature);
+                let ingest = webhook::receive(&DB, &source, signature, &body);
+                match tokio::time::timeout(timeout, ingest)
+                    .await
+                    .map_err(|_| warp::reject::custom(Timeout))?
+                {
+                    Ok(Some(written)) => Ok(warp::reply::json(&serde_json::json!({
+                        "written": written
+                    }))
+                    .into_response()),
+                    Ok(None) => Err(warp::reject::not_found()),
+                    Err(e) => match e.downcast_ref::<ApiError>() {
+                        Some(e) if e.code == Code::ValidationFailed => {
+                            Ok(api_error(e, StatusCode::BAD_REQUEST))
+                        }
+                        Some(e) if e.code == Code::Unauthorized => {
+                            Ok(api_error(e, StatusCode::UNAUTHORIZED))
+                        }
+                        _ => Err(warp::reject::custom(ServerError(e))),
+                    },
+                }
+            },
+        );
+
+    // InfluxDB line protocol, so that Telegraf can push without a webhook rule.
+    let influx = warp::path!("write")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("x-device-id"))
+        .and(warp::header::optional::<i64>("x-timestamp"))
+        .and(warp::header::optional::<String>("x-signature"))
+        .and(body_limit)
+        .and(warp::body::bytes())
+        .and_then(
+            move |params: HashMap<String, String>,
+                  device: Option<String>,
+                  timestamp: Option<i64>,
+                  signature: Option<String>,
+                  body: warp::hyper::body::Bytes| async move {
+                let signature = signature_headers(device, timestamp, signature);
+                let precision = params.get("precision").map_or("ns", String::as_str);
+                let write = influx::write(&DB, &body, precision, signature);
+                match tokio::time::timeout(timeout, write)
+                    .await
+                    .map_err(|_| warp::reject::custom(Timeout))?
+                {
+                    Ok(_) => Ok(StatusCode::NO_CONTENT.into_response()),
+                    Err(e) => match e.downcast_ref::<ApiError>() {
+                        Some(e) if e.code == Code::ValidationFailed => {
+                            Ok(api_error(e, StatusCode::BAD_REQUEST))
+                        }
+                        Some(e) if e.code == Code::Unauthorized => {
+                            Ok(api_error(e, StatusCode::UNAUTHORIZED))
+                        }
+                        _ => Err(warp::reject::custom(ServerError(e))),
+                    },
+                }
+            },
+        );
+
+    let origins = Arc::new(args.cors_origins);
+    let preflight_origins = origins.clone();
+    let preflight = warp::options()
+        .and(warp::header::<String>("origin"))
+        .and(warp::header::optional::<String>(
+            "access-control-request-headers",
+        ))
+        .map(move |origin: String, headers: Option<String>| {
+            if !cors::allow_origin(&preflight_origins, &origin) {
+                return HttpResponse::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(String::new());
             }
-            Ok(warp::reply::with_status(
-                "INTERNAL_SERVER_ERROR".to_string(),
-                StatusCode::INTERNAL_SERVER_ERROR,
-            ))
+            HttpResponse::builder()
+                .status(StatusCode::NO_CONTENT)
+                .header("access-control-allow-origin", origin)
+                .header("access-control-allow-methods", "GET, POST, OPTIONS")
+                .header(
+                    "access-control-allow-headers",
+                    headers.unwrap_or_else(|| "content-type".to_owned()),
+                )
+                .header("access-control-max-age", "600")
+                .body(String::new())
         });
-    warp::serve(routes).run(([0, 0, 0, 0], 8080)).await
+
+    let routes = preflight
+        .or(graphql_ui)
+        .or(graphql_schema)
+        .or(openapi)
+        .or(ha_sensors)
+        .or(ha_sensor)
+        .or(metrics_sensors)
+        .or(metrics_dynamodb)
+        .or(webhook)
+        .or(influx)
+        .or(graphql_post);
+    let routes = routes.recover(|err: Rejection| async move {
+        if let Some(BadRequest(err)) = err.find() {
+            return Ok::<_, Infallible>(
+                warp::reply::with_status(err.to_string(), StatusCode::BAD_REQUEST).into_response(),
+            );
+        }
+        if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+            return Ok(graphql_error(
+                "request body too large",
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ));
+        }
+        if err.find::<warp::reject::LengthRequired>().is_some() {
+            return Ok(graphql_error(
+                "content-length required",
+                StatusCode::LENGTH_REQUIRED,
+            ));
+        }
+        if err.find::<Timeout>().is_some() {
+            return Ok(graphql_error(
+                "request timed out",
+                StatusCode::GATEWAY_TIMEOUT,
+            ));
+        }
+        if err.is_not_found() {
+            return Ok(
+                warp::reply::with_status("NOT_FOUND".to_string(), StatusCode::NOT_FOUND)
+                    .into_response(),
+            );
+        }
+        if let Some(ServerError(e)) = err.find() {
+            log::error!("{:?}", e);
+        }
+        Ok(warp::reply::with_status(
+            "INTERNAL_SERVER_ERROR".to_string(),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response())
+    });
+    let routes = warp::header::optional::<String>("origin")
+        .and(routes)
+        .map(move |origin, reply| with_cors(&origins, origin, reply));
+    let routes = accepts("br")
+        .and(routes.clone())
+        .with(warp::compression::brotli())
+        .or(accepts("gzip")
+            .and(routes.clone())
+            .with(warp::compression::gzip()))
+        .or(routes)
+        .map(vary_encoding);
+
+    let (tx, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        log::info!("shutting down");
+        let _ = tx.send(true);
+    });
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
+    let grace = Duration::from_secs(args.shutdown_grace);
+    let tls = (args.tls_cert, args.tls_key);
+    let server = async {
+        match tls {
+            (Some(cert), Some(key)) => serve_tls(routes, addr, cert, key, shutdown.clone()).await,
+            _ => {
+                let (_, server) = warp::serve(routes)
+                    .bind_with_graceful_shutdown(addr, stopped(shutdown.clone()));
+                server.await
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = server => (),
+        _ = async {
+            stopped(shutdown.clone()).await;
+            tokio::time::sleep(grace).await
+        } => log::warn!("grace period expired, closing remaining connections"),
+    }
 }
@@ -5,15 +5,14 @@ use anyhow::Result;
 use chrono::{DateTime, Utc};
 use lambda_runtime::{handler_fn, Context, Error};
 use once_cell::sync::Lazy;
-use rusoto_core::Region;
-use rusoto_dynamodb::DynamoDbClient;
-use rust_decimal::prelude::*;
-use rust_decimal_macros::*;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use homeapi::dynamodb::Client;
-use homeapi::models::{Device, Electricity, PlaceCondition};
+use homeapi::echonet;
+use homeapi::error::is_conflict;
+use homeapi::models::{Device, Electricity, PlaceCondition, RawData};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Event<T> {
@@ -32,6 +31,8 @@ struct NewestEvents {
 #[derive(Debug, Serialize, Deserialize)]
 struct NatureRemoDevice {
     id: String,
+    firmware_version: Option<String>,
+    serial_number: Option<String>,
 
     newest_events: Option<NewestEvents>,
 }
@@ -63,42 +64,88 @@ static REQWEST: Lazy<reqwest::Client> = Lazy::new(|| {
         .unwrap()
 });
 static NATURE_REMO_TOKEN: Lazy<String> = Lazy::new(|| std::env::var("NATURE_REMO_TOKEN").unwrap());
-static DB: Lazy<Client> = Lazy::new(|| {
-    Client::new(
-        DynamoDbClient::new(Region::default()),
-        std::env::var("TABLE_NAME").unwrap(),
-    )
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+static RAW_DATA_TTL: Lazy<Option<chrono::Duration>> = Lazy::new(|| {
+    std::env::var("RAW_DATA_TTL_DAYS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x > 0)
+        .map(chrono::Duration::days)
 });
 
-fn parse_epc225(i: u32) -> Decimal {
-    if i < 0xA {
-        dec!(1) / Decimal::from_u32(10_u32.pow(i)).unwrap()
-    } else {
-        Decimal::from_u32(10_u32.pow(i - 0x9)).unwrap()
+async fn fetch<T>(source: &str, url: &str) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let timestamp = Utc::now();
+    let body = REQWEST
+        .get(url)
+        .bearer_auth(&*NATURE_REMO_TOKEN)
+        .send()
+        .await?
+        .text()
+        .await?;
+
+    if let Some(ttl) = *RAW_DATA_TTL {
+        let raw = RawData::capture(source, timestamp, body.clone(), Some(ttl));
+        DB.put_item(&raw).await?;
     }
+
+    Ok(serde_json::from_str(&body)?)
+}
+
+// firmware_version looks like "Remo-E-lite/1.3.3" or "Remo/1.0.77-g808448c".
+fn describe(device: &mut Device, entry: &NatureRemoDevice) -> bool {
+    let (model, firmware_version) = match entry.firmware_version.as_deref() {
+        Some(x) => match x.split_once('/') {
+            Some((model, version)) => (Some(model.to_owned()), Some(version.to_owned())),
+            None => (None, Some(x.to_owned())),
+        },
+        None => (None, None),
+    };
+    let manufacturer = Some("Nature".to_owned());
+
+    let changed = device.manufacturer != manufacturer
+        || (model.is_some() && device.model != model)
+        || (firmware_version.is_some() && device.firmware_version != firmware_version)
+        || (entry.serial_number.is_some() && device.serial != entry.serial_number);
+
+    device.manufacturer = manufacturer;
+    device.model = model.or_else(|| device.model.take());
+    device.firmware_version = firmware_version.or_else(|| device.firmware_version.take());
+    device.serial = entry.serial_number.clone().or_else(|| device.serial.take());
+
+    changed
 }
 
 async fn import_devices(devices: &[Device]) -> Result<()> {
     let mut items = Vec::new();
 
-    let entries: Vec<NatureRemoDevice> = REQWEST
-        .get("https://api.nature.global/1/devices")
-        .bearer_auth(&*NATURE_REMO_TOKEN)
-        .send()
-        .await?
-        .json()
-        .await?;
+    let entries: Vec<NatureRemoDevice> =
+        fetch("nature-remo/devices", "https://api.nature.global/1/devices").await?;
 
     for entry in entries.iter() {
-        let place = match devices.iter().find(|x| x.id == entry.id) {
-            Some(device) => device.place.clone(),
+        let mut device = match devices.iter().find(|x| x.id == entry.id) {
+            Some(device) if device.deleted_at.is_some() => continue,
+            Some(device) => device.clone(),
             None => {
                 let mut device = Device::new(entry.id.to_string());
                 device.place = "unknown".to_owned();
-                DB.put_item(&device).await?;
-                device.place.clone()
+                device
             }
         };
+        // Descriptions are refreshed on the next run when the device was
+        // created or edited in the meantime.
+        if describe(&mut device, entry) {
+            let version = device.version;
+            device.version += 1;
+            if let Err(e) = DB.put_item_versioned(&device, version).await {
+                if !is_conflict(&e) {
+                    return Err(e);
+                }
+            }
+        }
+        let place = device.place;
 
         if entry.newest_events.is_none() {
             continue;
@@ -126,6 +173,7 @@ async fn import_devices(devices: &[Device]) -> Result<()> {
                 humidity: newest_events.hu.as_ref().map(|x| x.val),
                 illuminance: newest_events.il.as_ref().map(|x| x.val),
                 motion: newest_events.mo.as_ref().map(|x| x.val),
+                battery_percent: None,
             };
             items.push(entry);
         }
@@ -139,13 +187,11 @@ async fn import_devices(devices: &[Device]) -> Result<()> {
 async fn import_appliances(devices: &[Device]) -> Result<()> {
     let mut items = Vec::new();
 
-    let entries: Vec<NatureRemoAppliance> = REQWEST
-        .get("https://api.nature.global/1/appliances")
-        .bearer_auth(&*NATURE_REMO_TOKEN)
-        .send()
-        .await?
-        .json()
-        .await?;
+    let entries: Vec<NatureRemoAppliance> = fetch(
+        "nature-remo/appliances",
+        "https://api.nature.global/1/appliances",
+    )
+    .await?;
 
     for entry in entries.iter() {
         if let Some(smart_meter) = &entry.smart_meter {
@@ -159,25 +205,20 @@ async fn import_appliances(devices: &[Device]) -> Result<()> {
 
             let device = devices.iter().find(|x| x.id == entry.device.id);
             let place = match device {
+                Some(device) if device.deleted_at.is_some() => continue,
                 Some(device) => device.place.clone(),
                 None => "unknown".into(),
             };
 
-            let coeff: Decimal = Decimal::from_u32(*epcs.get(&211).unwrap_or(&1)).unwrap()
-                * parse_epc225(*epcs.get(&225).unwrap_or(&0));
-            let cumulative_kwh_p =
-                coeff * Decimal::from_u32(*epcs.get(&224).unwrap_or(&0)).unwrap();
-            let cumulative_kwh_n =
-                coeff * Decimal::from_u32(*epcs.get(&227).unwrap_or(&0)).unwrap();
-            let current_w = *epcs.get(&231).unwrap_or(&0);
+            let reading = echonet::meter_reading(&epcs);
 
             items.push(Electricity {
                 id: entry.device.id.to_string(),
                 timestamp,
                 place,
-                cumulative_kwh_p,
-                cumulative_kwh_n,
-                current_w,
+                cumulative_kwh_p: reading.cumulative_kwh_p,
+                cumulative_kwh_n: reading.cumulative_kwh_n,
+                current_w: reading.current_w,
             });
         }
     }
@@ -188,7 +229,9 @@ async fn import_appliances(devices: &[Device]) -> Result<()> {
 }
 
 async fn import() -> Result<()> {
-    let (devices, _) = DB.get_items("DEVICE", None, None, None, None, None).await?;
+    let (devices, _) = DB
+        .get_items("DEVICE", None, Vec::new(), None, None, None, None)
+        .await?;
 
     let (res0, res1) = tokio::join!(import_devices(&devices), import_appliances(&devices));
     res0?;
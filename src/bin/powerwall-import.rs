@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use reqwest::StatusCode;
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use homeapi::dynamodb::Client;
+use homeapi::models::{BatteryState, Device, Electricity, SolarProduction};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Login {
+    token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Meter {
+    last_communication_time: DateTime<Utc>,
+    instant_power: f64,
+    energy_exported: f64,
+    energy_imported: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Aggregates {
+    site: Meter,
+    battery: Meter,
+    load: Meter,
+    solar: Meter,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Soe {
+    percentage: f64,
+}
+
+static REQWEST: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::ClientBuilder::new()
+        .timeout(Duration::from_secs(5))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap()
+});
+static POWERWALL_HOST: Lazy<String> = Lazy::new(|| std::env::var("POWERWALL_HOST").unwrap());
+static POWERWALL_EMAIL: Lazy<String> = Lazy::new(|| std::env::var("POWERWALL_EMAIL").unwrap());
+static POWERWALL_PASSWORD: Lazy<String> =
+    Lazy::new(|| std::env::var("POWERWALL_PASSWORD").unwrap());
+static POWERWALL_ID: Lazy<String> =
+    Lazy::new(|| std::env::var("POWERWALL_ID").unwrap_or_else(|_| "powerwall".to_owned()));
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+
+fn kwh(wh: f64) -> Decimal {
+    Decimal::from_f64(wh / 1000.0).unwrap_or_default()
+}
+
+fn watts(w: f64) -> u32 {
+    w.max(0.0).round() as u32
+}
+
+async fn login() -> Result<String> {
+    let login: Login = REQWEST
+        .post(&format!("https://{}/api/login/Basic", *POWERWALL_HOST))
+        .json(&json!({
+            "username": "customer",
+            "email": *POWERWALL_EMAIL,
+            "password": *POWERWALL_PASSWORD,
+            "force_sm_off": false,
+        }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(login.token)
+}
+
+async fn get<T>(token: &str, path: &str) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let res = REQWEST
+        .get(&format!("https://{}{}", *POWERWALL_HOST, path))
+        .bearer_auth(token)
+        .send()
+        .await?;
+    match res.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(anyhow!("unauthorized")),
+        _ => Ok(res.error_for_status()?.json().await?),
+    }
+}
+
+async fn import(token: &str) -> Result<()> {
+    let aggregates: Aggregates = get(token, "/api/meters/aggregates").await?;
+    let soe: Soe = get(token, "/api/system_status/soe").await?;
+
+    let id = POWERWALL_ID.as_str();
+    let place = match DB.find_item::<Device>("DEVICE", id).await? {
+        Some(device) if device.deleted_at.is_some() => return Ok(()),
+        Some(device) => device.place,
+        None => {
+            let mut device = Device::new(id.to_owned());
+            device.place = "unknown".to_owned();
+            if !DB.put_item_if_absent(&device).await? {
+                // Created in the meantime, possibly with a place.
+                device = DB.get_item("DEVICE", id).await?;
+            }
+            device.place
+        }
+    };
+
+    let site = &aggregates.site;
+    let load = &aggregates.load;
+    DB.put_items(vec![
+        Electricity {
+            id: id.to_owned(),
+            timestamp: site.last_communication_time,
+            place: place.clone(),
+            cumulative_kwh_p: kwh(site.energy_imported),
+            cumulative_kwh_n: kwh(site.energy_exported),
+            current_w: watts(site.instant_power),
+        },
+        Electricity {
+            id: format!("{}-load", id),
+            timestamp: load.last_communication_time,
+            place: place.clone(),
+            cumulative_kwh_p: kwh(load.energy_imported),
+            cumulative_kwh_n: kwh(load.energy_exported),
+            current_w: watts(load.instant_power),
+        },
+    ])
+    .await?;
+
+    let solar = &aggregates.solar;
+    DB.put_item(&SolarProduction {
+        id: id.to_owned(),
+        timestamp: solar.last_communication_time,
+        place: place.clone(),
+        power_w: watts(solar.instant_power),
+        cumulative_kwh: kwh(solar.energy_exported),
+    })
+    .await?;
+
+    let battery = &aggregates.battery;
+    DB.put_item(&BatteryState {
+        id: id.to_owned(),
+        timestamp: battery.last_communication_time,
+        place,
+        percent: soe.percentage,
+        power_w: battery.instant_power.round() as i64,
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    let mut token = None;
+
+    loop {
+        interval.tick().await;
+
+        if token.is_none() {
+            match login().await {
+                Ok(x) => token = Some(x),
+                Err(e) => {
+                    println!("{:?}", e);
+                    continue;
+                }
+            }
+        }
+
+        if let Err(e) = import(token.as_ref().unwrap()).await {
+            println!("{:?}", e);
+            token = None;
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+
+use homeapi::dynamodb::Client;
+use homeapi::home_mode;
+use homeapi::models::{Mode, Person, PresenceEvent};
+
+#[derive(Debug, Deserialize)]
+struct UnifiClient {
+    mac: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnifiResponse {
+    data: Vec<UnifiClient>,
+}
+
+// "arp" reads the ARP table of the host, which works on a Linux router or
+// any machine on the same segment. "unifi" asks a UniFi controller.
+static PRESENCE_SOURCE: Lazy<String> =
+    Lazy::new(|| std::env::var("PRESENCE_SOURCE").unwrap_or_else(|_| "arp".to_owned()));
+static PRESENCE_ARP_PATH: Lazy<String> =
+    Lazy::new(|| std::env::var("PRESENCE_ARP_PATH").unwrap_or_else(|_| "/proc/net/arp".to_owned()));
+static UNIFI_SITE: Lazy<String> =
+    Lazy::new(|| std::env::var("UNIFI_SITE").unwrap_or_else(|_| "default".to_owned()));
+// UniFi OS consoles serve the controller API under /proxy/network.
+static UNIFI_OS: Lazy<bool> = Lazy::new(|| std::env::var("UNIFI_OS").is_ok());
+
+// Settings that can be missing or invalid, read once at startup so that a
+// mistake stops the importer with a message instead of a panic later.
+struct Config {
+    db: Client,
+    http: reqwest::Client,
+    // "name=mac|mac,name=mac", e.g. phones and watches of each person.
+    people: HashMap<String, Vec<String>>,
+    // Phones drop off Wi-Fi while asleep, so a person is away only after none
+    // of their devices has been seen for this long.
+    away: chrono::Duration,
+    interval: Duration,
+    unifi_url: Option<String>,
+}
+
+fn env_or<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(name) {
+        Ok(x) => x
+            .parse()
+            .with_context(|| format!("invalid {}: {}", name, x)),
+        Err(_) => Ok(default),
+    }
+}
+
+impl Config {
+    fn from_env() -> Result<Self> {
+        let people = std::env::var("PRESENCE_PEOPLE")
+            .context("PRESENCE_PEOPLE is not set")?
+            .split(',')
+            .filter_map(|x| x.split_once('='))
+            .map(|(name, macs)| {
+                let macs = macs.split('|').map(|x| x.trim().to_lowercase()).collect();
+                (name.trim().to_owned(), macs)
+            })
+            .collect();
+        // UniFi controllers usually serve a self-signed certificate, which is
+        // only accepted with UNIFI_INSECURE set.
+        let http = reqwest::ClientBuilder::new()
+            .timeout(Duration::from_secs(5))
+            .danger_accept_invalid_certs(std::env::var("UNIFI_INSECURE").is_ok())
+            .build()
+            .context("cannot create the HTTP client")?;
+
+        Ok(Self {
+            db: Client::from_env().context("cannot open the storage")?,
+            http,
+            people,
+            away: chrono::Duration::minutes(env_or("PRESENCE_AWAY_MINUTES", 10)?),
+            interval: Duration::from_secs(env_or("PRESENCE_INTERVAL", 60)?),
+            unifi_url: std::env::var("UNIFI_URL").ok(),
+        })
+    }
+}
+
+// Complete entries only; incomplete ones are hosts that did not answer.
+fn arp() -> Result<HashSet<String>> {
+    let table = std::fs::read_to_string(&*PRESENCE_ARP_PATH)?;
+    Ok(table
+        .lines()
+        .skip(1)
+        .map(|x| x.split_whitespace().collect::<Vec<_>>())
+        .filter(|x| x.len() >= 4 && x[2] == "0x2")
+        .map(|x| x[3].to_lowercase())
+        .collect())
+}
+
+async fn unifi(config: &Config) -> Result<HashSet<String>> {
+    let base = config
+        .unifi_url
+        .as_deref()
+        .context("UNIFI_URL is not set")?
+        .trim_end_matches('/');
+    let (login, api) = if *UNIFI_OS {
+        (
+            format!("{}/api/auth/login", base),
+            format!("{}/proxy/network", base),
+        )
+    } else {
+        (format!("{}/api/login", base), base.to_owned())
+    };
+    let credentials = json!({
+        "username": std::env::var("UNIFI_USERNAME").context("UNIFI_USERNAME is not set")?,
+        "password": std::env::var("UNIFI_PASSWORD").context("UNIFI_PASSWORD is not set")?,
+    });
+
+    let res = config
+        .http
+        .post(&login)
+        .json(&credentials)
+        .send()
+        .await?
+        .error_for_status()?;
+    let cookie = res
+        .headers()
+        .get_all("set-cookie")
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .filter_map(|x| x.split(';').next())
+        .collect::<Vec<_>>()
+        .join("; ");
+    if cookie.is_empty() {
+        return Err(anyhow!("UniFi login returned no session"));
+    }
+
+    let res: UnifiResponse = config
+        .http
+        .get(&format!("{}/api/s/{}/stat/sta", api, *UNIFI_SITE))
+        .header("cookie", cookie)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(res.data.into_iter().map(|x| x.mac.to_lowercase()).collect())
+}
+
+// Switches between home and away as people come and go. A vacation set
+// by hand is left alone.
+async fn update_mode(db: &Client, anyone_home: bool) -> Result<()> {
+    home_mode::update(db, |home| {
+        home.mode = match (home.mode, anyone_home) {
+            (Mode::Away, true) => Mode::Home,
+            (Mode::Home, false) => Mode::Away,
+            _ => return false,
+        };
+        true
+    })
+    .await?;
+    Ok(())
+}
+
+async fn import(config: &Config, seen: &mut HashMap<String, DateTime<Utc>>) -> Result<()> {
+    let now = Utc::now();
+    let macs = match PRESENCE_SOURCE.as_str() {
+        "arp" => arp()?,
+        "unifi" => unifi(config).await?,
+        x => return Err(anyhow!("unknown presence source: {}", x)),
+    };
+    for (name, devices) in config.people.iter() {
+        if devices.iter().any(|x| macs.contains(x)) {
+            seen.insert(name.clone(), now);
+        }
+    }
+
+    let db = &config.db;
+    let people: Vec<Person> = db.get_range("PERSON", None).await?;
+    let mut changed = false;
+    let mut anyone_home = false;
+
+    for name in config.people.keys() {
+        let home = seen.get(name).map_or(false, |x| now - *x <= config.away);
+        anyone_home |= home;
+        if people.iter().any(|x| &x.name == name && x.home == home) {
+            continue;
+        }
+
+        db.put_item(&PresenceEvent::new(name.clone(), now, home))
+            .await?;
+        db.put_item(&Person::new(name.clone(), home, now)).await?;
+        println!("{} {}", name, if home { "arrived" } else { "left" });
+        changed = true;
+    }
+
+    if changed {
+        update_mode(db, anyone_home).await?;
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = Config::from_env()?;
+    // People stored as home count as just seen, so that a restart does not
+    // mark them away before the first scan finds them.
+    let started = Utc::now();
+    let mut seen: HashMap<String, DateTime<Utc>> = config
+        .db
+        .get_range::<Person>("PERSON", None)
+        .await?
+        .into_iter()
+        .filter(|x| x.home)
+        .map(|x| (x.name, started))
+        .collect();
+    let mut interval = tokio::time::interval(config.interval);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = import(&config, &mut seen).await {
+            println!("{:?}", e);
+        }
+    }
+}
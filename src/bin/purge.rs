@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use lambda_runtime::{handler_fn, Context, Error};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::Value;
+
+use homeapi::dynamodb::{attr_string, Client, Filter};
+
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+// Deleted devices and places can be restored for this long.
+static TOMBSTONE_GRACE_DAYS: Lazy<i64> = Lazy::new(|| {
+    std::env::var("TOMBSTONE_GRACE_DAYS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(30)
+});
+
+#[derive(Debug, Deserialize)]
+struct Tombstoned {
+    sk: String,
+    deleted_at: String,
+}
+
+// Only the configuration item goes; telemetry stays under the device's id.
+// An item is deleted only if it still has the tombstone that was read, so
+// one restored meanwhile is kept.
+async fn purge(pk: &str, cutoff: DateTime<Utc>) -> Result<usize> {
+    let items: Vec<Tombstoned> = DB
+        .get_filtered_range(pk, None, vec![Filter::Exists("deleted_at".to_owned())])
+        .await?;
+    let mut purged = 0;
+
+    for item in items {
+        if item.deleted_at.parse::<DateTime<Utc>>()? >= cutoff {
+            continue;
+        }
+        let mut params = HashMap::new();
+        params.insert(":deleted_at".to_owned(), attr_string(item.deleted_at));
+        if DB
+            .delete_item_if(pk, &item.sk, "deleted_at = :deleted_at", params)
+            .await?
+        {
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
+
+async fn purge_all() -> Result<()> {
+    let cutoff = Utc::now() - Duration::days(*TOMBSTONE_GRACE_DAYS);
+    let devices = purge("DEVICE", cutoff).await?;
+    let places = purge("PLACE", cutoff).await?;
+
+    println!(
+        "{} device(s) and {} place(s) deleted before {:?} purged",
+        devices, places, cutoff
+    );
+    Ok(())
+}
+
+async fn handler(_: Value, _: Context) -> Result<(), Error> {
+    purge_all().await.map_err(|e| {
+        println!("{:?}", e);
+        Error::from("error")
+    })?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    lambda_runtime::run(handler_fn(handler)).await?;
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use anyhow::Result;
+use chrono::Utc;
+use lambda_runtime::{handler_fn, Context, Error};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use homeapi::dynamodb::Client;
+use homeapi::notify;
+use homeapi::report::{self, Period};
+
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+// A notification channel as accepted by alert rules, e.g. "slack:<url>".
+static REPORT_CHANNEL: Lazy<String> =
+    Lazy::new(|| std::env::var("REPORT_CHANNEL").unwrap_or_else(|_| "log".to_owned()));
+
+// Scheduled with {"period": "daily"} or {"period": "weekly"}; the report
+// covers the period that ends when it runs.
+async fn send(event: Value) -> Result<()> {
+    let period = Period::parse(event["period"].as_str().unwrap_or("daily"))?;
+    let report = report::compose(&DB, period, Utc::now()).await?;
+    notify::send(&REPORT_CHANNEL, &report.title(), &report.text()).await
+}
+
+async fn handler(event: Value, _: Context) -> Result<(), Error> {
+    send(event).await.map_err(|e| {
+        println!("{:?}", e);
+        Error::from("error")
+    })?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    lambda_runtime::run(handler_fn(handler)).await?;
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use lambda_runtime::{handler_fn, Context, Error};
+use once_cell::sync::Lazy;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::Value;
+
+use homeapi::billing;
+use homeapi::dynamodb::{Client, Condition};
+use homeapi::models::{live, Device, DynamoItem, FinalElectricity};
+
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+
+// Electricity and PlaceCondition share the TS# prefix, so only the meter
+// fields are picked up and the rest of the item is ignored.
+#[derive(Debug, Deserialize)]
+struct Reading {
+    sk: String,
+    place: Option<String>,
+    cumulative_kwh_p: Option<Decimal>,
+    cumulative_kwh_n: Option<Decimal>,
+}
+
+// Writes the final reading at the start of the device's current billing
+// period.
+async fn rollup_device(device: &Device, now: DateTime<Utc>) -> Result<bool> {
+    let (boundary, _) = billing::billing_period(&billing::anchor(device), now);
+    let sk = format!("{}{:?}", FinalElectricity::sk_prefix(), boundary);
+    if DB
+        .get_item::<FinalElectricity>(&device.id, &sk)
+        .await
+        .is_ok()
+    {
+        return Ok(false);
+    }
+
+    let reading: Option<Reading> = DB
+        .get_last_item(
+            &device.id,
+            Condition::Between("TS#".to_owned(), format!("TS#{:?}", boundary)),
+        )
+        .await?;
+    let reading = match reading {
+        Some(x) => x,
+        None => return Ok(false),
+    };
+    let (cumulative_kwh_p, cumulative_kwh_n) =
+        match (reading.cumulative_kwh_p, reading.cumulative_kwh_n) {
+            (Some(p), Some(n)) => (p, n),
+            _ => return Ok(false),
+        };
+
+    let fin = FinalElectricity {
+        id: device.id.clone(),
+        timestamp: boundary,
+        place: reading.place.unwrap_or_else(|| device.place.clone()),
+        cumulative_kwh_p,
+        cumulative_kwh_n,
+    };
+    DB.put_item(&fin).await?;
+    println!("{}: {} (from {})", fin.id, fin.sk(), reading.sk);
+
+    Ok(true)
+}
+
+async fn rollup() -> Result<()> {
+    let now = Utc::now();
+    let devices: Vec<Device> = live(DB.get_range("DEVICE", None).await?);
+    let mut count = 0;
+
+    for device in devices.iter() {
+        if rollup_device(device, now).await? {
+            count += 1;
+        }
+    }
+
+    println!("{} final reading(s) written", count);
+    Ok(())
+}
+
+async fn handler(_: Value, _: Context) -> Result<(), Error> {
+    rollup().await.map_err(|e| {
+        println!("{:?}", e);
+        Error::from("error")
+    })?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    lambda_runtime::run(handler_fn(handler)).await?;
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use once_cell::sync::Lazy;
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use homeapi::dynamodb::Client;
+use homeapi::models::{ApplianceState, Device, Electricity};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShellyDeviceInfo {
+    id: String,
+    model: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShellyEnergy {
+    total: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShellySwitch {
+    id: u32,
+    output: bool,
+    apower: Option<f64>,
+    aenergy: Option<ShellyEnergy>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShellyEm1 {
+    id: u32,
+    act_power: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShellyEm1Data {
+    id: u32,
+    total_act_energy: f64,
+    total_act_ret_energy: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShellyEm {
+    id: u32,
+    total_act_power: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShellyEmData {
+    id: u32,
+    total_act: f64,
+    total_act_ret: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShellySys {
+    unixtime: Option<i64>,
+}
+
+static REQWEST: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::ClientBuilder::new()
+        .timeout(Duration::from_secs(2))
+        .build()
+        .unwrap()
+});
+static SHELLY_HOSTS: Lazy<Vec<String>> = Lazy::new(|| {
+    std::env::var("SHELLY_HOSTS")
+        .unwrap()
+        .split(',')
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .collect()
+});
+static SHELLY_INTERVAL: Lazy<u64> = Lazy::new(|| {
+    std::env::var("SHELLY_INTERVAL")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(60)
+});
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+
+async fn rpc<T>(host: &str, method: &str) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    Ok(REQWEST
+        .get(&format!("http://{}/rpc/{}", host, method))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+fn component<T>(status: &HashMap<String, Value>, name: &str) -> Result<Option<T>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    match status.get(name) {
+        Some(value) => Ok(Some(serde_json::from_value(value.clone())?)),
+        None => Ok(None),
+    }
+}
+
+fn components<T>(status: &HashMap<String, Value>, kind: &str) -> Result<Vec<T>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let prefix = format!("{}:", kind);
+    status
+        .iter()
+        .filter(|(k, _)| k.starts_with(&prefix))
+        .map(|(_, v)| Ok(serde_json::from_value(v.clone())?))
+        .collect()
+}
+
+fn channel_id(device_id: &str, channel: u32) -> String {
+    if channel == 0 {
+        device_id.to_owned()
+    } else {
+        format!("{}-{}", device_id, channel)
+    }
+}
+
+fn kwh(wh: f64) -> Decimal {
+    Decimal::from_f64(wh / 1000.0).unwrap_or_default()
+}
+
+fn watts(w: f64) -> u32 {
+    w.max(0.0).round() as u32
+}
+
+async fn import_host(host: &str, devices: &[Device]) -> Result<()> {
+    let info: ShellyDeviceInfo = rpc(host, "Shelly.GetDeviceInfo").await?;
+    let status: HashMap<String, Value> = rpc(host, "Shelly.GetStatus").await?;
+
+    let place = match devices.iter().find(|x| x.id == info.id) {
+        Some(device) if device.deleted_at.is_some() => return Ok(()),
+        Some(device) => device.place.clone(),
+        None => {
+            let mut device = Device::new(info.id.to_string());
+            device.place = "unknown".to_owned();
+            if !DB.put_item_if_absent(&device).await? {
+                // Created in the meantime, possibly with a place.
+                device = DB.get_item("DEVICE", &info.id).await?;
+            }
+            device.place
+        }
+    };
+
+    let timestamp = match component::<ShellySys>(&status, "sys")?.and_then(|x| x.unixtime) {
+        Some(unixtime) => Utc.timestamp(unixtime, 0),
+        None => Utc::now(),
+    };
+
+    let mut electricity = Vec::new();
+    let mut states = Vec::new();
+
+    for switch in components::<ShellySwitch>(&status, "switch")? {
+        let id = channel_id(&info.id, switch.id);
+        states.push(ApplianceState {
+            id: id.clone(),
+            timestamp,
+            place: place.clone(),
+            on: switch.output,
+        });
+        if let Some(aenergy) = switch.aenergy {
+            electricity.push(Electricity {
+                id,
+                timestamp,
+                place: place.clone(),
+                cumulative_kwh_p: kwh(aenergy.total),
+                cumulative_kwh_n: Decimal::zero(),
+                current_w: watts(switch.apower.unwrap_or(0.0)),
+            });
+        }
+    }
+
+    let em1s = components::<ShellyEm1>(&status, "em1")?;
+    for data in components::<ShellyEm1Data>(&status, "em1data")? {
+        let power = em1s
+            .iter()
+            .find(|x| x.id == data.id)
+            .map(|x| x.act_power)
+            .unwrap_or(0.0);
+        electricity.push(Electricity {
+            id: channel_id(&info.id, data.id),
+            timestamp,
+            place: place.clone(),
+            cumulative_kwh_p: kwh(data.total_act_energy),
+            cumulative_kwh_n: kwh(data.total_act_ret_energy),
+            current_w: watts(power),
+        });
+    }
+
+    let ems = components::<ShellyEm>(&status, "em")?;
+    for data in components::<ShellyEmData>(&status, "emdata")? {
+        let power = ems
+            .iter()
+            .find(|x| x.id == data.id)
+            .map(|x| x.total_act_power)
+            .unwrap_or(0.0);
+        electricity.push(Electricity {
+            id: channel_id(&info.id, data.id),
+            timestamp,
+            place: place.clone(),
+            cumulative_kwh_p: kwh(data.total_act),
+            cumulative_kwh_n: kwh(data.total_act_ret),
+            current_w: watts(power),
+        });
+    }
+
+    DB.put_items(electricity).await?;
+    DB.put_items(states).await?;
+
+    Ok(())
+}
+
+async fn import() -> Result<()> {
+    let (devices, _) = DB
+        .get_items("DEVICE", None, Vec::new(), None, None, None, None)
+        .await?;
+
+    for host in SHELLY_HOSTS.iter() {
+        if let Err(e) = import_host(host, &devices).await {
+            println!("{}: {:?}", host, e);
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(*SHELLY_INTERVAL));
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = import().await {
+            println!("{:?}", e);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use lambda_runtime::{handler_fn, Context, Error};
+use once_cell::sync::Lazy;
+use rust_decimal::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use homeapi::dynamodb::Client;
+use homeapi::models::{Device, RawData, SolarProduction};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SolarEdgeSite {
+    id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SolarEdgeSites {
+    site: Vec<SolarEdgeSite>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SolarEdgeSiteList {
+    sites: SolarEdgeSites,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SolarEdgeEnergy {
+    energy: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SolarEdgePower {
+    power: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SolarEdgeOverview {
+    life_time_data: SolarEdgeEnergy,
+    current_power: SolarEdgePower,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SolarEdgeOverviewResponse {
+    overview: SolarEdgeOverview,
+}
+
+static REQWEST: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::ClientBuilder::new()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap()
+});
+static SOLAREDGE_API_KEY: Lazy<String> = Lazy::new(|| std::env::var("SOLAREDGE_API_KEY").unwrap());
+static SOLAREDGE_SITE_IDS: Lazy<Option<String>> =
+    Lazy::new(|| std::env::var("SOLAREDGE_SITE_IDS").ok());
+static DB: Lazy<Client> = Lazy::new(|| Client::from_env().unwrap());
+static RAW_DATA_TTL: Lazy<Option<chrono::Duration>> = Lazy::new(|| {
+    std::env::var("RAW_DATA_TTL_DAYS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x > 0)
+        .map(chrono::Duration::days)
+});
+
+async fn fetch<T>(source: &str, path: &str) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let timestamp = Utc::now();
+    let body = REQWEST
+        .get(&format!("https://monitoringapi.solaredge.com{}", path))
+        .query(&[("api_key", SOLAREDGE_API_KEY.as_str())])
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    if let Some(ttl) = *RAW_DATA_TTL {
+        let raw = RawData::capture(source, timestamp, body.clone(), Some(ttl));
+        DB.put_item(&raw).await?;
+    }
+
+    Ok(serde_json::from_str(&body)?)
+}
+
+async fn site_ids() -> Result<Vec<u64>> {
+    if let Some(ids) = &*SOLAREDGE_SITE_IDS {
+        return ids
+            .split(',')
+            .map(str::trim)
+            .map(|x| {
+                x.parse()
+                    .map_err(|_| anyhow!("invalid SOLAREDGE_SITE_IDS entry: {}", x))
+            })
+            .collect();
+    }
+
+    let list: SolarEdgeSiteList = fetch("solaredge/sites", "/sites/list").await?;
+    Ok(list.sites.site.iter().map(|x| x.id).collect())
+}
+
+async fn import() -> Result<()> {
+    let (devices, _): (Vec<Device>, _) = DB
+        .get_items("DEVICE", None, Vec::new(), None, None, None, None)
+        .await?;
+    let mut items = Vec::new();
+
+    for site_id in site_ids().await? {
+        let timestamp = Utc::now();
+        let res: SolarEdgeOverviewResponse = fetch(
+            &format!("solaredge/site/{}/overview", site_id),
+            &format!("/site/{}/overview", site_id),
+        )
+        .await?;
+
+        let id = format!("solaredge-{}", site_id);
+        let place = match devices.iter().find(|x| x.id == id) {
+            Some(device) if device.deleted_at.is_some() => continue,
+            Some(device) => device.place.clone(),
+            None => {
+                let mut device = Device::new(id.to_string());
+                device.place = "unknown".to_owned();
+                if !DB.put_item_if_absent(&device).await? {
+                    // Created in the meantime, possibly with a place.
+                    device = DB.get_item("DEVICE", &id).await?;
+                }
+                device.place
+            }
+        };
+
+        let overview = res.overview;
+        items.push(SolarProduction {
+            id,
+            timestamp,
+            place,
+            power_w: overview.current_power.power.max(0.0).round() as u32,
+            cumulative_kwh: Decimal::from_f64(overview.life_time_data.energy / 1000.0)
+                .unwrap_or_default(),
+        });
+    }
+
+    DB.put_items(items).await?;
+
+    Ok(())
+}
+
+async fn handler(_: Value, _: Context) -> Result<(), Error> {
+    import().await.map_err(|e| {
+        println!("{:?}", e);
+        Error::from("error")
+    })?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    lambda_runtime::run(handler_fn(handler)).await?;
+    Ok(())
+}
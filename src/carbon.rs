@@ -0,0 +1,63 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rust_decimal::prelude::*;
+
+use crate::aggregation;
+use crate::dynamodb::{Client, Condition};
+use crate::models::{CarbonFootprint, CarbonIntensity};
+
+// Intensity periods are half an hour, so the one covering the start of the
+// query is looked up this far back.
+const PERIOD_MINUTES: i64 = 60;
+
+// Multiplies the energy between each pair of readings by the intensity of
+// the period its midpoint falls in.
+pub async fn footprint(
+    dynamodb: &Client,
+    device: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<CarbonFootprint> {
+    let readings = aggregation::electricity_readings(dynamodb, device, from, to).await?;
+    let since = from - chrono::Duration::minutes(PERIOD_MINUTES);
+    let sk = Condition::Between(format!("TS#{:?}", since), format!("TS#{:?}", to));
+    let intensities: Vec<CarbonIntensity> =
+        dynamodb.get_range("CARBON_INTENSITY", Some(sk)).await?;
+
+    let mut imported_kwh = Decimal::zero();
+    let mut uncovered_kwh = Decimal::zero();
+    let mut kg_co2 = 0.0;
+
+    for pair in readings.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        let span = (b.timestamp - a.timestamp).num_milliseconds() as f64;
+        let (start, end) = (a.timestamp.max(from), b.timestamp.min(to));
+        let overlap = (end - start).num_milliseconds() as f64;
+        if span <= 0.0 || overlap <= 0.0 {
+            continue;
+        }
+
+        let ratio = Decimal::from_f64(overlap / span).unwrap_or_default();
+        let kwh =
+            aggregation::delta(a.cumulative_kwh_p, b.cumulative_kwh_p).unwrap_or_default() * ratio;
+        imported_kwh += kwh;
+
+        let midpoint = start + (end - start) / 2;
+        match intensities
+            .iter()
+            .find(|x| x.from <= midpoint && midpoint < x.to)
+        {
+            Some(x) => kg_co2 += kwh.to_f64().unwrap_or(0.0) * x.g_per_kwh / 1000.0,
+            None => uncovered_kwh += kwh,
+        }
+    }
+
+    let covered = (imported_kwh - uncovered_kwh).to_f64().unwrap_or(0.0);
+    Ok(CarbonFootprint {
+        device: device.to_owned(),
+        imported_kwh: imported_kwh.round_dp(3),
+        kg_co2,
+        uncovered_kwh: uncovered_kwh.round_dp(3),
+        g_per_kwh: Some(kg_co2 * 1000.0 / covered).filter(|_| covered > 0.0),
+    })
+}
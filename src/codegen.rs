@@ -0,0 +1,139 @@
+use anyhow::Result;
+use async_graphql::parser::types::{
+    BaseType, FieldDefinition, InputValueDefinition, Type, TypeKind, TypeSystemDefinition,
+};
+use async_graphql::parser::{parse_schema, Positioned};
+
+const BUILTIN_SCALARS: [(&str, &str); 5] = [
+    ("ID", "string"),
+    ("String", "string"),
+    ("Boolean", "boolean"),
+    ("Int", "number"),
+    ("Float", "number"),
+];
+
+// TypeScript types for the schema in SDL, in the shape graphql-codegen's
+// typescript plugin produces: a Scalars map, one type per GraphQL type and
+// an Args type per field argument list.
+pub fn typescript(sdl: &str) -> Result<String> {
+    let document = parse_schema(sdl)?;
+    let mut out = String::from(
+        "// Generated by homeapi-codegen. Do not edit.\n\n\
+         export type Maybe<T> = T | null;\n\n",
+    );
+    let mut scalars: Vec<(String, &str)> = BUILTIN_SCALARS
+        .iter()
+        .map(|(name, ty)| (name.to_string(), *ty))
+        .collect();
+    let mut types = String::new();
+
+    for definition in document.definitions {
+        let definition = match definition {
+            TypeSystemDefinition::Type(x) => x.node,
+            _ => continue,
+        };
+        let name = definition.name.node.to_string();
+        if name.starts_with("__") {
+            continue;
+        }
+
+        match definition.kind {
+            TypeKind::Scalar => {
+                if !scalars.iter().any(|x| x.0 == name) {
+                    types += &format!("export type {} = Scalars[\"{}\"];\n\n", name, name);
+                    // Uploads are sent as multipart files.
+                    let ty = if name == "Upload" { "File" } else { "unknown" };
+                    scalars.push((name, ty));
+                }
+            }
+            TypeKind::Enum(x) => {
+                let values = x
+                    .values
+                    .iter()
+                    .map(|x| format!("\"{}\"", x.node.value.node))
+                    .collect::<Vec<_>>();
+                types += &format!("export type {} = {};\n\n", name, values.join(" | "));
+            }
+            TypeKind::Union(x) => {
+                let members = x
+                    .members
+                    .iter()
+                    .map(|x| x.node.to_string())
+                    .collect::<Vec<_>>();
+                types += &format!("export type {} = {};\n\n", name, members.join(" | "));
+            }
+            TypeKind::Object(x) => {
+                types += &object(&name, &x.fields);
+            }
+            TypeKind::Interface(x) => {
+                types += &object(&name, &x.fields);
+            }
+            TypeKind::InputObject(x) => {
+                types += &inputs(&name, &x.fields);
+            }
+        }
+    }
+
+    out += "export type Scalars = {\n";
+    for (name, ty) in scalars {
+        out += &format!("  {}: {};\n", name, ty);
+    }
+    out += "};\n\n";
+    out += &types;
+    Ok(out.trim_end().to_owned() + "\n")
+}
+
+fn ts_type(ty: &Type) -> String {
+    let base = match &ty.base {
+        BaseType::Named(x) if BUILTIN_SCALARS.iter().any(|y| y.0 == x.as_str()) => {
+            format!("Scalars[\"{}\"]", x)
+        }
+        BaseType::Named(x) => x.to_string(),
+        BaseType::List(x) => format!("Array<{}>", ts_type(x)),
+    };
+    if ty.nullable {
+        format!("Maybe<{}>", base)
+    } else {
+        base
+    }
+}
+
+fn object(name: &str, fields: &[Positioned<FieldDefinition>]) -> String {
+    let mut out = format!("export type {} = {{\n", name);
+    let mut args = String::new();
+
+    for field in fields {
+        let field = &field.node;
+        out += &format!("  {}: {};\n", field.name.node, ts_type(&field.ty.node));
+        if !field.arguments.is_empty() {
+            let args_name = format!("{}{}Args", name, capitalize(field.name.node.as_str()));
+            args += &inputs(&args_name, &field.arguments);
+        }
+    }
+
+    out + "};\n\n" + &args
+}
+
+// Nullable inputs may be left out.
+fn inputs(name: &str, fields: &[Positioned<InputValueDefinition>]) -> String {
+    let mut out = format!("export type {} = {{\n", name);
+    for field in fields {
+        let field = &field.node;
+        let optional = if field.ty.node.nullable { "?" } else { "" };
+        out += &format!(
+            "  {}{}: {};\n",
+            field.name.node,
+            optional,
+            ts_type(&field.ty.node)
+        );
+    }
+    out + "};\n\n"
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(x) => x.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,47 @@
+// Psychrometric values derived from temperature (Celsius) and relative
+// humidity (percent).
+
+// Magnus formula with the Sonntag constants, good to about 0.1C over
+// -45C..60C.
+pub fn dew_point(temperature: f64, humidity: f64) -> Option<f64> {
+    if humidity <= 0.0 || humidity > 100.0 {
+        return None;
+    }
+    let (a, b) = (17.62, 243.12);
+    let gamma = (humidity / 100.0).ln() + a * temperature / (b + temperature);
+    Some(b * gamma / (a - gamma))
+}
+
+// The NWS heat index, in Celsius. Below about 27C it is close to the air
+// temperature, so the simple formula is used there as the NWS does.
+pub fn heat_index(temperature: f64, humidity: f64) -> f64 {
+    let t = temperature * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+
+    let hi = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut hi = -42.379 + 2.04901523 * t + 10.14333127 * rh
+            - 0.22475541 * t * rh
+            - 0.00683783 * t * t
+            - 0.05481717 * rh * rh
+            + 0.00122874 * t * t * rh
+            + 0.00085282 * t * rh * rh
+            - 0.00000199 * t * t * rh * rh;
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            hi -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            hi += (rh - 85.0) / 10.0 * (87.0 - t) / 5.0;
+        }
+        hi
+    };
+
+    (hi - 32.0) * 5.0 / 9.0
+}
+
+// The temperature-humidity index used in Japan. Around 60-75 is
+// comfortable; above 80 most people feel uncomfortably hot.
+pub fn discomfort_index(temperature: f64, humidity: f64) -> f64 {
+    0.81 * temperature + 0.01 * humidity * (0.99 * temperature - 14.3) + 46.3
+}
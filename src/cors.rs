@@ -0,0 +1,35 @@
+use std::str::FromStr;
+
+use regex::Regex;
+
+#[derive(Clone, Debug)]
+pub struct OriginPattern(Regex);
+
+impl FromStr for OriginPattern {
+    type Err = regex::Error;
+
+    // "*" allows any origin, "~<regex>" is matched as a regular expression and
+    // anything else is an exact origin in which "*" stands for one or more
+    // host labels, e.g. "https://*.example.com".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = match s {
+            "*" => ".*".to_owned(),
+            _ => match s.strip_prefix('~') {
+                Some(x) => x.to_owned(),
+                None => regex::escape(s).replace(r"\*", "[^/:]+"),
+            },
+        };
+
+        Ok(Self(Regex::new(&format!("^(?:{})$", pattern))?))
+    }
+}
+
+impl OriginPattern {
+    pub fn matches(&self, origin: &str) -> bool {
+        self.0.is_match(origin)
+    }
+}
+
+pub fn allow_origin(patterns: &[OriginPattern], origin: &str) -> bool {
+    patterns.iter().any(|x| x.matches(origin))
+}
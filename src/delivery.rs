@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+
+use crate::automation;
+use crate::dynamodb::{Client, Condition, Filter};
+use crate::models::{DeliveryStatus, EventKind, WebhookDelivery, WebhookTarget};
+use crate::signature;
+
+// Attempts are spaced 30s, 1m, 2m, ... apart, so the last of them happens
+// about an hour after the event.
+const MAX_ATTEMPTS: u32 = 8;
+const BACKOFF_SECONDS: i64 = 30;
+// Pending deliveries are looked up within this window; it is longer than
+// the whole backoff schedule.
+const RETRY_WINDOW_HOURS: i64 = 24;
+static LOG_DAYS: Lazy<i64> = Lazy::new(|| {
+    std::env::var("WEBHOOK_LOG_DAYS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(30)
+});
+
+// Redirects are not followed, as they could lead past check_url.
+static REQWEST: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::ClientBuilder::new()
+        .timeout(std::time::Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+});
+
+// Sends one attempt and records its outcome on the delivery. Requests carry
+// the same x-timestamp and x-signature headers as signed ingestion.
+async fn attempt(target: &WebhookTarget, delivery: &mut WebhookDelivery, now: DateTime<Utc>) {
+    let timestamp = now.timestamp();
+    let signature = signature::sign(&target.secret, timestamp, delivery.payload.as_bytes());
+    // Also checked here for targets stored before the check existed.
+    let response = match automation::check_url(&target.url) {
+        Ok(()) => REQWEST
+            .post(&target.url)
+            .header("content-type", "application/json")
+            .header("x-event", format!("{:?}", delivery.event))
+            .header("x-delivery-id", delivery.id.as_str())
+            .header("x-timestamp", timestamp)
+            .header("x-signature", signature)
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+
+    delivery.attempts += 1;
+    delivery.last_attempt_at = Some(now);
+    let error = match response {
+        Ok(response) => {
+            delivery.response_status = Some(response.status().as_u16());
+            match response.error_for_status() {
+                Ok(_) => None,
+                Err(e) => Some(e.to_string()),
+            }
+        }
+        Err(e) => Some(e),
+    };
+
+    match error {
+        None => {
+            delivery.status = DeliveryStatus::Delivered;
+            delivery.next_attempt_at = None;
+            delivery.error = None;
+        }
+        Some(e) if delivery.attempts >= MAX_ATTEMPTS => {
+            delivery.status = DeliveryStatus::Failed;
+            delivery.next_attempt_at = None;
+            delivery.error = Some(e);
+        }
+        Some(e) => {
+            let backoff = BACKOFF_SECONDS << (delivery.attempts - 1);
+            delivery.next_attempt_at = Some(now + Duration::seconds(backoff));
+            delivery.error = Some(e);
+        }
+    }
+}
+
+// Queues the event for every target that accepts it and makes the first
+// attempt right away. Failed attempts are left to retry().
+pub async fn publish(dynamodb: &Client, event: EventKind, data: Value) -> Result<usize> {
+    let targets: Vec<WebhookTarget> = dynamodb.get_range("WEBHOOK_TARGET", None).await?;
+    let now = Utc::now();
+    let payload = json!({
+        "event": format!("{:?}", event),
+        "timestamp": format!("{:?}", now),
+        "data": data,
+    })
+    .to_string();
+    let mut count = 0;
+
+    for target in targets.iter().filter(|x| x.accepts(event)) {
+        let expires_at = now + Duration::days(*LOG_DAYS);
+        let mut delivery = WebhookDelivery::new(target, event, payload.clone(), now, expires_at);
+        attempt(target, &mut delivery, now).await;
+        dynamodb.put_item(&delivery).await?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// Retries pending deliveries that are due. Meant to be called from the
+// periodic sweep.
+pub async fn retry(dynamodb: &Client, now: DateTime<Utc>) -> Result<usize> {
+    let from = now - Duration::hours(RETRY_WINDOW_HOURS);
+    let sk = Condition::Ge(format!("{:?}", from));
+    let filter = vec![Filter::Exists("next_attempt_at".to_owned())];
+    let deliveries: Vec<WebhookDelivery> = dynamodb
+        .get_filtered_range("WEBHOOK_DELIVERY", Some(sk), filter)
+        .await?;
+    let targets: HashMap<String, WebhookTarget> = dynamodb
+        .get_range::<WebhookTarget>("WEBHOOK_TARGET", None)
+        .await?
+        .into_iter()
+        .map(|x| (x.id.clone(), x))
+        .collect();
+    let mut count = 0;
+
+    for mut delivery in deliveries {
+        if delivery.next_attempt_at.map_or(true, |x| x > now) {
+            continue;
+        }
+        match targets.get(&delivery.target).filter(|x| x.enabled) {
+            Some(target) => attempt(target, &mut delivery, now).await,
+            None => {
+                delivery.status = DeliveryStatus::Failed;
+                delivery.next_attempt_at = None;
+                delivery.error = Some("target removed or disabled".to_owned());
+            }
+        }
+        dynamodb.put_item(&delivery).await?;
+        count += 1;
+    }
+
+    Ok(count)
+}
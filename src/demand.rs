@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use async_graphql::Object;
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use once_cell::sync::Lazy;
+use rust_decimal::prelude::*;
+
+use crate::aggregation;
+use crate::dynamodb::{attr_number, Client};
+use crate::models::{Electricity, PeakDemand};
+
+// Monthly peaks are tracked on write only when this is set. Demand tariffs
+// commonly bill the highest 15- or 30-minute average.
+static WINDOW_MINUTES: Lazy<Option<i64>> = Lazy::new(|| {
+    std::env::var("PEAK_DEMAND_WINDOW_MINUTES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x > 0)
+});
+// Months of the tracked peaks start at local midnight in this zone.
+static TIMEZONE: Lazy<Tz> = Lazy::new(|| {
+    std::env::var("PEAK_DEMAND_TIMEZONE")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(Tz::UTC)
+});
+
+pub struct DemandPeak {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub average_w: f64,
+}
+
+#[Object]
+impl DemandPeak {
+    async fn start(&self) -> String {
+        format!("{:?}", &self.start)
+    }
+
+    async fn end(&self) -> String {
+        format!("{:?}", &self.end)
+    }
+
+    async fn average_w(&self) -> String {
+        format!("{:.1}", &self.average_w)
+    }
+}
+
+// Imported energy since the first reading, in kWh, at each reading.
+fn energy(readings: &[Electricity]) -> Vec<(DateTime<Utc>, f64)> {
+    let mut total = Decimal::zero();
+    let mut points = Vec::new();
+
+    for (i, x) in readings.iter().enumerate() {
+        if i > 0 {
+            let kwh = aggregation::delta(readings[i - 1].cumulative_kwh_p, x.cumulative_kwh_p);
+            total += kwh.unwrap_or_default();
+        }
+        points.push((x.timestamp, total.to_f64().unwrap_or(0.0)));
+    }
+
+    points
+}
+
+// Linear interpolation between the readings around `t`.
+fn energy_at(points: &[(DateTime<Utc>, f64)], t: DateTime<Utc>) -> Option<f64> {
+    let i = points.partition_point(|x| x.0 < t);
+    let b = points.get(i)?;
+    if b.0 == t {
+        return Some(b.1);
+    }
+    let a = points.get(i.checked_sub(1)?)?;
+    let span = (b.0 - a.0).num_milliseconds() as f64;
+    let ratio = (t - a.0).num_milliseconds() as f64 / span;
+    Some(a.1 + (b.1 - a.1) * ratio)
+}
+
+// The window within `from`..`to` with the highest average import. Energy is
+// interpolated linearly between readings, so the maximum is found with a
+// window starting or ending at a reading.
+pub fn peak(
+    readings: &[Electricity],
+    window: Duration,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Option<DemandPeak> {
+    let points = energy(readings);
+    let hours = window.num_milliseconds() as f64 / 3_600_000.0;
+    if hours <= 0.0 {
+        return None;
+    }
+
+    let ends = points
+        .iter()
+        .map(|x| x.0)
+        .chain(points.iter().map(|x| x.0 + window));
+    let mut best: Option<DemandPeak> = None;
+
+    for end in ends {
+        let start = end - window;
+        if start < from || end > to {
+            continue;
+        }
+        let kwh = match (energy_at(&points, start), energy_at(&points, end)) {
+            (Some(a), Some(b)) => b - a,
+            _ => continue,
+        };
+
+        let average_w = kwh / hours * 1000.0;
+        if best.as_ref().map_or(true, |x| average_w > x.average_w) {
+            best = Some(DemandPeak {
+                start,
+                end,
+                average_w,
+            });
+        }
+    }
+
+    best
+}
+
+fn month(t: DateTime<Utc>) -> String {
+    t.with_timezone(&*TIMEZONE).format("%Y-%m").to_string()
+}
+
+// Updates the monthly peaks of each device with the windows ending at the new
+// readings. Readings are grouped by month, so a batch spanning the turn of a
+// month updates both. Does nothing unless PEAK_DEMAND_WINDOW_MINUTES is set.
+pub async fn track(dynamodb: &Client, records: &[Electricity]) -> Result<()> {
+    let window = match *WINDOW_MINUTES {
+        Some(x) => Duration::minutes(x),
+        None => return Ok(()),
+    };
+    let mut ranges: BTreeMap<(&str, String), (DateTime<Utc>, DateTime<Utc>)> = BTreeMap::new();
+    for x in records {
+        ranges
+            .entry((x.id.as_str(), month(x.timestamp)))
+            .and_modify(|r| *r = (r.0.min(x.timestamp), r.1.max(x.timestamp)))
+            .or_insert((x.timestamp, x.timestamp));
+    }
+
+    for ((device, month), (first, last)) in ranges {
+        // Windows end between the first and the last reading, so within the
+        // month.
+        let from = first - window;
+        let readings = aggregation::electricity_readings(dynamodb, device, from, last).await?;
+        let highest = match peak(&readings, window, from, last) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        // Only a higher peak replaces the stored one, also when another write
+        // updates the month at the same time.
+        let item = PeakDemand::new(
+            device.to_owned(),
+            month,
+            window.num_minutes(),
+            highest.start,
+            highest.end,
+            highest.average_w,
+        );
+        let mut params = HashMap::new();
+        params.insert(":average_w".to_owned(), attr_number(highest.average_w));
+        dynamodb
+            .put_item_if(
+                &item,
+                "attribute_not_exists(sk) OR average_w < :average_w",
+                params,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
@@ -1,12 +1,25 @@
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use moka::sync::Cache;
+use once_cell::sync::Lazy;
+use rusoto_core::RusotoError;
 use rusoto_dynamodb::{
-    AttributeValue, BatchWriteItemInput, DynamoDb, DynamoDbClient, GetItemInput, PutItemInput,
-    PutRequest, QueryInput, WriteRequest,
+    AttributeValue, BatchGetItemInput, BatchWriteItemInput, DeleteItemError, DeleteItemInput,
+    DeleteRequest, DynamoDbClient, GetItemInput, KeysAndAttributes, PutItemError, PutItemInput,
+    PutRequest, QueryInput, UpdateItemError, UpdateItemInput, WriteRequest,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::error::{ApiError, Code};
+use crate::metrics::{self, MeteredStorage};
+use crate::models;
+use crate::storage::{self, Storage};
+
+#[derive(Clone)]
 pub enum Condition {
     BeginsWith(String),
     Between(String, String),
@@ -17,27 +30,388 @@ pub enum Condition {
     Lt(String),
 }
 
+// Non-key conditions applied after the key condition. DynamoDB evaluates them
+// after Limit, so a filtered page may hold fewer items than requested.
+#[derive(Clone)]
+pub enum Filter {
+    Contains(String, AttributeValue),
+    Eq(String, AttributeValue),
+    Exists(String),
+    Ge(String, AttributeValue),
+    Gt(String, AttributeValue),
+    NotExists(String),
+}
+
+pub type TableResolver = Arc<dyn Fn(&str, &str) -> Option<String> + Send + Sync>;
+
+#[derive(Clone)]
 pub struct Client {
-    pub dynamodb: DynamoDbClient,
+    pub dynamodb: Arc<dyn Storage>,
     pub table: String,
+    resolver: Option<TableResolver>,
+    items: Cache<(String, String), HashMap<String, AttributeValue>>,
+}
+
+// Parses TABLE_ROUTES such as "pk:RAW_DATA=homeapi-raw,sk:TS#=homeapi-telemetry",
+// which sends items whose pk equals or whose sk starts with the given value to
+// another table.
+pub fn table_routes(spec: &str) -> Result<TableResolver> {
+    let routes = spec
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .map(|route| {
+            let (key, table) = route
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid table route: {}", route))?;
+            match key.split_once(':') {
+                Some((kind @ "pk", value)) | Some((kind @ "sk", value)) => {
+                    Ok((kind == "pk", value.to_owned(), table.to_owned()))
+                }
+                _ => Err(anyhow!("invalid table route: {}", route)),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Arc::new(move |pk: &str, sk: &str| {
+        routes
+            .iter()
+            .find(|(is_pk, value, _)| {
+                if *is_pk {
+                    pk == value
+                } else {
+                    sk.starts_with(value.as_str())
+                }
+            })
+            .map(|(_, _, table)| table.clone())
+    }))
+}
+
+pub fn attr_number<T: ToString>(val: T) -> AttributeValue {
+    AttributeValue {
+        n: Some(val.to_string()),
+        ..Default::default()
+    }
 }
 
-fn attr_string(val: String) -> AttributeValue {
+pub fn attr_string(val: String) -> AttributeValue {
     AttributeValue {
         s: Some(val),
         ..Default::default()
     }
 }
 
+fn key_condition(pk: &str, sk: Option<Condition>) -> (String, HashMap<String, AttributeValue>) {
+    key_condition_on(("pk", "sk"), pk, sk)
+}
+
+fn key_condition_on(
+    (pk_name, sk_name): (&str, &str),
+    pk: &str,
+    sk: Option<Condition>,
+) -> (String, HashMap<String, AttributeValue>) {
+    let mut key_condition_expression = format!("{} = :pk", pk_name);
+    let mut params = HashMap::new();
+    params.insert(":pk".to_owned(), attr_string(pk.to_owned()));
+
+    let condition = match sk {
+        Some(Condition::BeginsWith(a)) => {
+            params.insert(":a".to_owned(), attr_string(a));
+            Some(format!("BEGINS_WITH({}, :a)", sk_name))
+        }
+        Some(Condition::Between(a, b)) => {
+            params.insert(":a".to_owned(), attr_string(a));
+            params.insert(":b".to_owned(), attr_string(b));
+            Some(format!("{} BETWEEN :a AND :b", sk_name))
+        }
+        Some(Condition::Eq(a)) => {
+            params.insert(":a".to_owned(), attr_string(a));
+            Some(format!("{} = :a", sk_name))
+        }
+        Some(Condition::Ge(a)) => {
+            params.insert(":a".to_owned(), attr_string(a));
+            Some(format!("{} >= :a", sk_name))
+        }
+        Some(Condition::Gt(a)) => {
+            params.insert(":a".to_owned(), attr_string(a));
+            Some(format!("{} > :a", sk_name))
+        }
+        Some(Condition::Le(a)) => {
+            params.insert(":a".to_owned(), attr_string(a));
+            Some(format!("{} <= :a", sk_name))
+        }
+        Some(Condition::Lt(a)) => {
+            params.insert(":a".to_owned(), attr_string(a));
+            Some(format!("{} < :a", sk_name))
+        }
+        None => None,
+    };
+    if let Some(condition) = condition {
+        key_condition_expression.push_str(" AND ");
+        key_condition_expression.push_str(&condition);
+    }
+
+    (key_condition_expression, params)
+}
+
+fn filter_expression(
+    filter: Vec<Filter>,
+    params: &mut HashMap<String, AttributeValue>,
+) -> (Option<String>, Option<HashMap<String, String>>) {
+    if filter.is_empty() {
+        return (None, None);
+    }
+
+    let mut names = HashMap::new();
+    let mut conditions = Vec::new();
+    for (i, filter) in filter.into_iter().enumerate() {
+        let (name, value) = (format!("#f{}", i), format!(":f{}", i));
+        let (attr, condition) = match filter {
+            Filter::Contains(attr, a) => {
+                params.insert(value.clone(), a);
+                (attr, format!("contains({}, {})", name, value))
+            }
+            Filter::Eq(attr, a) => {
+                params.insert(value.clone(), a);
+                (attr, format!("{} = {}", name, value))
+            }
+            Filter::Exists(attr) => (attr, format!("attribute_exists({})", name)),
+            Filter::Ge(attr, a) => {
+                params.insert(value.clone(), a);
+                (attr, format!("{} >= {}", name, value))
+            }
+            Filter::Gt(attr, a) => {
+                params.insert(value.clone(), a);
+                (attr, format!("{} > {}", name, value))
+            }
+            Filter::NotExists(attr) => (attr, format!("attribute_not_exists({})", name)),
+        };
+        names.insert(name, attr);
+        conditions.push(condition);
+    }
+
+    (Some(conditions.join(" AND ")), Some(names))
+}
+
+// Names attributes through placeholders, since many attribute names are
+// DynamoDB reserved words.
+fn projection_expression(
+    attributes: &[&str],
+    names: &mut Option<HashMap<String, String>>,
+) -> String {
+    let names = names.get_or_insert_with(HashMap::new);
+    attributes
+        .iter()
+        .enumerate()
+        .map(|(i, attr)| {
+            let name = format!("#p{}", i);
+            names.insert(name.clone(), (*attr).to_owned());
+            name
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn page_order<D>(result: &mut Vec<D>, first: Option<usize>, last: Option<usize>) {
+    match (first, last) {
+        (None, Some(_)) => result.reverse(),
+        (Some(first), Some(last)) if first > last => {
+            result.reverse();
+            result.truncate(last);
+            result.reverse()
+        }
+        _ => (),
+    }
+}
+
+fn sk_hint(sk: &Option<Condition>) -> &str {
+    match sk {
+        Some(Condition::BeginsWith(a))
+        | Some(Condition::Between(a, _))
+        | Some(Condition::Eq(a))
+        | Some(Condition::Ge(a))
+        | Some(Condition::Gt(a))
+        | Some(Condition::Le(a))
+        | Some(Condition::Lt(a)) => a,
+        None => "",
+    }
+}
+
+// Select=COUNT still reads every matching item, so totals are reused for a
+// short while instead of being recounted on every page.
+const COUNT_CACHE_SECONDS: u64 = 30;
+
+static COUNTS: Lazy<Mutex<HashMap<String, (Instant, i64)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn count_key(
+    table: &str,
+    expression: &str,
+    names: &Option<HashMap<String, String>>,
+    params: &HashMap<String, AttributeValue>,
+) -> String {
+    let mut values = params
+        .iter()
+        .map(|(k, v)| format!("{}={:?}", k, v))
+        .chain(names.iter().flatten().map(|(k, v)| format!("{}={}", k, v)))
+        .collect::<Vec<_>>();
+    values.sort();
+    format!("{}|{}|{}", table, expression, values.join("|"))
+}
+
+// DEVICE and PLACE items are read on nearly every request and rarely change,
+// so point lookups of them are cached. Writes through the client drop the
+// cached item; other processes may see the old one until it expires.
+const ITEM_CACHE_SECONDS: u64 = 60;
+const ITEM_CACHE_CAPACITY: usize = 10000;
+
+fn cacheable(pk: &str) -> bool {
+    pk == "DEVICE" || pk == "PLACE"
+}
+
+// BatchGetItem takes at most 100 keys. Keys DynamoDB leaves unprocessed are
+// retried with exponential backoff.
+const BATCH_GET_SIZE: usize = 100;
+const BATCH_GET_ATTEMPTS: u32 = 6;
+
+// BatchWriteItem takes at most 25 requests. Unprocessed requests are retried
+// the same way, and those still left after the last attempt are counted.
+const BATCH_WRITE_SIZE: usize = 25;
+const BATCH_WRITE_ATTEMPTS: u32 = 6;
+
+fn key_string(item: &HashMap<String, AttributeValue>, key: &str) -> String {
+    item.get(key).and_then(|x| x.s.clone()).unwrap_or_default()
+}
+
+// Device.last_seen_at is only rewritten when it is this much older than the
+// newest telemetry, which keeps the importers from updating it on every write.
+const LAST_SEEN_INTERVAL_MINUTES: i64 = 5;
+
+fn last_seen(items: &[HashMap<String, AttributeValue>]) -> HashMap<String, DateTime<Utc>> {
+    let mut seen: HashMap<String, DateTime<Utc>> = HashMap::new();
+
+    for item in items {
+        let sk = key_string(item, "sk");
+        let timestamp = match models::telemetry_timestamp(&sk).and_then(|x| x.parse().ok()) {
+            Some(x) => x,
+            None => continue,
+        };
+        let last = seen.entry(key_string(item, "pk")).or_insert(timestamp);
+        *last = (*last).max(timestamp);
+    }
+
+    seen
+}
+
+fn index_item(item: &mut HashMap<String, AttributeValue>) {
+    let (pk, sk) = (key_string(item, "pk"), key_string(item, "sk"));
+    if let Some((gsi1pk, gsi1sk)) = models::place_index(&pk, &sk, &key_string(item, "place")) {
+        item.insert("gsi1pk".to_owned(), attr_string(gsi1pk));
+        item.insert("gsi1sk".to_owned(), attr_string(gsi1sk));
+    }
+
+    // Tombstoned devices leave the attention index until they are restored.
+    let needs_attention = if item.contains_key("deleted_at") {
+        String::new()
+    } else {
+        key_string(item, "needs_attention")
+    };
+    if let Some((gsi2pk, gsi2sk)) = models::attention_index(&pk, &sk, &needs_attention) {
+        item.insert("gsi2pk".to_owned(), attr_string(gsi2pk));
+        item.insert("gsi2sk".to_owned(), attr_string(gsi2sk));
+    }
+
+    let fields = [sk, key_string(item, "name"), key_string(item, "notes")];
+    if let Some(search) = models::search_text(&pk, &fields) {
+        item.insert("search".to_owned(), attr_string(search));
+    }
+}
+
 impl Client {
     pub fn new(dynamodb: DynamoDbClient, table: String) -> Self {
-        Self { dynamodb, table }
+        Self::with_storage(Arc::new(dynamodb), table)
+    }
+
+    // STORAGE picks the backend as in storage::open and defaults to DynamoDB.
+    // TABLE_NAME is only required for DynamoDB. TABLE_ROUTES is optional.
+    pub fn from_env() -> Result<Self> {
+        let url = std::env::var("STORAGE").unwrap_or_else(|_| "dynamodb".to_owned());
+        let table = match std::env::var("TABLE_NAME") {
+            Ok(x) => x,
+            Err(_) if url != "dynamodb" => "homeapi".to_owned(),
+            Err(e) => return Err(e.into()),
+        };
+        let client = Self::with_storage(storage::open(&url)?, table);
+        match std::env::var("TABLE_ROUTES") {
+            Ok(x) => Ok(client.with_resolver(table_routes(&x).context("TABLE_ROUTES")?)),
+            Err(_) => Ok(client),
+        }
+    }
+
+    pub fn with_storage(dynamodb: Arc<dyn Storage>, table: String) -> Self {
+        Self {
+            dynamodb: Arc::new(MeteredStorage(dynamodb)),
+            table,
+            resolver: None,
+            items: Cache::builder()
+                .max_capacity(ITEM_CACHE_CAPACITY)
+                .time_to_live(std::time::Duration::from_secs(ITEM_CACHE_SECONDS))
+                .build(),
+        }
+    }
+
+    fn forget(&self, pk: &str, sk: &str) {
+        if cacheable(pk) {
+            self.items.invalidate(&(pk.to_owned(), sk.to_owned()));
+        }
+    }
+
+    pub fn with_resolver(mut self, resolver: TableResolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    pub fn table_for(&self, pk: &str, sk: &str) -> String {
+        self.resolver
+            .as_ref()
+            .and_then(|f| f(pk, sk))
+            .unwrap_or_else(|| self.table.clone())
     }
 
     pub async fn get_item<'de, D>(&self, pk: &str, sk: &str) -> Result<D>
     where
         D: Deserialize<'de>,
     {
+        self.find_item(pk, sk)
+            .await?
+            .ok_or_else(|| ApiError::new(Code::NotFound, "no item").into())
+    }
+
+    pub async fn find_item<'de, D>(&self, pk: &str, sk: &str) -> Result<Option<D>>
+    where
+        D: Deserialize<'de>,
+    {
+        let cache_key = (pk.to_owned(), sk.to_owned());
+        if let Some(item) = self.items.get(&cache_key) {
+            return Ok(Some(serde_dynamodb::from_hashmap(item)?));
+        }
+
+        self.read_item(pk, sk, false).await
+    }
+
+    // Reads past the item cache with a strongly consistent read, for items
+    // that must not be stale, such as a device holding its signing secret.
+    pub async fn find_fresh_item<'de, D>(&self, pk: &str, sk: &str) -> Result<Option<D>>
+    where
+        D: Deserialize<'de>,
+    {
+        self.read_item(pk, sk, true).await
+    }
+
+    async fn read_item<'de, D>(&self, pk: &str, sk: &str, consistent: bool) -> Result<Option<D>>
+    where
+        D: Deserialize<'de>,
+    {
+        let cache_key = (pk.to_owned(), sk.to_owned());
         let key: HashMap<String, AttributeValue> = [
             ("pk".to_owned(), attr_string(pk.to_string())),
             ("sk".to_owned(), attr_string(sk.to_string())),
@@ -47,25 +421,110 @@ impl Client {
         .collect();
 
         let input = GetItemInput {
-            table_name: self.table.clone(),
+            table_name: self.table_for(pk, sk),
             key,
+            consistent_read: Some(true).filter(|_| consistent),
             ..Default::default()
         };
 
-        let result = self
-            .dynamodb
-            .get_item(input)
-            .await?
-            .item
-            .ok_or_else(|| anyhow!("no item"))?;
+        let result = self.dynamodb.get_item(input).await?.item;
+        if let Some(item) = &result {
+            if cacheable(pk) {
+                self.items.insert(cache_key, item.clone());
+            }
+        }
+
+        Ok(result.map(serde_dynamodb::from_hashmap).transpose()?)
+    }
+
+    // Fetches many items with BatchGetItem. The result follows the order of
+    // `keys`, with None for items that do not exist.
+    pub async fn batch_get_items<'de, D>(&self, keys: &[(String, String)]) -> Result<Vec<Option<D>>>
+    where
+        D: Deserialize<'de>,
+    {
+        let mut found: HashMap<(String, String), HashMap<String, AttributeValue>> = HashMap::new();
+        let mut tables: HashMap<String, Vec<HashMap<String, AttributeValue>>> = HashMap::new();
+
+        for (pk, sk) in keys {
+            let cache_key = (pk.clone(), sk.clone());
+            if found.contains_key(&cache_key) {
+                continue;
+            }
+            if let Some(item) = self.items.get(&cache_key) {
+                found.insert(cache_key, item);
+                continue;
+            }
+
+            let key = [
+                ("pk".to_owned(), attr_string(pk.clone())),
+                ("sk".to_owned(), attr_string(sk.clone())),
+            ]
+            .iter()
+            .cloned()
+            .collect();
+            let requests = tables.entry(self.table_for(pk, sk)).or_default();
+            if !requests.contains(&key) {
+                requests.push(key);
+            }
+        }
+
+        for (table, requests) in tables {
+            for chunk in requests.chunks(BATCH_GET_SIZE) {
+                let mut request_items = HashMap::new();
+                request_items.insert(
+                    table.clone(),
+                    KeysAndAttributes {
+                        keys: chunk.to_vec(),
+                        ..Default::default()
+                    },
+                );
+
+                for attempt in 0.. {
+                    if attempt == BATCH_GET_ATTEMPTS {
+                        return Err(anyhow!("batch get: keys left unprocessed in {}", table));
+                    }
+                    if attempt > 0 {
+                        metrics::retry("batch_get_item");
+                        let backoff = std::time::Duration::from_millis(50 << attempt);
+                        tokio::time::sleep(backoff).await;
+                    }
+
+                    let input = BatchGetItemInput {
+                        request_items,
+                        ..Default::default()
+                    };
+                    let output = self.dynamodb.batch_get_item(input).await?;
+                    let items = output.responses.into_iter().flatten().flat_map(|(_, x)| x);
+                    for item in items {
+                        let key = (key_string(&item, "pk"), key_string(&item, "sk"));
+                        if cacheable(&key.0) {
+                            self.items.insert(key.clone(), item.clone());
+                        }
+                        found.insert(key, item);
+                    }
+
+                    request_items = output.unprocessed_keys.unwrap_or_default();
+                    if request_items.is_empty() {
+                        break;
+                    }
+                }
+            }
+        }
 
-        Ok(serde_dynamodb::from_hashmap(result)?)
+        Ok(keys
+            .iter()
+            .map(|x| found.get(x).cloned().map(serde_dynamodb::from_hashmap))
+            .map(Option::transpose)
+            .collect::<Result<Vec<Option<D>>, _>>()?)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn get_items<'de, D>(
         &self,
         pk: &str,
         sk: Option<Condition>,
+        filter: Vec<Filter>,
         after: Option<String>,
         before: Option<String>,
         first: Option<usize>,
@@ -74,42 +533,9 @@ impl Client {
     where
         D: Deserialize<'de>,
     {
-        let mut key_condition_expression = "pk = :pk".to_owned();
-        let mut params = HashMap::new();
-        params.insert(":pk".to_owned(), attr_string(pk.to_owned()));
-
-        match sk {
-            Some(Condition::BeginsWith(a)) => {
-                key_condition_expression.push_str(" AND BEGINS_WITH(sk, :a)");
-                params.insert(":a".to_owned(), attr_string(a));
-            }
-            Some(Condition::Between(a, b)) => {
-                key_condition_expression.push_str(" AND sk BETWEEN :a AND :b");
-                params.insert(":a".to_owned(), attr_string(a));
-                params.insert(":b".to_owned(), attr_string(b));
-            }
-            Some(Condition::Eq(a)) => {
-                key_condition_expression.push_str(" AND sk = :a");
-                params.insert(":a".to_owned(), attr_string(a));
-            }
-            Some(Condition::Ge(a)) => {
-                key_condition_expression.push_str(" AND sk >= :a");
-                params.insert(":a".to_owned(), attr_string(a));
-            }
-            Some(Condition::Gt(a)) => {
-                key_condition_expression.push_str(" AND sk > :a");
-                params.insert(":a".to_owned(), attr_string(a));
-            }
-            Some(Condition::Le(a)) => {
-                key_condition_expression.push_str(" AND sk <= :a");
-                params.insert(":a".to_owned(), attr_string(a));
-            }
-            Some(Condition::Lt(a)) => {
-                key_condition_expression.push_str(" AND sk < :a");
-                params.insert(":a".to_owned(), attr_string(a));
-            }
-            None => (),
-        }
+        let table_name = self.table_for(pk, sk_hint(&sk));
+        let (key_condition_expression, mut params) = key_condition(pk, sk);
+        let (filter_expression, names) = filter_expression(filter, &mut params);
 
         let (scan_index_forward, limit, next_sk) = match (first, last) {
             (None, None) => (None, None, after),
@@ -128,8 +554,10 @@ impl Client {
         });
 
         let query_input = QueryInput {
-            table_name: self.table.clone(),
+            table_name,
             key_condition_expression: Some(key_condition_expression),
+            filter_expression,
+            expression_attribute_names: names,
             expression_attribute_values: Some(params),
             scan_index_forward,
             limit,
@@ -145,34 +573,142 @@ impl Client {
             .items
             .unwrap_or_else(Vec::new)
             .into_iter()
-            .map(|item| serde_dynamodb::from_hashmap(item).unwrap())
-            .collect::<Vec<D>>();
+            .map(serde_dynamodb::from_hashmap)
+            .collect::<Result<Vec<D>, _>>()?;
+
+        page_order(&mut result, first, last);
+
+        Ok((result, next_sk))
+    }
+
+    pub async fn count(&self, pk: &str, sk: Option<Condition>, filter: Vec<Filter>) -> Result<i64> {
+        let table_name = self.table_for(pk, sk_hint(&sk));
+        let (key_condition_expression, mut params) = key_condition(pk, sk);
+        let (filter_expression, names) = filter_expression(filter, &mut params);
+        let key = count_key(
+            &table_name,
+            &format!(
+                "{} {}",
+                key_condition_expression,
+                filter_expression.as_deref().unwrap_or_default()
+            ),
+            &names,
+            &params,
+        );
+        let ttl = std::time::Duration::from_secs(COUNT_CACHE_SECONDS);
 
-        match (first, last) {
-            (None, Some(_)) => result.reverse(),
-            (Some(first), Some(last)) if first > last => {
-                result.reverse();
-                result.truncate(last);
-                result.reverse()
+        if let Some((at, count)) = COUNTS.lock().unwrap().get(&key) {
+            if at.elapsed() < ttl {
+                return Ok(*count);
             }
-            _ => (),
         }
 
-        Ok((result, next_sk))
+        let mut count = 0;
+        let mut exclusive_start_key = None;
+        loop {
+            let query_input = QueryInput {
+                table_name: table_name.clone(),
+                key_condition_expression: Some(key_condition_expression.clone()),
+                filter_expression: filter_expression.clone(),
+                expression_attribute_names: names.clone(),
+                expression_attribute_values: Some(params.clone()),
+                select: Some("COUNT".to_owned()),
+                exclusive_start_key,
+                ..Default::default()
+            };
+
+            let output = self.dynamodb.query(query_input).await?;
+            count += output.count.unwrap_or(0);
+            exclusive_start_key = output.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+
+        let mut counts = COUNTS.lock().unwrap();
+        counts.retain(|_, (at, _)| at.elapsed() < ttl);
+        counts.insert(key, (Instant::now(), count));
+
+        Ok(count)
+    }
+
+    // Queries a GSI whose keys are named "<index>pk" and "<index>sk". The
+    // cursor is the JSON-encoded LastEvaluatedKey since it spans both the
+    // table and the index keys.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn query_index<'de, D>(
+        &self,
+        index: &str,
+        pk: &str,
+        sk: Option<Condition>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<usize>,
+        last: Option<usize>,
+    ) -> Result<(Vec<D>, Option<String>)>
+    where
+        D: Deserialize<'de>,
+    {
+        let table_name = self.table_for(pk, sk_hint(&sk));
+        let keys = (format!("{}pk", index), format!("{}sk", index));
+        let (key_condition_expression, params) = key_condition_on((&keys.0, &keys.1), pk, sk);
+
+        let (scan_index_forward, limit, cursor) = match (first, last) {
+            (None, None) => (None, None, after),
+            (None, Some(last)) => (Some(false), Some(last as i64), before),
+            (Some(first), _) => (None, Some(first as i64), after),
+        };
+
+        let exclusive_start_key = cursor
+            .map(|x| serde_json::from_str::<HashMap<String, String>>(&x))
+            .transpose()?
+            .map(|x| x.into_iter().map(|(k, v)| (k, attr_string(v))).collect());
+
+        let query_input = QueryInput {
+            table_name,
+            index_name: Some(index.to_owned()),
+            key_condition_expression: Some(key_condition_expression),
+            expression_attribute_values: Some(params),
+            scan_index_forward,
+            limit,
+            exclusive_start_key,
+            ..Default::default()
+        };
+
+        let output = self.dynamodb.query(query_input).await?;
+        let cursor = output
+            .last_evaluated_key
+            .map(|x| {
+                let key: HashMap<String, String> = x
+                    .into_iter()
+                    .filter_map(|(k, v)| v.s.map(|v| (k, v)))
+                    .collect();
+                serde_json::to_string(&key)
+            })
+            .transpose()?;
+        let mut result = output
+            .items
+            .unwrap_or_else(Vec::new)
+            .into_iter()
+            .map(serde_dynamodb::from_hashmap)
+            .collect::<Result<Vec<D>, _>>()?;
+
+        page_order(&mut result, first, last);
+
+        Ok((result, cursor))
     }
 
     pub async fn get_all_items<'de, D>(&self, query_input: &mut QueryInput) -> Result<Vec<D>>
     where
         D: Deserialize<'de>,
     {
+        let mut result = Vec::new();
+
         loop {
             let output = self.dynamodb.query(query_input.clone()).await?;
-            let result = output
-                .items
-                .unwrap_or_else(Vec::new)
-                .into_iter()
-                .map(|item| serde_dynamodb::from_hashmap(item).unwrap())
-                .collect::<Vec<D>>();
+            for item in output.items.unwrap_or_else(Vec::new) {
+                result.push(serde_dynamodb::from_hashmap(item)?);
+            }
 
             if output.last_evaluated_key == None {
                 return Ok(result);
@@ -182,26 +718,176 @@ impl Client {
         }
     }
 
+    pub async fn get_range<'de, D>(&self, pk: &str, sk: Option<Condition>) -> Result<Vec<D>>
+    where
+        D: Deserialize<'de>,
+    {
+        self.get_filtered_range(pk, sk, Vec::new()).await
+    }
+
+    pub async fn get_filtered_range<'de, D>(
+        &self,
+        pk: &str,
+        sk: Option<Condition>,
+        filter: Vec<Filter>,
+    ) -> Result<Vec<D>>
+    where
+        D: Deserialize<'de>,
+    {
+        let table_name = self.table_for(pk, sk_hint(&sk));
+        let (key_condition_expression, mut params) = key_condition(pk, sk);
+        let (filter_expression, names) = filter_expression(filter, &mut params);
+        let mut query_input = QueryInput {
+            table_name,
+            key_condition_expression: Some(key_condition_expression),
+            filter_expression,
+            expression_attribute_names: names,
+            expression_attribute_values: Some(params),
+            ..Default::default()
+        };
+
+        self.get_all_items(&mut query_input).await
+    }
+
+    // Reads only the given attributes, which cuts read capacity and payload
+    // when callers need a couple of fields from a long range.
+    pub async fn get_projected_range<'de, D>(
+        &self,
+        pk: &str,
+        sk: Option<Condition>,
+        attributes: &[&str],
+        filter: Vec<Filter>,
+    ) -> Result<Vec<D>>
+    where
+        D: Deserialize<'de>,
+    {
+        let table_name = self.table_for(pk, sk_hint(&sk));
+        let (key_condition_expression, mut params) = key_condition(pk, sk);
+        let (filter_expression, mut names) = filter_expression(filter, &mut params);
+        let projection_expression = projection_expression(attributes, &mut names);
+        let mut query_input = QueryInput {
+            table_name,
+            key_condition_expression: Some(key_condition_expression),
+            filter_expression,
+            projection_expression: Some(projection_expression),
+            expression_attribute_names: names,
+            expression_attribute_values: Some(params),
+            ..Default::default()
+        };
+
+        self.get_all_items(&mut query_input).await
+    }
+
+    pub async fn get_last_item<'de, D>(&self, pk: &str, sk: Condition) -> Result<Option<D>>
+    where
+        D: Deserialize<'de>,
+    {
+        let (mut items, _) = self
+            .get_items(pk, Some(sk), Vec::new(), None, None, None, Some(1))
+            .await?;
+        Ok(items.pop())
+    }
+
+    async fn touch_device(&self, device: &str, timestamp: DateTime<Utc>) -> Result<()> {
+        let key: HashMap<String, AttributeValue> = [
+            ("pk".to_owned(), attr_string("DEVICE".to_owned())),
+            ("sk".to_owned(), attr_string(device.to_owned())),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let limit = timestamp - Duration::minutes(LAST_SEEN_INTERVAL_MINUTES);
+        let params: HashMap<String, AttributeValue> = [
+            (":t".to_owned(), attr_string(format!("{:?}", timestamp))),
+            (":limit".to_owned(), attr_string(format!("{:?}", limit))),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        let input = UpdateItemInput {
+            table_name: self.table_for("DEVICE", device),
+            key,
+            update_expression: Some("SET last_seen_at = :t".to_owned()),
+            condition_expression: Some(
+                "attribute_exists(sk) AND \
+                 (attribute_not_exists(last_seen_at) OR last_seen_at < :limit)"
+                    .to_owned(),
+            ),
+            expression_attribute_values: Some(params),
+            ..Default::default()
+        };
+
+        let result = self.dynamodb.update_item(input).await;
+        self.forget("DEVICE", device);
+        match result {
+            Ok(_) | Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn touch_devices(&self, items: &[HashMap<String, AttributeValue>]) -> Result<()> {
+        for (device, timestamp) in last_seen(items) {
+            self.touch_device(&device, timestamp).await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn batch_put_items(&self, items: Vec<HashMap<String, AttributeValue>>) -> Result<()> {
-        let items = items
-            .into_iter()
-            .map(|item| WriteRequest {
+        self.touch_devices(&items).await?;
+        let mut tables: HashMap<String, Vec<WriteRequest>> = HashMap::new();
+        for mut item in items {
+            index_item(&mut item);
+            let (pk, sk) = (key_string(&item, "pk"), key_string(&item, "sk"));
+            self.forget(&pk, &sk);
+            let table = self.table_for(&pk, &sk);
+            tables.entry(table).or_default().push(WriteRequest {
                 put_request: Some(PutRequest { item }),
                 ..Default::default()
-            })
-            .collect();
+            });
+        }
+
+        for (table, items) in tables {
+            for chunk in items.chunks(BATCH_WRITE_SIZE) {
+                let left = self.batch_write(&table, chunk.to_vec()).await?;
+                if left > 0 {
+                    return Err(anyhow!(
+                        "batch write: {} item(s) left unprocessed in {}",
+                        left,
+                        table
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
 
+    // Returns the number of requests still unprocessed.
+    async fn batch_write(&self, table: &str, requests: Vec<WriteRequest>) -> Result<usize> {
         let mut request_items = HashMap::new();
-        request_items.insert(self.table.clone(), items);
+        request_items.insert(table.to_owned(), requests);
 
-        let input = BatchWriteItemInput {
-            request_items,
-            ..Default::default()
-        };
+        for attempt in 0..BATCH_WRITE_ATTEMPTS {
+            if attempt > 0 {
+                metrics::retry("batch_write_item");
+                let backoff = std::time::Duration::from_millis(50 << attempt);
+                tokio::time::sleep(backoff).await;
+            }
 
-        let _res = self.dynamodb.batch_write_item(input).await?;
+            let input = BatchWriteItemInput {
+                request_items,
+                ..Default::default()
+            };
+            let output = self.dynamodb.batch_write_item(input).await?;
+            request_items = output.unprocessed_items.unwrap_or_default();
+            if request_items.values().all(Vec::is_empty) {
+                return Ok(0);
+            }
+        }
 
-        Ok(())
+        Ok(request_items.values().map(Vec::len).sum())
     }
 
     pub async fn put_items<S>(&self, items: Vec<S>) -> Result<()>
@@ -217,17 +903,254 @@ impl Client {
         Ok(())
     }
 
+    pub async fn delete_item(&self, pk: &str, sk: &str) -> Result<()> {
+        let key: HashMap<String, AttributeValue> = [
+            ("pk".to_owned(), attr_string(pk.to_string())),
+            ("sk".to_owned(), attr_string(sk.to_string())),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        let input = DeleteItemInput {
+            table_name: self.table_for(pk, sk),
+            key,
+            ..Default::default()
+        };
+        let _res = self.dynamodb.delete_item(input).await?;
+        self.forget(pk, sk);
+
+        Ok(())
+    }
+
+    // Returns false without deleting when `condition` does not hold for the
+    // stored item, as put_item_if does for writes.
+    pub async fn delete_item_if(
+        &self,
+        pk: &str,
+        sk: &str,
+        condition: &str,
+        params: HashMap<String, AttributeValue>,
+    ) -> Result<bool> {
+        let key: HashMap<String, AttributeValue> = [
+            ("pk".to_owned(), attr_string(pk.to_string())),
+            ("sk".to_owned(), attr_string(sk.to_string())),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        let input = DeleteItemInput {
+            table_name: self.table_for(pk, sk),
+            key,
+            condition_expression: Some(condition.to_owned()),
+            expression_attribute_values: Some(params).filter(|x| !x.is_empty()),
+            ..Default::default()
+        };
+
+        let result = self.dynamodb.delete_item(input).await;
+        self.forget(pk, sk);
+        match result {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(DeleteItemError::ConditionalCheckFailed(_))) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Deletes the item and returns what was stored, so that only one caller
+    // can ever take a given item.
+    pub async fn take_item<'de, D>(&self, pk: &str, sk: &str) -> Result<Option<D>>
+    where
+        D: Deserialize<'de>,
+    {
+        let key: HashMap<String, AttributeValue> = [
+            ("pk".to_owned(), attr_string(pk.to_string())),
+            ("sk".to_owned(), attr_string(sk.to_string())),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        let input = DeleteItemInput {
+            table_name: self.table_for(pk, sk),
+            key,
+            return_values: Some("ALL_OLD".to_owned()),
+            ..Default::default()
+        };
+
+        let result = self.dynamodb.delete_item(input).await?.attributes;
+        self.forget(pk, sk);
+
+        Ok(result.map(serde_dynamodb::from_hashmap).transpose()?)
+    }
+
+    // Deletes every item in the range one query page at a time, so a large
+    // range never has to be held in memory. Only deletions DynamoDB processed
+    // are counted, and an error says how many were done before it.
+    pub async fn delete_range(&self, pk: &str, sk: Condition) -> Result<usize> {
+        let table_name = self.table_for(pk, sk_hint(&Some(sk.clone())));
+        let (key_condition_expression, params) = key_condition(pk, Some(sk));
+        let mut query_input = QueryInput {
+            table_name: table_name.clone(),
+            key_condition_expression: Some(key_condition_expression),
+            expression_attribute_values: Some(params),
+            projection_expression: Some("pk, sk".to_owned()),
+            ..Default::default()
+        };
+        let mut deleted = 0;
+
+        loop {
+            let output = self.dynamodb.query(query_input.clone()).await?;
+            let keys = output
+                .items
+                .unwrap_or_else(Vec::new)
+                .into_iter()
+                .inspect(|key| self.forget(pk, &key_string(key, "sk")))
+                .map(|key| WriteRequest {
+                    delete_request: Some(DeleteRequest { key }),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>();
+
+            for chunk in keys.chunks(BATCH_WRITE_SIZE) {
+                let left = self
+                    .batch_write(&table_name, chunk.to_vec())
+                    .await
+                    .with_context(|| format!("{}: {} item(s) deleted", pk, deleted))?;
+                deleted += chunk.len() - left;
+                if left > 0 {
+                    return Err(anyhow!(
+                        "{}: {} item(s) deleted, {} left unprocessed",
+                        pk,
+                        deleted,
+                        left
+                    ));
+                }
+            }
+
+            if output.last_evaluated_key == None {
+                return Ok(deleted);
+            }
+
+            query_input.exclusive_start_key = output.last_evaluated_key;
+        }
+    }
+
     pub async fn put_item<S>(&self, item: &S) -> Result<()>
     where
         S: Serialize,
     {
+        let mut item = serde_dynamodb::to_hashmap(item)?;
+        index_item(&mut item);
+        self.touch_devices(std::slice::from_ref(&item)).await?;
+        let (pk, sk) = (key_string(&item, "pk"), key_string(&item, "sk"));
         let item = PutItemInput {
-            item: serde_dynamodb::to_hashmap(item)?,
-            table_name: self.table.clone(),
+            table_name: self.table_for(&pk, &sk),
+            item,
             ..Default::default()
         };
-        let _res = self.dynamodb.put_item(item).await?;
+        let result = self.dynamodb.put_item(item).await;
+        self.forget(&pk, &sk);
+        result?;
 
         Ok(())
     }
+
+    // Writes the item only if the stored version still equals `version`.
+    // Items written before versioning have no version attribute and count
+    // as version 0.
+    pub async fn put_item_versioned<S>(&self, item: &S, version: i64) -> Result<()>
+    where
+        S: Serialize,
+    {
+        let mut item = serde_dynamodb::to_hashmap(item)?;
+        index_item(&mut item);
+        let condition = if version == 0 {
+            "attribute_not_exists(version) OR version = :version"
+        } else {
+            "version = :version"
+        };
+        let mut params = HashMap::new();
+        params.insert(":version".to_owned(), attr_number(version));
+
+        let (pk, sk) = (key_string(&item, "pk"), key_string(&item, "sk"));
+        let input = PutItemInput {
+            table_name: self.table_for(&pk, &sk),
+            item,
+            condition_expression: Some(condition.to_owned()),
+            expression_attribute_values: Some(params),
+            ..Default::default()
+        };
+
+        // A conflict means the cached item was stale as well.
+        let result = self.dynamodb.put_item(input).await;
+        self.forget(&pk, &sk);
+        match result {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {
+                Err(ApiError::new(
+                    Code::Conflict,
+                    "version conflict: the item was modified by someone else",
+                )
+                .into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Returns false without writing when an item with the same key exists.
+    pub async fn put_item_if_absent<S>(&self, item: &S) -> Result<bool>
+    where
+        S: Serialize,
+    {
+        let item = serde_dynamodb::to_hashmap(item)?;
+        self.put_attributes_if(item, "attribute_not_exists(sk)", HashMap::new())
+            .await
+    }
+
+    // Returns false without writing when `condition` does not hold for the
+    // stored item. `params` are the condition's placeholder values, and
+    // `#name` stands for the attribute `name`, as needed for reserved words.
+    pub async fn put_item_if<S>(
+        &self,
+        item: &S,
+        condition: &str,
+        params: HashMap<String, AttributeValue>,
+    ) -> Result<bool>
+    where
+        S: Serialize,
+    {
+        let item = serde_dynamodb::to_hashmap(item)?;
+        self.put_attributes_if(item, condition, params).await
+    }
+
+    pub async fn put_attributes_if(
+        &self,
+        mut item: HashMap<String, AttributeValue>,
+        condition: &str,
+        params: HashMap<String, AttributeValue>,
+    ) -> Result<bool> {
+        index_item(&mut item);
+        let names: HashMap<String, String> = condition
+            .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '#'))
+            .filter_map(|x| Some((x.to_owned(), x.strip_prefix('#')?.to_owned())))
+            .collect();
+        let (pk, sk) = (key_string(&item, "pk"), key_string(&item, "sk"));
+        let input = PutItemInput {
+            table_name: self.table_for(&pk, &sk),
+            item,
+            condition_expression: Some(condition.to_owned()),
+            expression_attribute_names: Some(names).filter(|x| !x.is_empty()),
+            expression_attribute_values: Some(params).filter(|x| !x.is_empty()),
+            ..Default::default()
+        };
+
+        let result = self.dynamodb.put_item(input).await;
+        self.forget(&pk, &sk);
+        match result {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use rust_decimal::prelude::*;
+use rust_decimal_macros::*;
+
+pub const EPC_COEFFICIENT: u32 = 0xD3;
+pub const EPC_CUMULATIVE_NORMAL: u32 = 0xE0;
+pub const EPC_CUMULATIVE_UNIT: u32 = 0xE1;
+pub const EPC_CUMULATIVE_REVERSE: u32 = 0xE3;
+pub const EPC_INSTANTANEOUS_POWER: u32 = 0xE7;
+
+pub const METER_EPCS: [u32; 5] = [
+    EPC_COEFFICIENT,
+    EPC_CUMULATIVE_UNIT,
+    EPC_CUMULATIVE_NORMAL,
+    EPC_CUMULATIVE_REVERSE,
+    EPC_INSTANTANEOUS_POWER,
+];
+
+const EHD: [u8; 2] = [0x10, 0x81];
+const EOJ_CONTROLLER: [u8; 3] = [0x05, 0xFF, 0x01];
+const EOJ_SMART_METER: [u8; 3] = [0x02, 0x88, 0x01];
+const ESV_GET: u8 = 0x62;
+const ESV_GET_RES: u8 = 0x72;
+
+pub struct MeterReading {
+    pub cumulative_kwh_p: Decimal,
+    pub cumulative_kwh_n: Decimal,
+    pub current_w: u32,
+}
+
+pub fn parse_epc225(i: u32) -> Decimal {
+    if i < 0xA {
+        dec!(1) / Decimal::from_u32(10_u32.pow(i)).unwrap()
+    } else {
+        Decimal::from_u32(10_u32.pow(i - 0x9)).unwrap()
+    }
+}
+
+pub fn meter_reading(epcs: &HashMap<u32, u32>) -> MeterReading {
+    let coeff: Decimal = Decimal::from_u32(*epcs.get(&EPC_COEFFICIENT).unwrap_or(&1)).unwrap()
+        * parse_epc225(*epcs.get(&EPC_CUMULATIVE_UNIT).unwrap_or(&0));
+    let cumulative_kwh_p =
+        coeff * Decimal::from_u32(*epcs.get(&EPC_CUMULATIVE_NORMAL).unwrap_or(&0)).unwrap();
+    let cumulative_kwh_n =
+        coeff * Decimal::from_u32(*epcs.get(&EPC_CUMULATIVE_REVERSE).unwrap_or(&0)).unwrap();
+    let current_w = (*epcs.get(&EPC_INSTANTANEOUS_POWER).unwrap_or(&0) as i32).max(0) as u32;
+
+    MeterReading {
+        cumulative_kwh_p,
+        cumulative_kwh_n,
+        current_w,
+    }
+}
+
+pub fn get_request(tid: u16, epcs: &[u32]) -> Vec<u8> {
+    let mut frame = Vec::new();
+    frame.extend_from_slice(&EHD);
+    frame.extend_from_slice(&tid.to_be_bytes());
+    frame.extend_from_slice(&EOJ_CONTROLLER);
+    frame.extend_from_slice(&EOJ_SMART_METER);
+    frame.push(ESV_GET);
+    frame.push(epcs.len() as u8);
+    for epc in epcs {
+        frame.push(*epc as u8);
+        frame.push(0);
+    }
+    frame
+}
+
+pub fn parse_get_response(tid: u16, frame: &[u8]) -> Result<HashMap<u32, u32>> {
+    if frame.len() < 12 || frame[0..2] != EHD {
+        return Err(anyhow!("not an ECHONET Lite frame"));
+    }
+    if u16::from_be_bytes([frame[2], frame[3]]) != tid {
+        return Err(anyhow!("transaction id mismatch"));
+    }
+    if frame[4..7] != EOJ_SMART_METER || frame[10] != ESV_GET_RES {
+        return Err(anyhow!("unexpected response: ESV {:#04x}", frame[10]));
+    }
+
+    let mut props = HashMap::new();
+    let mut rest = &frame[12..];
+    for _ in 0..frame[11] {
+        if rest.len() < 2 || rest.len() < 2 + rest[1] as usize {
+            return Err(anyhow!("truncated property"));
+        }
+        let (epc, pdc) = (rest[0] as u32, rest[1] as usize);
+        let val = rest[2..2 + pdc]
+            .iter()
+            .fold(0_u32, |acc, x| (acc << 8) | *x as u32);
+        props.insert(epc, val);
+        rest = &rest[2 + pdc..];
+    }
+
+    Ok(props)
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+
+use async_graphql::{Error, ErrorExtensions};
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{GetItemError, PutItemError, QueryError};
+
+use crate::request_id;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Code {
+    NotFound,
+    Unauthorized,
+    Conflict,
+    ValidationFailed,
+    Throttled,
+    Internal,
+}
+
+impl Code {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::NotFound => "NOT_FOUND",
+            Code::Unauthorized => "UNAUTHORIZED",
+            Code::Conflict => "CONFLICT",
+            Code::ValidationFailed => "VALIDATION_FAILED",
+            Code::Throttled => "THROTTLED",
+            Code::Internal => "INTERNAL",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub code: Code,
+    pub message: String,
+    pub field: Option<String>,
+}
+
+impl ApiError {
+    pub fn new<S: Into<String>>(code: Code, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            field: None,
+        }
+    }
+
+    pub fn invalid<S: Into<String>>(field: &str, message: S) -> Self {
+        Self {
+            code: Code::ValidationFailed,
+            message: format!("{}: {}", field, message.into()),
+            field: Some(field.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ErrorExtensions for ApiError {
+    fn extend(&self) -> Error {
+        Error::new(self.message.clone()).extend_with(|_, e| {
+            e.set("code", self.code.as_str());
+            if let Some(field) = &self.field {
+                e.set("field", field.as_str());
+            }
+        })
+    }
+}
+
+pub fn validation<E: fmt::Display>(e: E) -> Error {
+    ApiError::new(Code::ValidationFailed, e.to_string()).extend()
+}
+
+// True when a versioned put lost to a concurrent write.
+pub fn is_conflict(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ApiError>()
+        .map_or(false, |x| x.code == Code::Conflict)
+}
+
+fn throttled(e: &anyhow::Error) -> bool {
+    if let Some(RusotoError::Service(e)) = e.downcast_ref::<RusotoError<QueryError>>() {
+        return matches!(
+            e,
+            QueryError::ProvisionedThroughputExceeded(_) | QueryError::RequestLimitExceeded(_)
+        );
+    }
+    if let Some(RusotoError::Service(e)) = e.downcast_ref::<RusotoError<GetItemError>>() {
+        return matches!(
+            e,
+            GetItemError::ProvisionedThroughputExceeded(_) | GetItemError::RequestLimitExceeded(_)
+        );
+    }
+    if let Some(RusotoError::Service(e)) = e.downcast_ref::<RusotoError<PutItemError>>() {
+        return matches!(
+            e,
+            PutItemError::ProvisionedThroughputExceeded(_) | PutItemError::RequestLimitExceeded(_)
+        );
+    }
+
+    false
+}
+
+// Only ApiError messages reach clients. Anything else is logged and reported
+// as INTERNAL so that table names and AWS details are not exposed.
+pub fn api_error(e: anyhow::Error) -> Error {
+    if let Some(e) = e.downcast_ref::<ApiError>() {
+        return e.extend();
+    }
+    if throttled(&e) {
+        return ApiError::new(Code::Throttled, "request throttled, retry later").extend();
+    }
+
+    match request_id::current() {
+        Some(id) => println!("request {}: {:?}", id, e),
+        None => println!("{:?}", e),
+    }
+    ApiError::new(Code::Internal, "internal error").extend()
+}
@@ -0,0 +1,78 @@
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use rusoto_core::Region;
+use rusoto_firehose::{KinesisFirehose, KinesisFirehoseClient, PutRecordBatchInput, Record};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+// Firehose accepts at most 500 records per PutRecordBatch call.
+const BATCH_SIZE: usize = 500;
+const QUEUE_SIZE: usize = 10000;
+
+// Accepted telemetry is sent to FIREHOSE_STREAM when it is set. Records are
+// queued and sent in the background, and dropped if the queue is full, so a
+// slow or failing stream never delays ingestion.
+static QUEUE: Lazy<Option<mpsc::Sender<Value>>> = Lazy::new(|| {
+    let stream = std::env::var("FIREHOSE_STREAM").ok()?;
+    let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+    tokio::spawn(deliver(stream, rx));
+    Some(tx)
+});
+
+async fn deliver(stream: String, mut rx: mpsc::Receiver<Value>) {
+    let firehose = KinesisFirehoseClient::new(Region::default());
+
+    while let Some(record) = rx.recv().await {
+        let mut records = vec![record];
+        while records.len() < BATCH_SIZE {
+            match rx.try_recv() {
+                Ok(x) => records.push(x),
+                Err(_) => break,
+            }
+        }
+
+        let input = PutRecordBatchInput {
+            delivery_stream_name: stream.clone(),
+            records: records
+                .iter()
+                .map(|x| Record {
+                    data: Bytes::from(format!("{}\n", x)),
+                })
+                .collect(),
+        };
+        match firehose.put_record_batch(input).await {
+            Ok(output) if output.failed_put_count > 0 => println!(
+                "firehose: {} of {} records failed",
+                output.failed_put_count,
+                records.len()
+            ),
+            Ok(_) => {}
+            Err(e) => println!("firehose: {:?}", e),
+        }
+    }
+}
+
+pub fn forward<S: Serialize>(kind: &str, records: &[S]) {
+    let queue = match QUEUE.as_ref() {
+        Some(x) => x,
+        None => return,
+    };
+
+    let mut dropped = 0;
+    for record in records {
+        let mut value = match serde_json::to_value(record) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if let Value::Object(x) = &mut value {
+            x.insert("kind".to_owned(), Value::from(kind));
+        }
+        if queue.try_send(value).is_err() {
+            dropped += 1;
+        }
+    }
+    if dropped > 0 {
+        println!("firehose: queue full, dropped {} records", dropped);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::dynamodb::{Client, Condition};
+use crate::models::{live, Device, Place};
+
+// homeapi has no users, so every request is for the same agent user.
+static AGENT_USER_ID: Lazy<String> =
+    Lazy::new(|| std::env::var("GOOGLE_AGENT_USER_ID").unwrap_or_else(|_| "homeapi".to_owned()));
+
+#[derive(Debug, Deserialize)]
+struct Reading {
+    temperature: Option<f64>,
+    humidity: Option<i64>,
+}
+
+async fn latest(dynamodb: &Client, device: &str) -> Result<Option<Reading>> {
+    dynamodb
+        .get_last_item(device, Condition::BeginsWith("TS#".to_owned()))
+        .await
+}
+
+// Sensors are exposed with query-only traits; Google has no power meter
+// trait, so meters are not synced.
+fn traits(reading: &Reading) -> (Vec<&'static str>, Map<String, Value>) {
+    let mut traits = Vec::new();
+    let mut attributes = Map::new();
+    if reading.temperature.is_some() {
+        traits.push("action.devices.traits.TemperatureControl");
+        attributes.insert("queryOnlyTemperatureControl".to_owned(), json!(true));
+        attributes.insert("temperatureUnitForUX".to_owned(), json!("C"));
+        attributes.insert(
+            "temperatureRange".to_owned(),
+            json!({ "minThresholdCelsius": -40, "maxThresholdCelsius": 60 }),
+        );
+    }
+    if reading.humidity.is_some() {
+        traits.push("action.devices.traits.HumiditySetting");
+        attributes.insert("queryOnlyHumiditySetting".to_owned(), json!(true));
+    }
+    (traits, attributes)
+}
+
+async fn sync(dynamodb: &Client) -> Result<Value> {
+    let devices: Vec<Device> = live(dynamodb.get_range("DEVICE", None).await?);
+    let places: HashMap<String, String> = dynamodb
+        .get_range::<Place>("PLACE", None)
+        .await?
+        .into_iter()
+        .map(|x| (x.id, x.name))
+        .collect();
+    let mut synced = Vec::new();
+
+    for device in devices {
+        let reading = match latest(dynamodb, &device.id).await? {
+            Some(x) => x,
+            None => continue,
+        };
+        let (traits, attributes) = traits(&reading);
+        if traits.is_empty() {
+            continue;
+        }
+
+        let room = places.get(&device.place).filter(|x| !x.is_empty());
+        let mut x = json!({
+            "id": device.id,
+            "type": "action.devices.types.SENSOR",
+            "traits": traits,
+            "name": { "name": room.unwrap_or(&device.id) },
+            "willReportState": false,
+            "attributes": attributes,
+            "deviceInfo": {
+                "manufacturer": device.manufacturer.as_deref().unwrap_or("homeapi"),
+                "model": device.model.as_deref().unwrap_or("sensor"),
+                "swVersion": device.firmware_version.as_deref().unwrap_or(""),
+            },
+        });
+        if let Some(room) = room {
+            x["roomHint"] = json!(room);
+        }
+        synced.push(x);
+    }
+
+    Ok(json!({ "agentUserId": *AGENT_USER_ID, "devices": synced }))
+}
+
+async fn state(dynamodb: &Client, id: &str) -> Result<Value> {
+    let device: Option<Device> = dynamodb.find_item("DEVICE", id).await?;
+    let device = match device.filter(|x| x.deleted_at.is_none()) {
+        Some(x) => x,
+        None => return Ok(json!({ "status": "ERROR", "errorCode": "deviceNotFound" })),
+    };
+    let online = device.needs_attention.as_deref() != Some("offline");
+    let reading = match latest(dynamodb, id).await? {
+        Some(x) if online => x,
+        _ => return Ok(json!({ "online": false, "status": "OFFLINE" })),
+    };
+
+    let mut state = json!({ "online": true, "status": "SUCCESS" });
+    if let Some(x) = reading.temperature {
+        state["temperatureAmbientCelsius"] = json!(x);
+    }
+    if let Some(x) = reading.humidity {
+        state["humidityAmbientPercent"] = json!(x);
+    }
+    Ok(state)
+}
+
+async fn query(dynamodb: &Client, input: &Value) -> Result<Value> {
+    let mut states = Map::new();
+    let devices = input["payload"]["devices"].as_array().cloned();
+
+    for device in devices.unwrap_or_default() {
+        if let Some(id) = device["id"].as_str() {
+            states.insert(id.to_owned(), state(dynamodb, id).await?);
+        }
+    }
+
+    Ok(json!({ "devices": states }))
+}
+
+// Every device is query-only, so all commands fail.
+fn execute(input: &Value) -> Value {
+    let ids = input["payload"]["commands"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|x| x["devices"].as_array().cloned().unwrap_or_default())
+        .filter_map(|x| x["id"].as_str().map(ToOwned::to_owned))
+        .collect::<Vec<_>>();
+
+    json!({
+        "commands": [{
+            "ids": ids,
+            "status": "ERROR",
+            "errorCode": "functionNotSupported",
+        }]
+    })
+}
+
+// Handles a Smart Home fulfillment request and returns the response body.
+pub async fn fulfill(dynamodb: &Client, request: &Value) -> Result<Value> {
+    let input = &request["inputs"][0];
+    let payload = match input["intent"].as_str().unwrap_or("") {
+        "action.devices.SYNC" => sync(dynamodb).await?,
+        "action.devices.QUERY" => query(dynamodb, input).await?,
+        "action.devices.EXECUTE" => execute(input),
+        "action.devices.DISCONNECT" => return Ok(json!({})),
+        _ => json!({ "errorCode": "notSupported" }),
+    };
+
+    Ok(json!({ "requestId": request["requestId"], "payload": payload }))
+}
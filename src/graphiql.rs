@@ -0,0 +1,5 @@
+pub fn graphiql_source(endpoint: &str, subscription_endpoint: &str) -> String {
+    include_str!("graphiql.html")
+        .replace("__ENDPOINT__", endpoint)
+        .replace("__SUBSCRIPTION_ENDPOINT__", subscription_endpoint)
+}
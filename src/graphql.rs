@@ -1,17 +1,58 @@
+use std::convert::TryFrom;
+
 use async_graphql::connection::{query, Connection, Edge, EmptyFields};
-use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Result, Schema};
+use async_graphql::{
+    Context, EmptySubscription, ErrorExtensions, Object, Result, Schema, SchemaBuilder,
+    SimpleObject, Upload,
+};
 use chrono::{DateTime, Duration, TimeZone, Utc};
-use serde::Deserialize;
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
-use crate::dynamodb::{Client, Condition};
-use crate::models::{Device, DynamoItem, Electricity, FinalElectricity, PlaceCondition};
+use crate::aggregation::{
+    self, EnergyInterval, EnergySummary, Fill, Resolution, SolarAnalytics, UsageForecast,
+};
+use crate::automation;
+use crate::backup;
+use crate::billing;
+use crate::carbon;
+use crate::demand::{self, DemandPeak};
+use crate::dynamodb::{attr_number, attr_string, Client, Condition, Filter};
+use crate::error::{api_error, validation, ApiError, Code};
+use crate::health::{self, DeviceBattery};
+use crate::history;
+use crate::home_mode;
+use crate::models::{
+    Alert, AlertRule, AlertRuleInput, ApplianceState, Automation, AutomationInput, Backup,
+    BatteryState, BillingAnchor, BillingAnchorInput, BillingStatement, CarbonFootprint,
+    CarbonIntensity, CreatedWebhookTarget, DeliveryStatus, Device, DeviceCredentials, DeviceField,
+    DeviceFilter, DynamoItem, Electricity, ElectricityPoint, FinalElectricity, HomeMode, Mode,
+    ModePeriod, Occupancy, PeakDemand, Person, Place, PlaceCondition, PlaceCost, PlaceInput,
+    PresenceEvent, ProvisionedDevice, Revision, SearchResult, SolarProduction, Tariff, TariffInput,
+    TelemetryKind, Tombstone, WebhookDelivery, WebhookTarget, WebhookTargetInput,
+};
+use crate::notify::Channel;
+use crate::provisioning;
+use crate::signature;
+use crate::upload::{self, UploadFormat, UploadSummary};
 
 pub struct Query;
 
+pub struct Mutation;
+
+#[derive(SimpleObject)]
+pub struct ConnectionFields {
+    total_count: Option<i64>,
+    // Where the query stopped reading. Filtered pages can come back short or
+    // empty, so clients continue from here rather than from the last edge.
+    last_evaluated: Option<String>,
+}
+
 fn sk_time(prefix: &str, time: Option<String>, after: bool) -> Result<String> {
     let delta = if after { 1 } else { -1 };
     let time = match time {
-        Some(x) => DateTime::parse_from_rfc3339(&x)?.with_timezone(&Utc) + Duration::seconds(delta),
+        Some(x) => parse_time(&x)? + Duration::seconds(delta),
         None => {
             if after {
                 Utc.ymd(0, 1, 1).and_hms(0, 0, 0)
@@ -24,18 +65,137 @@ fn sk_time(prefix: &str, time: Option<String>, after: bool) -> Result<String> {
     Ok(format!("{}{:?}", prefix, time))
 }
 
-async fn get_items<'de, D>(
+// Key condition for items of `prefix` between `from` and `to`, both
+// inclusive.
+fn time_range(prefix: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Condition {
+    let from = from.unwrap_or_else(|| Utc.ymd(0, 1, 1).and_hms(0, 0, 0));
+    let to = to.unwrap_or_else(|| Utc.ymd(9999, 12, 31).and_hms(23, 59, 59));
+    Condition::Between(
+        format!("{}{:?}", prefix, from),
+        format!("{}{:?}", prefix, to),
+    )
+}
+
+// Applies after/before cursors to items built in memory, such as
+// downsampled series.
+fn retain_between<D: DynamoItem>(
+    items: &mut Vec<D>,
+    after: &Option<String>,
+    before: &Option<String>,
+) {
+    items.retain(|x| {
+        let cursor = x.sk_value();
+        after.as_ref().map_or(true, |after| &cursor > after)
+            && before.as_ref().map_or(true, |before| &cursor < before)
+    });
+}
+
+fn parse_time(time: &str) -> Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|x| x.with_timezone(&Utc))
+        .map_err(validation)
+}
+
+fn check_webhook_url(url: &str) -> Result<()> {
+    automation::check_url(url).map_err(validation)
+}
+
+fn parse_timezone(timezone: Option<String>) -> Result<Tz> {
+    timezone.map_or(Ok(Tz::UTC), |x| x.parse().map_err(validation))
+}
+
+// Whether some of the device's readings from `from` on have been compacted.
+async fn compacted(dynamodb: &Client, device: &str, from: Option<DateTime<Utc>>) -> Result<bool> {
+    let until = aggregation::compacted_until(dynamodb, device)
+        .await
+        .map_err(api_error)?;
+    Ok(until.map_or(false, |until| from.map_or(true, |from| from < until)))
+}
+
+async fn billing_period(
     dynamodb: &Client,
+    device: &str,
+    at: DateTime<Utc>,
+) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let device: Device = dynamodb
+        .get_item("DEVICE", device)
+        .await
+        .map_err(api_error)?;
+    Ok(billing::billing_period(&billing::anchor(&device), at))
+}
+
+// Tombstoned devices and places read as missing.
+async fn get_live<'de, D>(dynamodb: &Client, pk: &str, id: &str) -> Result<D>
+where
+    D: Deserialize<'de> + Tombstone,
+{
+    let item: D = dynamodb.get_item(pk, id).await.map_err(api_error)?;
+    if item.deleted_at().is_some() {
+        return Err(ApiError::new(Code::NotFound, "no item").extend());
+    }
+    Ok(item)
+}
+
+async fn bury<D>(dynamodb: &Client, pk: &str, id: &str, by: Option<String>) -> Result<bool>
+where
+    D: for<'de> Deserialize<'de> + Clone + DynamoItem + Serialize + Tombstone,
+{
+    let before: D = get_live(dynamodb, pk, id).await?;
+    let mut item = before.clone();
+    item.bury(by.clone());
+    dynamodb
+        .put_item_versioned(&item, before.version())
+        .await
+        .map_err(api_error)?;
+    history::record(dynamodb, Some(&before), &item, by).await;
+    Ok(true)
+}
+
+fn not_deleted() -> Filter {
+    Filter::NotExists("deleted_at".to_owned())
+}
+
+async fn get_entity<'de, D>(dynamodb: &Client, id: &str, timestamp: &str) -> Result<D>
+where
+    D: Deserialize<'de> + DynamoItem,
+{
+    let sk = format!("{}{:?}", D::sk_prefix(), parse_time(timestamp)?);
+    dynamodb.get_item(id, &sk).await.map_err(api_error)
+}
+
+async fn get_items<'de, D>(
+    ctx: &Context<'_>,
+    pk: &str,
+    sk: Option<Condition>,
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>,
+) -> Result<Connection<String, D, ConnectionFields, EmptyFields>>
+where
+    D: Deserialize<'de> + DynamoItem,
+{
+    get_filtered_items(ctx, pk, sk, Vec::new(), after, before, first, last).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn get_filtered_items<'de, D>(
+    ctx: &Context<'_>,
     pk: &str,
     sk: Option<Condition>,
+    filter: Vec<Filter>,
     after: Option<String>,
     before: Option<String>,
     first: Option<i32>,
     last: Option<i32>,
-) -> Result<Connection<String, D, EmptyFields, EmptyFields>>
+) -> Result<Connection<String, D, ConnectionFields, EmptyFields>>
 where
     D: Deserialize<'de> + DynamoItem,
 {
+    let dynamodb = ctx.data_unchecked::<Client>();
+    // totalCount costs an extra query, so it is only computed when selected.
+    let count = ctx.look_ahead().field("totalCount").exists();
+
     query(
         after,
         before,
@@ -44,13 +204,34 @@ where
         |after, before, first, last| async move {
             let has_after = after.is_some();
             let has_before = before.is_some();
+            // Cursors are sk values without the prefix.
+            let after = after.map(|x| format!("{}{}", D::sk_prefix(), x));
+            let before = before.map(|x| format!("{}{}", D::sk_prefix(), x));
+            let total_count = if count {
+                Some(
+                    dynamodb
+                        .count(pk, sk.clone(), filter.clone())
+                        .await
+                        .map_err(api_error)?,
+                )
+            } else {
+                None
+            };
             let (items, next): (Vec<D>, _) = dynamodb
-                .get_items(pk, sk, after, before, first, last)
-                .await?;
+                .get_items(pk, sk, filter, after, before, first, last)
+                .await
+                .map_err(api_error)?;
 
             let has_prev = has_after || (last.is_some() && next.is_some());
             let has_next = has_before || (first.is_some() && next.is_some());
-            let mut connection = Connection::new(has_prev, has_next);
+            let mut connection = Connection::with_additional_fields(
+                has_prev,
+                has_next,
+                ConnectionFields {
+                    total_count,
+                    last_evaluated: next.map(|x| x.trim_start_matches(&D::sk_prefix()).to_owned()),
+                },
+            );
             connection.append(items.into_iter().map(|x| Edge::new(x.sk_value(), x)));
             Ok(connection)
         },
@@ -58,15 +239,87 @@ where
     .await
 }
 
+fn device_conditions(filter: &DeviceFilter) -> (Option<Condition>, Vec<Filter>) {
+    let sk = filter.id_prefix.clone().map(Condition::BeginsWith);
+    let mut filters = vec![not_deleted()];
+    if let Some(place) = &filter.place {
+        filters.push(Filter::Eq("place".to_owned(), attr_string(place.clone())));
+    }
+    if let Some(tag) = &filter.tag {
+        filters.push(Filter::Contains(
+            "tags".to_owned(),
+            attr_string(tag.clone()),
+        ));
+    }
+    match filter.needs_attention {
+        Some(true) => filters.push(Filter::Exists("needs_attention".to_owned())),
+        Some(false) => filters.push(Filter::NotExists("needs_attention".to_owned())),
+        None => (),
+    }
+
+    (sk, filters)
+}
+
+fn connection_from<D>(
+    items: Vec<D>,
+    first: Option<i32>,
+    last: Option<i32>,
+) -> Result<Connection<String, D, ConnectionFields, EmptyFields>>
+where
+    D: DynamoItem,
+{
+    let total = items.len();
+    let mut range = 0..total;
+    if let Some(first) = first {
+        range.end = range.end.min(usize::try_from(first).map_err(validation)?);
+    }
+    if let Some(last) = last {
+        range.start = range
+            .end
+            .saturating_sub(usize::try_from(last).map_err(validation)?);
+    }
+
+    let mut connection = Connection::with_additional_fields(
+        range.start > 0,
+        range.end < total,
+        ConnectionFields {
+            total_count: Some(i64::try_from(total)?),
+            last_evaluated: None,
+        },
+    );
+    connection.append(
+        items
+            .into_iter()
+            .skip(range.start)
+            .take(range.end - range.start)
+            .map(|x| Edge::new(x.sk_value(), x)),
+    );
+    Ok(connection)
+}
+
 #[Object]
 impl Query {
     async fn device(&self, ctx: &Context<'_>, id: String) -> Result<Device> {
-        Ok(ctx
-            .data_unchecked::<Client>()
-            .get_item("DEVICE", &id)
-            .await?)
+        get_live(ctx.data_unchecked::<Client>(), "DEVICE", &id).await
+    }
+
+    async fn place(&self, ctx: &Context<'_>, id: String) -> Result<Place> {
+        get_live(ctx.data_unchecked::<Client>(), "PLACE", &id).await
+    }
+
+    async fn places(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, Place, ConnectionFields, EmptyFields>> {
+        let filters = vec![not_deleted()];
+        get_filtered_items(ctx, "PLACE", None, filters, after, before, first, last).await
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn devices(
         &self,
         ctx: &Context<'_>,
@@ -74,48 +327,304 @@ impl Query {
         before: Option<String>,
         first: Option<i32>,
         last: Option<i32>,
-    ) -> Result<Connection<String, Device, EmptyFields, EmptyFields>> {
+        offline_for_minutes: Option<i32>,
+        filter: Option<DeviceFilter>,
+    ) -> Result<Connection<String, Device, ConnectionFields, EmptyFields>> {
+        let dynamodb = &ctx.data_unchecked::<Client>();
+        let (sk, filters) = filter
+            .as_ref()
+            .map(device_conditions)
+            .unwrap_or_else(|| (None, vec![not_deleted()]));
+        let minutes = match offline_for_minutes {
+            Some(x) => x,
+            None => {
+                return get_filtered_items(ctx, "DEVICE", sk, filters, after, before, first, last)
+                    .await
+            }
+        };
+
+        let since = Utc::now() - Duration::minutes(minutes.into());
+        let devices: Vec<Device> = dynamodb.get_range("DEVICE", sk).await.map_err(api_error)?;
+        let devices = devices
+            .into_iter()
+            .filter(|x| x.deleted_at.is_none())
+            .filter(|x| filter.as_ref().map_or(true, |filter| filter.matches(x)))
+            .filter(|x| x.last_seen_at.map_or(true, |x| x < since))
+            .filter(|x| after.as_ref().map_or(true, |after| &x.id > after))
+            .filter(|x| before.as_ref().map_or(true, |before| &x.id < before))
+            .collect();
+        connection_from(devices, first, last)
+    }
+
+    // Recorded updates of the device, place or tariff with the id, oldest
+    // first.
+    async fn history(&self, ctx: &Context<'_>, id: String) -> Result<Vec<Revision>> {
+        history::history(ctx.data_unchecked::<Client>(), &id)
+            .await
+            .map_err(api_error)
+    }
+
+    async fn search(&self, ctx: &Context<'_>, query: String) -> Result<Vec<SearchResult>> {
+        let dynamodb = &ctx.data_unchecked::<Client>();
+        let filter = || {
+            vec![
+                Filter::Contains("search".to_owned(), attr_string(query.to_lowercase())),
+                not_deleted(),
+            ]
+        };
+        let devices: Vec<Device> = dynamodb
+            .get_filtered_range("DEVICE", None, filter())
+            .await
+            .map_err(api_error)?;
+        let places: Vec<Place> = dynamodb
+            .get_filtered_range("PLACE", None, filter())
+            .await
+            .map_err(api_error)?;
+
+        Ok(devices
+            .into_iter()
+            .map(SearchResult::Device)
+            .chain(places.into_iter().map(SearchResult::Place))
+            .collect())
+    }
+
+    async fn batteries(&self, ctx: &Context<'_>) -> Result<Vec<DeviceBattery>> {
+        health::batteries(ctx.data_unchecked::<Client>())
+            .await
+            .map_err(api_error)
+    }
+
+    async fn devices_needing_attention(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, Device, ConnectionFields, EmptyFields>> {
         let dynamodb = &ctx.data_unchecked::<Client>();
-        get_items(dynamodb, "DEVICE", None, after, before, first, last).await
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let has_after = after.is_some();
+                let has_before = before.is_some();
+                let (items, next): (Vec<Device>, _) = dynamodb
+                    .query_index("gsi2", "ATTENTION", None, after, before, first, last)
+                    .await
+                    .map_err(api_error)?;
+
+                let has_prev = has_after || (last.is_some() && next.is_some());
+                let has_next = has_before || (first.is_some() && next.is_some());
+                let mut connection = Connection::with_additional_fields(
+                    has_prev,
+                    has_next,
+                    ConnectionFields {
+                        total_count: None,
+                        last_evaluated: None,
+                    },
+                );
+                connection.append(items.into_iter().map(|x| {
+                    let cursor = serde_json::json!({
+                        "pk": x.pk(),
+                        "sk": x.sk(),
+                        "gsi2pk": "ATTENTION",
+                        "gsi2sk": x.sk(),
+                    });
+                    Edge::new(cursor.to_string(), x)
+                }));
+                Ok(connection)
+            },
+        )
+        .await
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn electricity(
         &self,
         ctx: &Context<'_>,
         id: String,
+        from: Option<String>,
+        to: Option<String>,
         after: Option<String>,
         before: Option<String>,
         first: Option<i32>,
         last: Option<i32>,
-    ) -> Result<Connection<String, Electricity, EmptyFields, EmptyFields>> {
+        resolution: Option<Resolution>,
+        fill: Option<Fill>,
+        min_current_w: Option<u32>,
+        timezone: Option<String>,
+    ) -> Result<Connection<String, Electricity, ConnectionFields, EmptyFields>> {
         let dynamodb = &ctx.data_unchecked::<Client>();
+        let tz = parse_timezone(timezone)?;
+        let from = from.as_deref().map(parse_time).transpose()?;
+        let to = to.as_deref().map(parse_time).transpose()?;
+        let sk = Some(time_range(&Electricity::sk_prefix(), from, to));
+        // Compacted readings are only in the summary tiers, so raw pages
+        // over them are built in memory.
+        let resolution = match resolution.filter(|x| *x != Resolution::Raw) {
+            None if compacted(dynamodb, &id, from).await? => Some(Resolution::Raw),
+            x => x,
+        };
+
+        match resolution {
+            Some(resolution) => {
+                let items = aggregation::tiered_range(dynamodb, &id, from, to, resolution)
+                    .await
+                    .map_err(api_error)?;
+                let items = aggregation::downsample_electricity(items, resolution, tz);
+                let fill = fill.unwrap_or(Fill::None);
+                let mut items = aggregation::fill_gaps(items, resolution, fill, from, to, tz);
+                if let Some(w) = min_current_w {
+                    items.retain(|x| x.current_w >= w);
+                }
+                retain_between(&mut items, &after, &before);
+                connection_from(items, first, last)
+            }
+            None => {
+                let filter = min_current_w
+                    .map(|w| Filter::Ge("current_w".to_owned(), attr_number(w)))
+                    .into_iter()
+                    .collect();
+                get_filtered_items(ctx, &id, sk, filter, after, before, first, last).await
+            }
+        }
+    }
+
+    async fn final_electricity(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        from: Option<String>,
+        to: Option<String>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, FinalElectricity, ConnectionFields, EmptyFields>> {
+        let from = from.as_deref().map(parse_time).transpose()?;
+        let to = to.as_deref().map(parse_time).transpose()?;
+        let sk = Some(time_range(&FinalElectricity::sk_prefix(), from, to));
+        get_items(ctx, &id, sk, after, before, first, last).await
+    }
+
+    // Raw readings with only timestamp and currentW, for sparklines over
+    // high-resolution ranges.
+    async fn electricity_sparkline(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        after: Option<String>,
+        before: Option<String>,
+    ) -> Result<Vec<ElectricityPoint>> {
         let prefix = Electricity::sk_prefix();
         let sk = Some(Condition::Between(
             sk_time(&prefix, after, true)?,
             sk_time(&prefix, before, false)?,
         ));
-        get_items(dynamodb, &id, sk, None, None, first, last).await
+        // PlaceCondition items share the prefix; only Electricity has a meter
+        // reading.
+        let filter = vec![Filter::Exists("cumulative_kwh_p".to_owned())];
+        ctx.data_unchecked::<Client>()
+            .get_projected_range(&id, sk, &ElectricityPoint::ATTRIBUTES, filter)
+            .await
+            .map_err(api_error)
     }
 
-    async fn final_electricity(
+    #[allow(clippy::too_many_arguments)]
+    async fn place_conditions(
         &self,
         ctx: &Context<'_>,
         id: String,
+        from: Option<String>,
+        to: Option<String>,
         after: Option<String>,
         before: Option<String>,
         first: Option<i32>,
         last: Option<i32>,
-    ) -> Result<Connection<String, FinalElectricity, EmptyFields, EmptyFields>> {
+        resolution: Option<Resolution>,
+        fill: Option<Fill>,
+        motion_only: Option<bool>,
+        temperature_above: Option<f64>,
+        timezone: Option<String>,
+    ) -> Result<Connection<String, PlaceCondition, ConnectionFields, EmptyFields>> {
         let dynamodb = &ctx.data_unchecked::<Client>();
-        let prefix = FinalElectricity::sk_prefix();
+        let tz = parse_timezone(timezone)?;
+        let from = from.as_deref().map(parse_time).transpose()?;
+        let to = to.as_deref().map(parse_time).transpose()?;
+        let sk = Some(time_range(&PlaceCondition::sk_prefix(), from, to));
+        let resolution = match resolution.filter(|x| *x != Resolution::Raw) {
+            None if compacted(dynamodb, &id, from).await? => Some(Resolution::Raw),
+            x => x,
+        };
+
+        match resolution {
+            Some(resolution) => {
+                let items = aggregation::tiered_range(dynamodb, &id, from, to, resolution)
+                    .await
+                    .map_err(api_error)?;
+                let items = aggregation::downsample_place_conditions(items, resolution, tz);
+                let fill = fill.unwrap_or(Fill::None);
+                let mut items = aggregation::fill_gaps(items, resolution, fill, from, to, tz);
+                if motion_only == Some(true) {
+                    items.retain(|x| x.motion.map_or(false, |x| x > 0));
+                }
+                if let Some(t) = temperature_above {
+                    items.retain(|x| x.temperature.map_or(false, |x| x > t));
+                }
+                retain_between(&mut items, &after, &before);
+                connection_from(items, first, last)
+            }
+            None => {
+                let mut filter = Vec::new();
+                if motion_only == Some(true) {
+                    filter.push(Filter::Gt("motion".to_owned(), attr_number(0)));
+                }
+                if let Some(t) = temperature_above {
+                    filter.push(Filter::Gt("temperature".to_owned(), attr_number(t)));
+                }
+                get_filtered_items(ctx, &id, sk, filter, after, before, first, last).await
+            }
+        }
+    }
+
+    async fn appliance_states(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, ApplianceState, ConnectionFields, EmptyFields>> {
+        let prefix = ApplianceState::sk_prefix();
         let sk = Some(Condition::Between(
             sk_time(&prefix, after, true)?,
             sk_time(&prefix, before, false)?,
         ));
-        get_items(dynamodb, &id, sk, None, None, first, last).await
+        get_items(ctx, &id, sk, None, None, first, last).await
     }
 
-    async fn place_conditions(
+    async fn solar_production(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, SolarProduction, ConnectionFields, EmptyFields>> {
+        let prefix = SolarProduction::sk_prefix();
+        let sk = Some(Condition::Between(
+            sk_time(&prefix, after, true)?,
+            sk_time(&prefix, before, false)?,
+        ));
+        get_items(ctx, &id, sk, None, None, first, last).await
+    }
+
+    async fn battery_states(
         &self,
         ctx: &Context<'_>,
         id: String,
@@ -123,21 +632,998 @@ impl Query {
         before: Option<String>,
         first: Option<i32>,
         last: Option<i32>,
-    ) -> Result<Connection<String, PlaceCondition, EmptyFields, EmptyFields>> {
+    ) -> Result<Connection<String, BatteryState, ConnectionFields, EmptyFields>> {
+        let prefix = BatteryState::sk_prefix();
+        let sk = Some(Condition::Between(
+            sk_time(&prefix, after, true)?,
+            sk_time(&prefix, before, false)?,
+        ));
+        get_items(ctx, &id, sk, None, None, first, last).await
+    }
+
+    async fn energy_intervals(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        from: String,
+        to: String,
+        interval: Resolution,
+        timezone: Option<String>,
+    ) -> Result<Vec<EnergyInterval>> {
+        let dynamodb = &ctx.data_unchecked::<Client>();
+        let tz = parse_timezone(timezone)?;
+        let (from, to) = (parse_time(&from)?, parse_time(&to)?);
+        let readings = aggregation::electricity_readings(dynamodb, &device, from, to)
+            .await
+            .map_err(api_error)?;
+        Ok(aggregation::energy_intervals(
+            &readings, interval, from, to, tz,
+        ))
+    }
+
+    // Without a range, summarizes the device's current billing period.
+    async fn energy_summary(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        from: Option<String>,
+        to: Option<String>,
+    ) -> Result<EnergySummary> {
+        let dynamodb = &ctx.data_unchecked::<Client>();
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) => (parse_time(&from)?, parse_time(&to)?),
+            (None, None) => billing_period(dynamodb, &device, Utc::now()).await?,
+            _ => return Err(validation("from and to must be given together")),
+        };
+        let readings = aggregation::electricity_readings(dynamodb, &device, from, to)
+            .await
+            .map_err(api_error)?;
+        Ok(aggregation::energy_summary(&readings, from, to))
+    }
+
+    async fn usage_forecast(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        horizon_days: i32,
+        timezone: Option<String>,
+    ) -> Result<UsageForecast> {
+        let horizon_days = u32::try_from(horizon_days)
+            .ok()
+            .filter(|x| *x <= aggregation::MAX_FORECAST_DAYS)
+            .ok_or_else(|| {
+                let message = format!("must be between 0 and {}", aggregation::MAX_FORECAST_DAYS);
+                ApiError::invalid("horizonDays", message).extend()
+            })?;
+        let dynamodb = &ctx.data_unchecked::<Client>();
+        let tz = parse_timezone(timezone)?;
+        let now = Utc::now();
+        let from = Resolution::Day.bucket_start(now, tz) - Duration::days(28);
+        let readings = aggregation::electricity_readings(dynamodb, &device, from, now)
+            .await
+            .map_err(api_error)?;
+        let daily = aggregation::energy_intervals(&readings, Resolution::Day, from, now, tz);
+        Ok(aggregation::usage_forecast(&daily, now, horizon_days, tz))
+    }
+
+    // `meter` is the grid meter and `inverter` the solar inverter. Prices
+    // come from the meter's tariff; `battery_kwh` sizes the battery the
+    // savings estimate assumes, 10 kWh by default.
+    #[allow(clippy::too_many_arguments)]
+    async fn solar_analytics(
+        &self,
+        ctx: &Context<'_>,
+        meter: String,
+        inverter: String,
+        from: String,
+        to: String,
+        battery_kwh: Option<String>,
+        timezone: Option<String>,
+    ) -> Result<SolarAnalytics> {
+        let dynamodb = &ctx.data_unchecked::<Client>();
+        let tz = parse_timezone(timezone)?;
+        let (from, to) = (parse_time(&from)?, parse_time(&to)?);
+        let battery_kwh: Decimal = battery_kwh
+            .as_deref()
+            .unwrap_or("10")
+            .parse()
+            .map_err(validation)?;
+        if battery_kwh.is_sign_negative() {
+            return Err(ApiError::invalid("batteryKwh", "must not be negative").extend());
+        }
+
+        let device: Device = dynamodb
+            .get_item("DEVICE", &meter)
+            .await
+            .map_err(api_error)?;
+        let tariff: Option<Tariff> = match device.tariff {
+            Some(id) => dynamodb.find_item("TARIFF", &id).await.map_err(api_error)?,
+            None => None,
+        };
+        let readings = aggregation::electricity_readings(dynamodb, &meter, from, to)
+            .await
+            .map_err(api_error)?;
+        let solar = aggregation::solar_readings(dynamodb, &inverter, from, to)
+            .await
+            .map_err(api_error)?;
+
+        Ok(aggregation::solar_analytics(
+            &solar,
+            &readings,
+            tariff.as_ref(),
+            battery_kwh,
+            from,
+            to,
+            tz,
+        ))
+    }
+
+    // The highest average import over any `window_minutes` window.
+    async fn peak_demand(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        from: String,
+        to: String,
+        window_minutes: i32,
+    ) -> Result<Option<DemandPeak>> {
         let dynamodb = &ctx.data_unchecked::<Client>();
-        let prefix = PlaceCondition::sk_prefix();
+        let (from, to) = (parse_time(&from)?, parse_time(&to)?);
+        if window_minutes <= 0 {
+            return Err(validation("windowMinutes must be positive"));
+        }
+        let readings = aggregation::electricity_readings(dynamodb, &device, from, to)
+            .await
+            .map_err(api_error)?;
+        let window = Duration::minutes(window_minutes.into());
+        Ok(demand::peak(&readings, window, from, to))
+    }
+
+    // Monthly peaks tracked on write, keyed by "YYYY-MM".
+    #[allow(clippy::too_many_arguments)]
+    async fn peak_demand_history(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        from: Option<String>,
+        to: Option<String>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, PeakDemand, ConnectionFields, EmptyFields>> {
+        let pk = format!("PEAK_DEMAND#{}", device);
+        let sk = Condition::Between(
+            from.unwrap_or_else(|| "0000-01".to_owned()),
+            to.unwrap_or_else(|| "9999-12".to_owned()),
+        );
+        get_items(ctx, &pk, Some(sk), after, before, first, last).await
+    }
+
+    async fn tariffs(&self, ctx: &Context<'_>) -> Result<Vec<Tariff>> {
+        ctx.data_unchecked::<Client>()
+            .get_range("TARIFF", None)
+            .await
+            .map_err(api_error)
+    }
+
+    async fn statements(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, BillingStatement, ConnectionFields, EmptyFields>> {
+        let prefix = BillingStatement::sk_prefix();
         let sk = Some(Condition::Between(
             sk_time(&prefix, after, true)?,
             sk_time(&prefix, before, false)?,
         ));
-        get_items(dynamodb, &id, sk, None, None, first, last).await
+        get_items(ctx, &device, sk, None, None, first, last).await
+    }
+
+    #[graphql(entity)]
+    async fn find_device_by_id(&self, ctx: &Context<'_>, id: String) -> Result<Device> {
+        self.device(ctx, id).await
+    }
+
+    #[graphql(entity)]
+    async fn find_place_by_id(&self, ctx: &Context<'_>, id: String) -> Result<Place> {
+        self.place(ctx, id).await
+    }
+
+    #[graphql(entity)]
+    async fn find_electricity_by_id_and_timestamp(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        timestamp: String,
+    ) -> Result<Electricity> {
+        get_entity(ctx.data_unchecked::<Client>(), &id, &timestamp).await
+    }
+
+    #[graphql(entity)]
+    async fn find_final_electricity_by_id_and_timestamp(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        timestamp: String,
+    ) -> Result<FinalElectricity> {
+        get_entity(ctx.data_unchecked::<Client>(), &id, &timestamp).await
+    }
+
+    #[graphql(entity)]
+    async fn find_place_condition_by_id_and_timestamp(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        timestamp: String,
+    ) -> Result<PlaceCondition> {
+        get_entity(ctx.data_unchecked::<Client>(), &id, &timestamp).await
+    }
+
+    #[graphql(entity)]
+    async fn find_appliance_state_by_id_and_timestamp(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        timestamp: String,
+    ) -> Result<ApplianceState> {
+        get_entity(ctx.data_unchecked::<Client>(), &id, &timestamp).await
+    }
+
+    #[graphql(entity)]
+    async fn find_solar_production_by_id_and_timestamp(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        timestamp: String,
+    ) -> Result<SolarProduction> {
+        get_entity(ctx.data_unchecked::<Client>(), &id, &timestamp).await
+    }
+
+    #[graphql(entity)]
+    async fn find_battery_state_by_id_and_timestamp(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        timestamp: String,
+    ) -> Result<BatteryState> {
+        get_entity(ctx.data_unchecked::<Client>(), &id, &timestamp).await
+    }
+
+    async fn backups(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, Backup, ConnectionFields, EmptyFields>> {
+        get_items(ctx, "BACKUP", None, after, before, first, last).await
+    }
+
+    async fn alert_rule(&self, ctx: &Context<'_>, id: String) -> Result<AlertRule> {
+        ctx.data_unchecked::<Client>()
+            .get_item("ALERT_RULE", &id)
+            .await
+            .map_err(api_error)
+    }
+
+    async fn alert_rules(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, AlertRule, ConnectionFields, EmptyFields>> {
+        get_items(ctx, "ALERT_RULE", None, after, before, first, last).await
+    }
+
+    async fn alerts(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, Alert, ConnectionFields, EmptyFields>> {
+        get_items(ctx, "ALERT", None, after, before, first, last).await
+    }
+
+    async fn automation(&self, ctx: &Context<'_>, id: String) -> Result<Automation> {
+        ctx.data_unchecked::<Client>()
+            .get_item("AUTOMATION", &id)
+            .await
+            .map_err(api_error)
+    }
+
+    async fn automations(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, Automation, ConnectionFields, EmptyFields>> {
+        get_items(ctx, "AUTOMATION", None, after, before, first, last).await
+    }
+
+    async fn webhook_target(&self, ctx: &Context<'_>, id: String) -> Result<WebhookTarget> {
+        ctx.data_unchecked::<Client>()
+            .get_item("WEBHOOK_TARGET", &id)
+            .await
+            .map_err(api_error)
+    }
+
+    async fn webhook_targets(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, WebhookTarget, ConnectionFields, EmptyFields>> {
+        get_items(ctx, "WEBHOOK_TARGET", None, after, before, first, last).await
+    }
+
+    // The delivery log, oldest first. Entries expire after WEBHOOK_LOG_DAYS.
+    #[allow(clippy::too_many_arguments)]
+    async fn webhook_deliveries(
+        &self,
+        ctx: &Context<'_>,
+        target: Option<String>,
+        status: Option<DeliveryStatus>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, WebhookDelivery, ConnectionFields, EmptyFields>> {
+        let mut filters = Vec::new();
+        if let Some(target) = target {
+            filters.push(Filter::Eq("target".to_owned(), attr_string(target)));
+        }
+        if let Some(status) = status {
+            let status = format!("{:?}", status);
+            filters.push(Filter::Eq("status".to_owned(), attr_string(status)));
+        }
+        get_filtered_items(
+            ctx,
+            "WEBHOOK_DELIVERY",
+            None,
+            filters,
+            after,
+            before,
+            first,
+            last,
+        )
+        .await
+    }
+
+    // Occupied periods of a place, oldest first. The latest one has no `to`
+    // while the place is still occupied.
+    #[allow(clippy::too_many_arguments)]
+    async fn occupancy_history(
+        &self,
+        ctx: &Context<'_>,
+        place: String,
+        from: Option<String>,
+        to: Option<String>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, Occupancy, ConnectionFields, EmptyFields>> {
+        let from = from.as_deref().map(parse_time).transpose()?;
+        let to = to.as_deref().map(parse_time).transpose()?;
+        let pk = format!("OCCUPANCY#{}", place);
+        let sk = Some(time_range("", from, to));
+        get_items(ctx, &pk, sk, after, before, first, last).await
+    }
+
+    async fn people(&self, ctx: &Context<'_>) -> Result<Vec<Person>> {
+        ctx.data_unchecked::<Client>()
+            .get_range("PERSON", None)
+            .await
+            .map_err(api_error)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn presence_history(
+        &self,
+        ctx: &Context<'_>,
+        person: String,
+        from: Option<String>,
+        to: Option<String>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, PresenceEvent, ConnectionFields, EmptyFields>> {
+        let from = from.as_deref().map(parse_time).transpose()?;
+        let to = to.as_deref().map(parse_time).transpose()?;
+        let pk = format!("PRESENCE#{}", person);
+        let sk = Some(time_range(&PresenceEvent::sk_prefix(), from, to));
+        get_items(ctx, &pk, sk, after, before, first, last).await
+    }
+
+    async fn cost_by_place(
+        &self,
+        ctx: &Context<'_>,
+        from: String,
+        to: String,
+    ) -> Result<Vec<PlaceCost>> {
+        let (from, to) = (parse_time(&from)?, parse_time(&to)?);
+        billing::cost_by_place(ctx.data_unchecked::<Client>(), from, to)
+            .await
+            .map_err(api_error)
+    }
+
+    async fn carbon_intensity(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<String>,
+        to: Option<String>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, CarbonIntensity, ConnectionFields, EmptyFields>> {
+        let from = from.as_deref().map(parse_time).transpose()?;
+        let to = to.as_deref().map(parse_time).transpose()?;
+        let sk = Some(time_range(&CarbonIntensity::sk_prefix(), from, to));
+        get_items(ctx, "CARBON_INTENSITY", sk, after, before, first, last).await
+    }
+
+    async fn carbon_footprint(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        from: String,
+        to: String,
+    ) -> Result<CarbonFootprint> {
+        let (from, to) = (parse_time(&from)?, parse_time(&to)?);
+        carbon::footprint(ctx.data_unchecked::<Client>(), &device, from, to)
+            .await
+            .map_err(api_error)
+    }
+
+    async fn home_mode(&self, ctx: &Context<'_>) -> Result<HomeMode> {
+        home_mode::get(ctx.data_unchecked::<Client>())
+            .await
+            .map_err(api_error)
     }
 }
 
-pub type HomeAPI = Schema<Query, EmptyMutation, EmptySubscription>;
+#[Object]
+impl Mutation {
+    async fn create_alert_rule(
+        &self,
+        ctx: &Context<'_>,
+        input: AlertRuleInput,
+    ) -> Result<AlertRule> {
+        let rule = AlertRule::new(uuid::Uuid::new_v4().to_string(), input);
+        Channel::parse(&rule.channel).map_err(validation)?;
+        ctx.data_unchecked::<Client>()
+            .put_item(&rule)
+            .await
+            .map_err(api_error)?;
+        Ok(rule)
+    }
+
+    async fn update_alert_rule(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: AlertRuleInput,
+        upsert: Option<bool>,
+    ) -> Result<AlertRule> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let rule: Option<AlertRule> = if upsert == Some(true) {
+            dynamodb
+                .find_item("ALERT_RULE", &id)
+                .await
+                .map_err(api_error)?
+        } else {
+            Some(
+                dynamodb
+                    .get_item("ALERT_RULE", &id)
+                    .await
+                    .map_err(api_error)?,
+            )
+        };
+        let mut rule = match rule {
+            Some(mut rule) => {
+                rule.update(input);
+                rule
+            }
+            None => AlertRule::new(id, input),
+        };
+        Channel::parse(&rule.channel).map_err(validation)?;
+        let version = rule.version;
+        rule.version += 1;
+        dynamodb
+            .put_item_versioned(&rule, version)
+            .await
+            .map_err(api_error)?;
+        Ok(rule)
+    }
+
+    // Sets the manual mode, or schedules a mode until `to` when it is given.
+    async fn set_home_mode(
+        &self,
+        ctx: &Context<'_>,
+        mode: Mode,
+        from: Option<String>,
+        to: Option<String>,
+    ) -> Result<HomeMode> {
+        let now = Utc::now();
+        let period = match to {
+            Some(to) => {
+                let from = from.as_deref().map(parse_time).transpose()?.unwrap_or(now);
+                let to = parse_time(&to)?;
+                if to <= from {
+                    return Err(validation("to must be after from"));
+                }
+                Some(ModePeriod { mode, from, to })
+            }
+            None if from.is_some() => return Err(validation("from requires to")),
+            None => None,
+        };
+
+        home_mode::update(ctx.data_unchecked::<Client>(), |home| {
+            match &period {
+                Some(x) => home.add_period(x.clone(), now),
+                None => home.mode = mode,
+            }
+            true
+        })
+        .await
+        .map_err(api_error)
+    }
+
+    async fn clear_home_mode_schedule(&self, ctx: &Context<'_>) -> Result<HomeMode> {
+        home_mode::update(ctx.data_unchecked::<Client>(), |home| {
+            home.schedule.clear();
+            true
+        })
+        .await
+        .map_err(api_error)
+    }
+
+    async fn delete_alert_rule(&self, ctx: &Context<'_>, id: String) -> Result<bool> {
+        ctx.data_unchecked::<Client>()
+            .delete_item("ALERT_RULE", &id)
+            .await
+            .map_err(api_error)?;
+        Ok(true)
+    }
+
+    async fn create_automation(
+        &self,
+        ctx: &Context<'_>,
+        input: AutomationInput,
+    ) -> Result<Automation> {
+        let mut automation = Automation::new(uuid::Uuid::new_v4().to_string());
+        automation.update(input).map_err(validation)?;
+        ctx.data_unchecked::<Client>()
+            .put_item(&automation)
+            .await
+            .map_err(api_error)?;
+        Ok(automation)
+    }
+
+    async fn update_automation(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: AutomationInput,
+    ) -> Result<Automation> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let mut automation: Automation = dynamodb
+            .get_item("AUTOMATION", &id)
+            .await
+            .map_err(api_error)?;
+        let version = automation.version;
+        automation.update(input).map_err(validation)?;
+        automation.version += 1;
+        dynamodb
+            .put_item_versioned(&automation, version)
+            .await
+            .map_err(api_error)?;
+        Ok(automation)
+    }
+
+    async fn delete_automation(&self, ctx: &Context<'_>, id: String) -> Result<bool> {
+        ctx.data_unchecked::<Client>()
+            .delete_item("AUTOMATION", &id)
+            .await
+            .map_err(api_error)?;
+        Ok(true)
+    }
+
+    // Runs the actions now, whether or not the automation is enabled.
+    async fn run_automation(&self, ctx: &Context<'_>, id: String) -> Result<Automation> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let mut automation: Automation = dynamodb
+            .get_item("AUTOMATION", &id)
+            .await
+            .map_err(api_error)?;
+        let trigger = serde_json::json!({ "manual": true });
+        automation::run(dynamodb, &mut automation, trigger, Utc::now())
+            .await
+            .map_err(api_error)?;
+        Ok(automation)
+    }
+
+    // The secret is returned only here and by rotateWebhookTargetSecret.
+    async fn create_webhook_target(
+        &self,
+        ctx: &Context<'_>,
+        input: WebhookTargetInput,
+    ) -> Result<CreatedWebhookTarget> {
+        check_webhook_url(&input.url)?;
+        let secret = signature::new_secret();
+        let target = WebhookTarget::new(uuid::Uuid::new_v4().to_string(), secret.clone(), input);
+        ctx.data_unchecked::<Client>()
+            .put_item(&target)
+            .await
+            .map_err(api_error)?;
+        Ok(CreatedWebhookTarget { target, secret })
+    }
+
+    async fn update_webhook_target(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: WebhookTargetInput,
+    ) -> Result<WebhookTarget> {
+        check_webhook_url(&input.url)?;
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let mut target: WebhookTarget = dynamodb
+            .get_item("WEBHOOK_TARGET", &id)
+            .await
+            .map_err(api_error)?;
+        let version = target.version;
+        target.update(input);
+        target.version += 1;
+        dynamodb
+            .put_item_versioned(&target, version)
+            .await
+            .map_err(api_error)?;
+        Ok(target)
+    }
+
+    async fn rotate_webhook_target_secret(&self, ctx: &Context<'_>, id: String) -> Result<String> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let mut target: WebhookTarget = dynamodb
+            .get_item("WEBHOOK_TARGET", &id)
+            .await
+            .map_err(api_error)?;
+        let version = target.version;
+        target.secret = signature::new_secret();
+        target.version += 1;
+        dynamodb
+            .put_item_versioned(&target, version)
+            .await
+            .map_err(api_error)?;
+        Ok(target.secret)
+    }
+
+    async fn delete_webhook_target(&self, ctx: &Context<'_>, id: String) -> Result<bool> {
+        ctx.data_unchecked::<Client>()
+            .delete_item("WEBHOOK_TARGET", &id)
+            .await
+            .map_err(api_error)?;
+        Ok(true)
+    }
+
+    async fn create_backup(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        table: Option<String>,
+    ) -> Result<Backup> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        backup::create_backup(dynamodb, &name, table.as_deref())
+            .await
+            .map_err(api_error)
+    }
+
+    async fn delete_telemetry_range(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        kind: TelemetryKind,
+        from: String,
+        to: String,
+    ) -> Result<usize> {
+        let prefix = kind.sk_prefix();
+        let sk = Condition::Between(
+            format!("{}{:?}", prefix, parse_time(&from)?),
+            format!("{}{:?}", prefix, parse_time(&to)?),
+        );
+        ctx.data_unchecked::<Client>()
+            .delete_range(&device, sk)
+            .await
+            .map_err(api_error)
+    }
+
+    // Imports a CSV or NDJSON file of telemetry of one kind, such as
+    // "electricity", sent as a multipart request.
+    async fn upload_telemetry(
+        &self,
+        ctx: &Context<'_>,
+        file: Upload,
+        kind: String,
+        format: UploadFormat,
+    ) -> Result<UploadSummary> {
+        let file = file.value(ctx).map_err(validation)?;
+        upload::upload(ctx.data_unchecked::<Client>(), &kind, format, file.content)
+            .await
+            .map_err(api_error)
+    }
+
+    async fn put_tariff(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: TariffInput,
+        by: Option<String>,
+    ) -> Result<Tariff> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let before: Option<Tariff> = dynamodb.find_item("TARIFF", &id).await.map_err(api_error)?;
+        let version = before.as_ref().map_or(0, |x| x.version);
+        let mut tariff = Tariff::new(id);
+        tariff.update(input).map_err(validation)?;
+        tariff.version = version + 1;
+        dynamodb
+            .put_item_versioned(&tariff, version)
+            .await
+            .map_err(api_error)?;
+        history::record(dynamodb, before.as_ref(), &tariff, by).await;
+        Ok(tariff)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn update_device(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: DeviceInput,
+        version: i64,
+        upsert: Option<bool>,
+        unset: Option<Vec<DeviceField>>,
+        by: Option<String>,
+    ) -> Result<Device> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let before: Option<Device> = if upsert == Some(true) {
+            dynamodb.find_item("DEVICE", &id).await.map_err(api_error)?
+        } else {
+            Some(get_live(dynamodb, "DEVICE", &id).await?)
+        };
+        let mut device = before.clone().unwrap_or_else(|| Device::new(id));
+        device.update(input);
+        device.unset(&unset.unwrap_or_default());
+        device.version = version + 1;
+        dynamodb
+            .put_item_versioned(&device, version)
+            .await
+            .map_err(api_error)?;
+        history::record(dynamodb, before.as_ref(), &device, by).await;
+        Ok(device)
+    }
+
+    async fn update_place(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        input: PlaceInput,
+        version: i64,
+        by: Option<String>,
+    ) -> Result<Place> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let before: Place = get_live(dynamodb, "PLACE", &id).await?;
+        let mut place = before.clone();
+        place.update(input);
+        place.version = version + 1;
+        dynamodb
+            .put_item_versioned(&place, version)
+            .await
+            .map_err(api_error)?;
+        history::record(dynamodb, Some(&before), &place, by).await;
+        Ok(place)
+    }
+
+    // Writes a tombstone; the device is purged after TOMBSTONE_GRACE_DAYS
+    // unless restored. `by` is recorded as who deleted it.
+    async fn delete_device(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        by: Option<String>,
+    ) -> Result<bool> {
+        bury::<Device>(ctx.data_unchecked::<Client>(), "DEVICE", &id, by).await
+    }
+
+    async fn delete_place(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        by: Option<String>,
+    ) -> Result<bool> {
+        bury::<Place>(ctx.data_unchecked::<Client>(), "PLACE", &id, by).await
+    }
+
+    // Brings back a deleted device or place that has not been purged yet.
+    async fn restore(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        by: Option<String>,
+    ) -> Result<SearchResult> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let device: Option<Device> = dynamodb.find_item("DEVICE", &id).await.map_err(api_error)?;
+        if let Some(before) = device.filter(|x| x.deleted_at.is_some()) {
+            let mut device = before.clone();
+            device.restore();
+            dynamodb
+                .put_item_versioned(&device, before.version)
+                .await
+                .map_err(api_error)?;
+            history::record(dynamodb, Some(&before), &device, by).await;
+            return Ok(SearchResult::Device(device));
+        }
+        let place: Option<Place> = dynamodb.find_item("PLACE", &id).await.map_err(api_error)?;
+        if let Some(before) = place.filter(|x| x.deleted_at.is_some()) {
+            let mut place = before.clone();
+            place.restore();
+            dynamodb
+                .put_item_versioned(&place, before.version)
+                .await
+                .map_err(api_error)?;
+            history::record(dynamodb, Some(&before), &place, by).await;
+            return Ok(SearchResult::Place(place));
+        }
+        Err(ApiError::new(Code::NotFound, "no deleted item").extend())
+    }
+
+    async fn set_device_tariff(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        tariff: Option<String>,
+        by: Option<String>,
+    ) -> Result<Device> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let before: Device = get_live(dynamodb, "DEVICE", &device).await?;
+        if let Some(id) = &tariff {
+            dynamodb
+                .get_item::<Tariff>("TARIFF", id)
+                .await
+                .map_err(api_error)?;
+        }
+        let mut device = before.clone();
+        device.tariff = tariff;
+        device.version += 1;
+        dynamodb
+            .put_item_versioned(&device, before.version)
+            .await
+            .map_err(api_error)?;
+        history::record(dynamodb, Some(&before), &device, by).await;
+        Ok(device)
+    }
+
+    // Clearing the anchor puts the device back on the deployment default.
+    async fn set_device_billing_anchor(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        anchor: Option<BillingAnchorInput>,
+        by: Option<String>,
+    ) -> Result<Device> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let before: Device = get_live(dynamodb, "DEVICE", &device).await?;
+        let anchor = anchor
+            .map(BillingAnchor::new)
+            .transpose()
+            .map_err(validation)?;
+        let mut device = before.clone();
+        device.billing_anchor = anchor;
+        device.version += 1;
+        dynamodb
+            .put_item_versioned(&device, before.version)
+            .await
+            .map_err(api_error)?;
+        history::record(dynamodb, Some(&before), &device, by).await;
+        Ok(device)
+    }
+
+    // Issues a new signing secret for the device. The secret is returned only
+    // here; the previous one stops working immediately.
+    async fn rotate_device_secret(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        by: Option<String>,
+    ) -> Result<String> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let before: Device = get_live(dynamodb, "DEVICE", &id).await?;
+        let secret = signature::new_secret();
+        let mut device = before.clone();
+        device.secret = Some(secret.clone());
+        device.version += 1;
+        dynamodb
+            .put_item_versioned(&device, before.version)
+            .await
+            .map_err(api_error)?;
+        history::record(dynamodb, Some(&before), &device, by).await;
+        Ok(secret)
+    }
+
+    async fn provision_device(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        place: String,
+    ) -> Result<ProvisionedDevice> {
+        provisioning::provision(ctx.data_unchecked::<Client>(), name, place)
+            .await
+            .map_err(api_error)
+    }
+
+    // Called by device firmware with the code printed at provisioning time.
+    async fn claim_device(&self, ctx: &Context<'_>, code: String) -> Result<DeviceCredentials> {
+        provisioning::claim(ctx.data_unchecked::<Client>(), &code)
+            .await
+            .map_err(api_error)
+    }
+
+    // Without a period, bills the device's last complete billing period.
+    async fn generate_statement(
+        &self,
+        ctx: &Context<'_>,
+        device: String,
+        period_start: Option<String>,
+        period_end: Option<String>,
+    ) -> Result<BillingStatement> {
+        let dynamodb = ctx.data_unchecked::<Client>();
+        let (from, to) = match (period_start, period_end) {
+            (Some(from), Some(to)) => (parse_time(&from)?, parse_time(&to)?),
+            (None, None) => {
+                let (current, _) = billing_period(dynamodb, &device, Utc::now()).await?;
+                billing_period(dynamodb, &device, current - Duration::milliseconds(1)).await?
+            }
+            _ => {
+                return Err(validation(
+                    "periodStart and periodEnd must be given together",
+                ))
+            }
+        };
+        billing::generate_statement(dynamodb, &device, from, to)
+            .await
+            .map_err(api_error)
+    }
+}
+
+pub type HomeAPI = Schema<Query, Mutation, EmptySubscription>;
+
+fn builder() -> SchemaBuilder<Query, Mutation, EmptySubscription> {
+    Schema::build(Query, Mutation, EmptySubscription).enable_federation()
+}
+
+pub fn schema(dynamodb: Client, introspection: bool) -> HomeAPI {
+    let builder = builder().data(dynamodb);
+    if introspection {
+        builder.finish()
+    } else {
+        builder.disable_introspection().finish()
+    }
+}
 
-pub fn schema(dynamodb: Client) -> HomeAPI {
-    Schema::build(Query, EmptyMutation, EmptySubscription)
-        .data(dynamodb)
-        .finish()
+pub fn sdl() -> String {
+    builder().finish().sdl()
 }
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde_json::{Map, Value};
+
+// Telemetry for a device arriving less than WRITE_MIN_INTERVAL_SECONDS after
+// the last accepted record of the same kind is dropped. Exact duplicates of
+// the last accepted record are always dropped. State is per process, so a
+// Lambda deployment only guards within one warm container.
+static MIN_INTERVAL: Lazy<Duration> = Lazy::new(|| {
+    Duration::seconds(
+        std::env::var("WRITE_MIN_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(0),
+    )
+});
+
+static LAST_ACCEPTED: Lazy<Mutex<HashMap<(String, String), DateTime<Utc>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Window updates from one batch. They are applied by commit once the batch
+// has been written, so that the retry of a failed write isn't dropped as a
+// duplicate.
+#[derive(Default)]
+pub struct Admitted(HashMap<(String, String), DateTime<Utc>>);
+
+fn admit(admitted: &mut Admitted, device: &str, prefix: &str, timestamp: DateTime<Utc>) -> bool {
+    let key = (device.to_owned(), prefix.to_owned());
+    let last = admitted
+        .0
+        .get(&key)
+        .copied()
+        .or_else(|| LAST_ACCEPTED.lock().unwrap().get(&key).copied());
+
+    if let Some(x) = last {
+        if timestamp == x || (timestamp > x && timestamp - x < *MIN_INTERVAL) {
+            return false;
+        }
+        // Late or backfilled records are written but don't move the window.
+        if timestamp < x {
+            return true;
+        }
+    }
+    admitted.0.insert(key, timestamp);
+    true
+}
+
+pub fn commit(admitted: Admitted) {
+    let mut last = LAST_ACCEPTED.lock().unwrap();
+    for (key, timestamp) in admitted.0 {
+        let x = last.entry(key).or_insert(timestamp);
+        *x = (*x).max(timestamp);
+    }
+}
+
+// Filters webhook items in place and returns how many were dropped, with the
+// window updates to commit after writing the rest. Items whose keys can't be
+// read are left for validation to reject.
+pub fn filter(items: &mut Vec<Map<String, Value>>, prefix: &str) -> (usize, Admitted) {
+    let mut admitted = Admitted::default();
+    let count = items.len();
+    items.retain(|item| {
+        let device = item.get("pk").and_then(Value::as_str);
+        let timestamp = item
+            .get("sk")
+            .and_then(Value::as_str)
+            .and_then(|x| x.strip_prefix(prefix))
+            .and_then(|x| x.parse::<DateTime<Utc>>().ok());
+        match (device, timestamp) {
+            (Some(device), Some(timestamp)) => admit(&mut admitted, device, prefix, timestamp),
+            _ => true,
+        }
+    });
+    (count - items.len(), admitted)
+}
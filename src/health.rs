@@ -0,0 +1,188 @@
+use anyhow::Result;
+use async_graphql::Object;
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::delivery;
+use crate::dynamodb::{Client, Condition};
+use crate::error::is_conflict;
+use crate::models::{
+    live, ApplianceState, BatteryState, Device, DynamoItem, Electricity, EventKind, PlaceCondition,
+    SolarProduction,
+};
+
+static OFFLINE_MINUTES: Lazy<i64> = Lazy::new(|| {
+    std::env::var("ATTENTION_OFFLINE_MINUTES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(60)
+});
+static LOW_BATTERY_PERCENT: Lazy<f64> = Lazy::new(|| {
+    std::env::var("ATTENTION_LOW_BATTERY_PERCENT")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(20.0)
+});
+
+#[derive(Debug, Deserialize)]
+struct Seen {
+    sk: String,
+}
+
+// Only the battery of the last TS# item, which may be an Electricity reading
+// on a meter and would not decode as a PlaceCondition.
+#[derive(Debug, Deserialize)]
+struct ConditionBattery {
+    sk: String,
+    battery_percent: Option<f64>,
+}
+
+async fn last_seen(dynamodb: &Client, device: &str) -> Result<Option<DateTime<Utc>>> {
+    let prefixes = [
+        Electricity::sk_prefix(),
+        ApplianceState::sk_prefix(),
+        SolarProduction::sk_prefix(),
+        BatteryState::sk_prefix(),
+    ];
+    let mut last = None;
+
+    for prefix in prefixes.iter() {
+        let seen: Option<Seen> = dynamodb
+            .get_last_item(device, Condition::BeginsWith(prefix.to_owned()))
+            .await?;
+        let timestamp = seen.and_then(|x| {
+            x.sk.strip_prefix(prefix.as_str())
+                .and_then(|x| x.parse::<DateTime<Utc>>().ok())
+        });
+        last = last.max(timestamp);
+    }
+
+    Ok(last)
+}
+
+pub struct DeviceBattery {
+    pub device: String,
+    pub place: String,
+    pub percent: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[Object]
+impl DeviceBattery {
+    async fn device(&self) -> &str {
+        self.device.as_str()
+    }
+
+    async fn place(&self) -> &str {
+        self.place.as_str()
+    }
+
+    async fn percent(&self) -> String {
+        format!("{}", &self.percent)
+    }
+
+    async fn timestamp(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+
+    async fn low(&self) -> bool {
+        self.percent < *LOW_BATTERY_PERCENT
+    }
+}
+
+pub async fn battery(dynamodb: &Client, device: &Device) -> Result<Option<DeviceBattery>> {
+    let prefix = PlaceCondition::sk_prefix();
+    let condition: Option<ConditionBattery> = dynamodb
+        .get_last_item(&device.id, Condition::BeginsWith(prefix.clone()))
+        .await?;
+    let condition = condition.and_then(|x| {
+        let timestamp = x.sk.strip_prefix(prefix.as_str())?.parse().ok()?;
+        Some((x.battery_percent?, timestamp))
+    });
+    let reading = match condition {
+        Some(x) => Some(x),
+        None => {
+            let state: Option<BatteryState> = dynamodb
+                .get_last_item(&device.id, Condition::BeginsWith(BatteryState::sk_prefix()))
+                .await?;
+            state.map(|x| (x.percent, x.timestamp))
+        }
+    };
+
+    Ok(reading.map(|(percent, timestamp)| DeviceBattery {
+        device: device.id.clone(),
+        place: device.place.clone(),
+        percent,
+        timestamp,
+    }))
+}
+
+pub async fn batteries(dynamodb: &Client) -> Result<Vec<DeviceBattery>> {
+    let devices: Vec<Device> = live(dynamodb.get_range("DEVICE", None).await?);
+    let mut batteries = Vec::new();
+
+    for device in devices.iter() {
+        if let Some(x) = battery(dynamodb, device).await? {
+            batteries.push(x);
+        }
+    }
+
+    Ok(batteries)
+}
+
+async fn needs_attention(
+    dynamodb: &Client,
+    device: &Device,
+    now: DateTime<Utc>,
+) -> Result<Option<String>> {
+    let seen = match device.last_seen_at {
+        Some(x) => Some(x),
+        None => last_seen(dynamodb, &device.id).await?,
+    };
+    match seen {
+        Some(x) if now - x <= Duration::minutes(*OFFLINE_MINUTES) => (),
+        _ => return Ok(Some("offline".to_owned())),
+    }
+
+    let battery = battery(dynamodb, device).await?;
+    if battery.map_or(false, |x| x.percent < *LOW_BATTERY_PERCENT) {
+        return Ok(Some("low_battery".to_owned()));
+    }
+
+    Ok(None)
+}
+
+pub async fn refresh(dynamodb: &Client, now: DateTime<Utc>) -> Result<usize> {
+    let devices: Vec<Device> = live(dynamodb.get_range("DEVICE", None).await?);
+    let mut count = 0;
+
+    for mut device in devices {
+        let reason = needs_attention(dynamodb, &device, now).await?;
+        if reason.is_some() {
+            count += 1;
+        }
+        if reason != device.needs_attention {
+            // A device edited in the meantime is looked at again next time.
+            let version = device.version;
+            device.needs_attention = reason.clone();
+            device.version += 1;
+            if let Err(e) = dynamodb.put_item_versioned(&device, version).await {
+                if is_conflict(&e) {
+                    continue;
+                }
+                return Err(e);
+            }
+            if let Some(reason) = reason {
+                let data = json!({ "device": device.id, "place": device.place, "reason": reason });
+                if let Err(e) = delivery::publish(dynamodb, EventKind::DeviceAttention, data).await
+                {
+                    println!("{:?}", e);
+                }
+            }
+        }
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::dynamodb::{Client, Condition};
+use crate::models::{DynamoItem, FieldChange, Revision};
+use crate::request_id;
+
+// Item kinds with recorded revisions.
+const KINDS: [&str; 3] = ["DEVICE", "PLACE", "TARIFF"];
+// Keys and bookkeeping that change without anyone changing the configuration.
+const IGNORED: [&str; 4] = ["pk", "sk", "version", "last_seen_at"];
+// Recorded as changed, but without the values.
+const REDACTED: [&str; 1] = ["secret"];
+const ATTEMPTS: usize = 3;
+
+fn fields<S: Serialize>(item: Option<&S>) -> Result<Map<String, Value>> {
+    match item.map(serde_json::to_value).transpose()? {
+        Some(Value::Object(x)) => Ok(x),
+        _ => Ok(Map::new()),
+    }
+}
+
+// Attributes that differ between the two versions of an item. Unset optional
+// attributes are left out when serialized, so they read as null here.
+pub fn diff<S: Serialize>(before: Option<&S>, after: &S) -> Result<Vec<FieldChange>> {
+    let before = fields(before)?;
+    let after = fields(Some(after))?;
+    let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    Ok(keys
+        .into_iter()
+        .filter(|x| !IGNORED.contains(&x.as_str()))
+        .filter(|x| before.get(*x) != after.get(*x))
+        .map(|x| {
+            let redacted = REDACTED.contains(&x.as_str());
+            let value =
+                |item: &Map<String, Value>| item.get(x).filter(|_| !redacted).map(Value::to_string);
+            FieldChange {
+                field: x.to_owned(),
+                before: value(&before),
+                after: value(&after),
+            }
+        })
+        .collect())
+}
+
+// The next revision number is read from the last one, so a concurrent update
+// taking the same number makes this one retry with the following number.
+async fn try_record<S>(
+    dynamodb: &Client,
+    before: Option<&S>,
+    after: &S,
+    by: Option<String>,
+) -> Result<()>
+where
+    S: Serialize + DynamoItem,
+{
+    let changes = diff(before, after)?;
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let (kind, id) = (after.pk(), after.sk_value());
+    let pk = format!("{}#{}", kind, id);
+
+    for _ in 0..ATTEMPTS {
+        let last: Option<Revision> = dynamodb
+            .get_last_item(&pk, Condition::BeginsWith(Revision::sk_prefix()))
+            .await?;
+        let revision = Revision::new(
+            kind.clone(),
+            id.clone(),
+            last.map_or(1, |x| x.revision + 1),
+            by.clone(),
+            request_id::current(),
+            changes.clone(),
+        );
+        if dynamodb.put_item_if_absent(&revision).await? {
+            return Ok(());
+        }
+    }
+
+    Err(anyhow!("no free revision number for {}", pk))
+}
+
+// Records the update from `before` to `after` unless nothing changed. It is
+// called once the update is written and stands, so a failure is logged
+// instead of failing the request.
+pub async fn record<S>(dynamodb: &Client, before: Option<&S>, after: &S, by: Option<String>)
+where
+    S: Serialize + DynamoItem,
+{
+    if let Err(e) = try_record(dynamodb, before, after, by).await {
+        let item = format!("{}#{}", after.pk(), after.sk_value());
+        match request_id::current() {
+            Some(id) => println!("request {}: history of {}: {:?}", id, item, e),
+            None => println!("history of {}: {:?}", item, e),
+        }
+    }
+}
+
+// Revisions of every device, place and tariff with the id, oldest first.
+pub async fn history(dynamodb: &Client, id: &str) -> Result<Vec<Revision>> {
+    let mut revisions = Vec::new();
+    for kind in KINDS.iter() {
+        let items: Vec<Revision> = dynamodb
+            .get_range(
+                &format!("{}#{}", kind, id),
+                Some(Condition::BeginsWith(Revision::sk_prefix())),
+            )
+            .await?;
+        revisions.extend(items);
+    }
+    revisions.sort_by_key(|x| x.changed_at);
+    Ok(revisions)
+}
@@ -0,0 +1,37 @@
+use anyhow::Result;
+
+use crate::dynamodb::Client;
+use crate::error::is_conflict;
+use crate::models::HomeMode;
+
+const ATTEMPTS: usize = 3;
+
+pub async fn get(dynamodb: &Client) -> Result<HomeMode> {
+    Ok(dynamodb
+        .find_item("HOME_MODE", "current")
+        .await?
+        .unwrap_or_default())
+}
+
+// The mode is one item written by both the API and presence-import, so a
+// change is applied to the stored version and applied again to the newer one
+// if another write got in between. `change` returns false to leave it as is.
+pub async fn update<F>(dynamodb: &Client, change: F) -> Result<HomeMode>
+where
+    F: Fn(&mut HomeMode) -> bool,
+{
+    let mut attempt = 1;
+    loop {
+        let mut home = get(dynamodb).await?;
+        if !change(&mut home) {
+            return Ok(home);
+        }
+        let version = home.version;
+        home.version += 1;
+        match dynamodb.put_item_versioned(&home, version).await {
+            Ok(()) => return Ok(home),
+            Err(e) if is_conflict(&e) && attempt < ATTEMPTS => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
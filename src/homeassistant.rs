@@ -0,0 +1,170 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::dynamodb::{Client, Condition};
+use crate::models::{live, Device, DynamoItem, Electricity, PlaceCondition};
+
+#[derive(Debug, Serialize)]
+pub struct Sensor {
+    pub unique_id: String,
+    pub name: String,
+    pub device_id: String,
+    pub place: String,
+    pub metric: &'static str,
+    pub state: String,
+    pub unit_of_measurement: Option<&'static str>,
+    pub device_class: Option<&'static str>,
+    pub state_class: &'static str,
+    pub last_updated: DateTime<Utc>,
+}
+
+impl Sensor {
+    fn new(
+        device: &Device,
+        metric: &'static str,
+        state: String,
+        unit_of_measurement: Option<&'static str>,
+        device_class: Option<&'static str>,
+        state_class: &'static str,
+        last_updated: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            unique_id: format!("homeapi_{}_{}", device.id, metric),
+            name: format!("{} {}", device.place, metric.replace('_', " ")),
+            device_id: device.id.clone(),
+            place: device.place.clone(),
+            metric,
+            state,
+            unit_of_measurement,
+            device_class,
+            state_class,
+            last_updated,
+        }
+    }
+}
+
+fn electricity_sensors(device: &Device, x: &Electricity) -> Vec<Sensor> {
+    vec![
+        Sensor::new(
+            device,
+            "power",
+            format!("{}", x.current_w),
+            Some("W"),
+            Some("power"),
+            "measurement",
+            x.timestamp,
+        ),
+        Sensor::new(
+            device,
+            "energy",
+            format!("{}", x.cumulative_kwh_p),
+            Some("kWh"),
+            Some("energy"),
+            "total_increasing",
+            x.timestamp,
+        ),
+        Sensor::new(
+            device,
+            "energy_returned",
+            format!("{}", x.cumulative_kwh_n),
+            Some("kWh"),
+            Some("energy"),
+            "total_increasing",
+            x.timestamp,
+        ),
+    ]
+}
+
+fn place_condition_sensors(device: &Device, x: &PlaceCondition) -> Vec<Sensor> {
+    let mut sensors = Vec::new();
+
+    if let Some(v) = x.temperature {
+        sensors.push(Sensor::new(
+            device,
+            "temperature",
+            format!("{}", v),
+            Some("°C"),
+            Some("temperature"),
+            "measurement",
+            x.timestamp,
+        ));
+    }
+    if let Some(v) = x.humidity {
+        sensors.push(Sensor::new(
+            device,
+            "humidity",
+            format!("{}", v),
+            Some("%"),
+            Some("humidity"),
+            "measurement",
+            x.timestamp,
+        ));
+    }
+    if let Some(v) = x.illuminance {
+        sensors.push(Sensor::new(
+            device,
+            "illuminance",
+            format!("{}", v),
+            Some("lx"),
+            Some("illuminance"),
+            "measurement",
+            x.timestamp,
+        ));
+    }
+    if let Some(v) = x.motion {
+        sensors.push(Sensor::new(
+            device,
+            "motion",
+            format!("{}", v),
+            None,
+            None,
+            "measurement",
+            x.timestamp,
+        ));
+    }
+    if let Some(v) = x.battery_percent {
+        sensors.push(Sensor::new(
+            device,
+            "battery",
+            format!("{}", v),
+            Some("%"),
+            Some("battery"),
+            "measurement",
+            x.timestamp,
+        ));
+    }
+
+    sensors
+}
+
+pub async fn device_sensors(dynamodb: &Client, device: &Device) -> Result<Vec<Sensor>> {
+    let prefix = Electricity::sk_prefix();
+    let sk = || Condition::BeginsWith(prefix.clone());
+
+    if let Ok(Some(x)) = dynamodb
+        .get_last_item::<Electricity>(&device.id, sk())
+        .await
+    {
+        return Ok(electricity_sensors(device, &x));
+    }
+
+    match dynamodb
+        .get_last_item::<PlaceCondition>(&device.id, sk())
+        .await?
+    {
+        Some(x) => Ok(place_condition_sensors(device, &x)),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub async fn sensors(dynamodb: &Client) -> Result<Vec<Sensor>> {
+    let devices: Vec<Device> = live(dynamodb.get_range("DEVICE", None).await?);
+
+    let mut sensors = Vec::new();
+    for device in devices.iter() {
+        sensors.extend(device_sensors(dynamodb, device).await?);
+    }
+
+    Ok(sensors)
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{Duration, Utc};
+
+use crate::dynamodb::{attr_number, Client};
+use crate::models::IdempotencyRecord;
+
+// Long enough to outlast any client retry loop. Expired records are removed by
+// DynamoDB TTL, which can lag, so the expiry is checked on read as well.
+const TTL_HOURS: i64 = 24;
+// A claim left behind by a request that never finished can be taken over after
+// this long.
+const CLAIM_MINUTES: i64 = 5;
+
+pub enum Claim {
+    // The caller runs the request and then records or releases the key.
+    Claimed,
+    // Another request with the key is still running.
+    InProgress,
+    // The stored response of the request that ran first.
+    Done(String),
+}
+
+// Claims the key with an in-progress marker before the request runs, so that
+// concurrent retries don't run it a second time.
+pub async fn claim(dynamodb: &Client, key: &str) -> Result<Claim> {
+    let now = Utc::now();
+    let marker =
+        IdempotencyRecord::new(key.to_owned(), None, now + Duration::minutes(CLAIM_MINUTES));
+    let mut params = HashMap::new();
+    params.insert(":now".to_owned(), attr_number(now.timestamp()));
+    if dynamodb
+        .put_item_if(&marker, "attribute_not_exists(sk) OR #ttl <= :now", params)
+        .await?
+    {
+        return Ok(Claim::Claimed);
+    }
+
+    let record: Option<IdempotencyRecord> = dynamodb.find_item("IDEMPOTENCY", key).await?;
+    Ok(match record.and_then(|x| x.response) {
+        Some(x) => Claim::Done(x),
+        None => Claim::InProgress,
+    })
+}
+
+pub async fn record(dynamodb: &Client, key: &str, response: &str) -> Result<()> {
+    let record = IdempotencyRecord::new(
+        key.to_owned(),
+        Some(response.to_owned()),
+        Utc::now() + Duration::hours(TTL_HOURS),
+    );
+    dynamodb.put_item(&record).await
+}
+
+// Gives up a claim so that a retry can run the request again.
+pub async fn release(dynamodb: &Client, key: &str) -> Result<()> {
+    dynamodb.delete_item("IDEMPOTENCY", key).await
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use once_cell::sync::Lazy;
+use serde_json::{Map, Value};
+
+use crate::dynamodb::Client;
+use crate::error::{ApiError, Code};
+use crate::models::Device;
+use crate::signature::{self, Signature};
+use crate::webhook;
+
+// Maps measurement names to webhook kinds, e.g. "power=electricity,env=
+// place_condition". A measurement named after a kind always maps to it.
+static MEASUREMENTS: Lazy<HashMap<String, String>> = Lazy::new(|| {
+    std::env::var("INFLUX_MEASUREMENTS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|x| x.split_once('='))
+        .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
+        .collect()
+});
+
+fn kind(measurement: &str) -> &str {
+    MEASUREMENTS
+        .get(measurement)
+        .map_or(measurement, String::as_str)
+}
+
+// Splits at `sep` unless it is escaped with a backslash or inside a quoted
+// field value.
+fn split(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut quoted = false;
+
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == sep && !quoted {
+            parts.push(&s[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&s[start..]);
+
+    parts
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+fn pair(s: &str) -> Result<(String, &str)> {
+    match split(s, '=').as_slice() {
+        [k, v] if !k.is_empty() => Ok((unescape(k), *v)),
+        _ => Err(anyhow!("invalid key=value: {}", s)),
+    }
+}
+
+fn field_value(s: &str) -> Result<Value> {
+    if let Some(x) = s.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        return Ok(Value::from(unescape(x)));
+    }
+    if let Some(x) = s.strip_suffix('i').or_else(|| s.strip_suffix('u')) {
+        return Ok(Value::from(x.parse::<i64>()?));
+    }
+    match s {
+        "t" | "T" | "true" | "True" | "TRUE" => Ok(Value::from(true)),
+        "f" | "F" | "false" | "False" | "FALSE" => Ok(Value::from(false)),
+        s => {
+            let x = s.parse::<f64>()?;
+            // Whole floats are stored as integers so that integer model
+            // fields accept them, as with JSON webhooks.
+            if x.fract() == 0.0 && x.abs() < i64::MAX as f64 {
+                Ok(Value::from(x as i64))
+            } else {
+                Ok(Value::from(x))
+            }
+        }
+    }
+}
+
+fn timestamp(s: Option<&str>, precision: &str) -> Result<DateTime<Utc>> {
+    let n = match s {
+        Some(x) => x.parse::<i64>()?,
+        None => return Ok(Utc::now()),
+    };
+    let per_second: i64 = match precision {
+        "ns" | "n" => 1_000_000_000,
+        "us" | "u" => 1_000_000,
+        "ms" => 1_000,
+        "s" => 1,
+        x => return Err(anyhow!("unknown precision: {}", x)),
+    };
+    let nanos = (n.rem_euclid(per_second) * (1_000_000_000 / per_second)) as u32;
+
+    Utc.timestamp_opt(n.div_euclid(per_second), nanos)
+        .single()
+        .ok_or_else(|| anyhow!("timestamp out of range: {}", n))
+}
+
+// Parses one line into a webhook kind and the item to write. The "device" tag
+// becomes the item's key and the optional "place" tag its place; other tags
+// are ignored. Fields are taken as model attributes as they are.
+fn parse_line(line: &str, precision: &str) -> Result<(String, Map<String, Value>)> {
+    let parts = split(line, ' ');
+    let (series, fields, time) = match parts.as_slice() {
+        [series, fields] => (*series, *fields, None),
+        [series, fields, time] => (*series, *fields, Some(*time)),
+        _ => return Err(anyhow!("expected measurement, fields and timestamp")),
+    };
+
+    let mut series = split(series, ',').into_iter();
+    let measurement = unescape(series.next().unwrap_or_default());
+    let kind = kind(&measurement).to_owned();
+    let prefix = webhook::sk_prefix(&kind)?;
+    let timestamp = timestamp(time, precision)?;
+
+    let mut item = Map::new();
+    for tag in series {
+        let (k, v) = pair(tag)?;
+        if k == "device" {
+            item.insert("pk".to_owned(), Value::from(unescape(v)));
+        } else if k == "place" {
+            item.insert("place".to_owned(), Value::from(unescape(v)));
+        }
+    }
+    if !item.contains_key("pk") {
+        return Err(anyhow!("missing device tag"));
+    }
+    item.insert(
+        "sk".to_owned(),
+        Value::from(format!("{}{:?}", prefix, timestamp)),
+    );
+    for field in split(fields, ',') {
+        let (k, v) = pair(field)?;
+        item.insert(k, field_value(v)?);
+    }
+
+    Ok((kind, item))
+}
+
+// Checks the body as webhook ingestion does: a signed body may only carry
+// readings for the device that signed it, and devices with a secret only
+// accept signed readings.
+async fn authorize(
+    dynamodb: &Client,
+    signature: Option<Signature>,
+    body: &[u8],
+    items: &HashMap<String, Vec<Map<String, Value>>>,
+) -> Result<()> {
+    let mut ids = items
+        .values()
+        .flatten()
+        .map(|x| x["pk"].as_str().unwrap_or_default().to_owned())
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+
+    if let Some(signature) = signature {
+        signature::verify(dynamodb, &signature, body).await?;
+        if ids.iter().any(|x| *x != signature.device) {
+            return Err(ApiError::new(Code::Unauthorized, "readings for another device").into());
+        }
+        return Ok(());
+    }
+
+    let keys = ids
+        .iter()
+        .map(|x| ("DEVICE".to_owned(), x.clone()))
+        .collect::<Vec<_>>();
+    let devices: Vec<Option<Device>> = dynamodb.batch_get_items(&keys).await?;
+    if devices.iter().flatten().any(|x| x.secret.is_some()) {
+        return Err(ApiError::new(Code::Unauthorized, "signature required").into());
+    }
+
+    Ok(())
+}
+
+// Lines are grouped by kind and written in order of first appearance. A parse
+// error anywhere rejects the whole body before anything is written.
+pub async fn write(
+    dynamodb: &Client,
+    body: &[u8],
+    precision: &str,
+    signature: Option<Signature>,
+) -> Result<usize> {
+    let mut kinds: Vec<String> = Vec::new();
+    let mut items: HashMap<String, Vec<Map<String, Value>>> = HashMap::new();
+
+    for (i, line) in String::from_utf8_lossy(body).lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (kind, item) = parse_line(line, precision)
+            .map_err(|e| ApiError::new(Code::ValidationFailed, format!("line {}: {}", i + 1, e)))?;
+        if !items.contains_key(&kind) {
+            kinds.push(kind.clone());
+        }
+        items.entry(kind).or_default().push(item);
+    }
+    authorize(dynamodb, signature, body, &items).await?;
+
+    let mut count = 0;
+    for kind in kinds {
+        let items = items.remove(&kind).unwrap_or_default();
+        count += webhook::write(dynamodb, "influx", &kind, items).await?;
+    }
+
+    Ok(count)
+}
@@ -1,3 +1,42 @@
+pub mod aggregation;
+pub mod alerts;
+pub mod alexa;
+pub mod automation;
+pub mod backup;
+pub mod billing;
+pub mod carbon;
+pub mod codegen;
+pub mod comfort;
+pub mod cors;
+pub mod delivery;
+pub mod demand;
 pub mod dynamodb;
+pub mod echonet;
+pub mod error;
+pub mod firehose;
+pub mod google;
+pub mod graphiql;
 pub mod graphql;
+pub mod guard;
+pub mod health;
+pub mod history;
+pub mod home_mode;
+pub mod homeassistant;
+pub mod idempotency;
+pub mod influx;
+pub mod memory;
+pub mod metrics;
 pub mod models;
+pub mod notify;
+pub mod occupancy;
+pub mod openapi;
+pub mod prometheus;
+pub mod provisioning;
+pub mod report;
+pub mod request_id;
+pub mod signature;
+pub mod sqlite;
+pub mod storage;
+pub mod upload;
+pub mod validate;
+pub mod webhook;
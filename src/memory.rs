@@ -0,0 +1,961 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeValue, BatchGetItemError, BatchGetItemInput, BatchGetItemOutput, BatchWriteItemError,
+    BatchWriteItemInput, BatchWriteItemOutput, CreateBackupError, CreateBackupInput,
+    CreateBackupOutput, DeleteItemError, DeleteItemInput, DeleteItemOutput, GetItemError,
+    GetItemInput, GetItemOutput, PutItemError, PutItemInput, PutItemOutput, QueryError, QueryInput,
+    QueryOutput, UpdateItemError, UpdateItemInput, UpdateItemOutput,
+};
+
+use crate::storage::Storage;
+
+pub(crate) type Item = HashMap<String, AttributeValue>;
+
+// An in-process Storage for tests and local runs. It understands the subset
+// of DynamoDB expressions that Client generates: comparisons, BETWEEN,
+// AND/OR/NOT, begins_with, contains, attribute_exists and
+// attribute_not_exists, and SET-only update expressions.
+#[derive(Default)]
+pub struct MemoryStorage {
+    tables: Mutex<HashMap<String, BTreeMap<(String, String), Item>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Op(String),
+    Open,
+    Close,
+    Comma,
+}
+
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(String, String, String),
+    Between(String, String, String),
+    Function(String, Vec<String>),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' {
+            chars.next();
+            tokens.push(Token::Open);
+        } else if c == ')' {
+            chars.next();
+            tokens.push(Token::Close);
+        } else if c == ',' {
+            chars.next();
+            tokens.push(Token::Comma);
+        } else if "=<>".contains(c) {
+            let mut op = String::new();
+            while let Some(&c) = chars.peek().filter(|c| "=<>".contains(**c)) {
+                op.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        } else if c.is_alphanumeric() || "_#:.".contains(c) {
+            let mut word = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_alphanumeric() || "_#:.".contains(**c))
+            {
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        } else {
+            return Err(format!("unexpected {:?} in {}", c, s));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(x)) if x.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1)
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        match self.next() {
+            Some(x) if *x == token => Ok(()),
+            x => Err(format!("expected {:?}, found {:?}", token, x)),
+        }
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(x)) => Ok(x.clone()),
+            x => Err(format!("expected operand, found {:?}", x)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek_keyword("OR") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek_keyword("AND") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek_keyword("NOT") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.tokens.get(self.pos) == Some(&Token::Open) {
+            self.pos += 1;
+            let expr = self.or()?;
+            self.expect(Token::Close)?;
+            return Ok(expr);
+        }
+
+        let word = self.word()?;
+        if self.tokens.get(self.pos) == Some(&Token::Open) {
+            self.pos += 1;
+            let mut args = vec![self.word()?];
+            while self.tokens.get(self.pos) == Some(&Token::Comma) {
+                self.pos += 1;
+                args.push(self.word()?);
+            }
+            self.expect(Token::Close)?;
+            return Ok(Expr::Function(word.to_lowercase(), args));
+        }
+        if self.peek_keyword("BETWEEN") {
+            self.pos += 1;
+            let low = self.word()?;
+            if !self.peek_keyword("AND") {
+                return Err("expected AND in BETWEEN".to_owned());
+            }
+            self.pos += 1;
+            return Ok(Expr::Between(word, low, self.word()?));
+        }
+        match self.next() {
+            Some(Token::Op(op)) => {
+                let op = op.clone();
+                Ok(Expr::Compare(word, op, self.word()?))
+            }
+            x => Err(format!("expected comparison, found {:?}", x)),
+        }
+    }
+}
+
+fn parse(s: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(s)?,
+        pos: 0,
+    };
+    let expr = parser.or()?;
+    if parser.pos != parser.tokens.len() {
+        return Err(format!("unexpected trailing input in {}", s));
+    }
+    Ok(expr)
+}
+
+struct Scope<'a> {
+    names: &'a HashMap<String, String>,
+    values: &'a HashMap<String, AttributeValue>,
+}
+
+impl Scope<'_> {
+    fn get<'b>(&'b self, item: &'b Item, operand: &str) -> Option<&'b AttributeValue> {
+        if operand.starts_with(':') {
+            return self.values.get(operand);
+        }
+        if operand.starts_with('#') {
+            return self.names.get(operand).and_then(|x| item.get(x));
+        }
+        item.get(operand)
+    }
+
+    fn eval(&self, expr: &Expr, item: &Item) -> bool {
+        let get = |x: &str| self.get(item, x);
+        match expr {
+            Expr::And(a, b) => self.eval(a, item) && self.eval(b, item),
+            Expr::Or(a, b) => self.eval(a, item) || self.eval(b, item),
+            Expr::Not(a) => !self.eval(a, item),
+            Expr::Compare(a, op, b) => match (get(a), get(b)) {
+                (Some(a), Some(b)) => compare(a, op, b),
+                _ => false,
+            },
+            Expr::Between(a, low, high) => match (get(a), get(low), get(high)) {
+                (Some(a), Some(low), Some(high)) => compare(a, ">=", low) && compare(a, "<=", high),
+                _ => false,
+            },
+            Expr::Function(f, args) => self.function(f, args, item),
+        }
+    }
+
+    fn function(&self, f: &str, args: &[String], item: &Item) -> bool {
+        let arg = |i: usize| args.get(i).and_then(|x| self.get(item, x));
+        match f {
+            "attribute_exists" => arg(0).is_some(),
+            "attribute_not_exists" => arg(0).is_none(),
+            "begins_with" => match (arg(0).and_then(|x| x.s.as_ref()), arg(1)) {
+                (Some(a), Some(b)) => b.s.as_ref().map_or(false, |b| a.starts_with(b.as_str())),
+                _ => false,
+            },
+            "contains" => match (arg(0), arg(1)) {
+                (Some(a), Some(b)) => contains(a, b),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+fn order(a: &AttributeValue, b: &AttributeValue) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (&a.s, &b.s) {
+        return Some(a.cmp(b));
+    }
+    if let (Some(a), Some(b)) = (&a.n, &b.n) {
+        return a.parse::<f64>().ok()?.partial_cmp(&b.parse::<f64>().ok()?);
+    }
+    if a == b {
+        return Some(Ordering::Equal);
+    }
+    None
+}
+
+fn compare(a: &AttributeValue, op: &str, b: &AttributeValue) -> bool {
+    let ordering = order(a, b);
+    match op {
+        "=" => ordering == Some(Ordering::Equal),
+        "<>" => ordering != Some(Ordering::Equal),
+        "<" => ordering == Some(Ordering::Less),
+        "<=" => matches!(ordering, Some(Ordering::Less) | Some(Ordering::Equal)),
+        ">" => ordering == Some(Ordering::Greater),
+        ">=" => matches!(ordering, Some(Ordering::Greater) | Some(Ordering::Equal)),
+        _ => false,
+    }
+}
+
+fn contains(a: &AttributeValue, b: &AttributeValue) -> bool {
+    if let (Some(a), Some(b)) = (&a.s, &b.s) {
+        return a.contains(b.as_str());
+    }
+    if let (Some(a), Some(b)) = (&a.ss, &b.s) {
+        return a.contains(b);
+    }
+    if let Some(a) = &a.l {
+        return a.contains(b);
+    }
+    false
+}
+
+pub(crate) fn key(item: &Item) -> Option<(String, String)> {
+    let get = |name: &str| item.get(name).and_then(|x| x.s.clone());
+    Some((get("pk")?, get("sk")?))
+}
+
+pub(crate) fn check(
+    condition: &Option<String>,
+    item: &Item,
+    names: &Option<HashMap<String, String>>,
+    values: &Option<HashMap<String, AttributeValue>>,
+) -> Result<bool, String> {
+    let condition = match condition {
+        Some(x) => parse(x)?,
+        None => return Ok(true),
+    };
+    let (names, values) = (
+        names.clone().unwrap_or_default(),
+        values.clone().unwrap_or_default(),
+    );
+    let scope = Scope {
+        names: &names,
+        values: &values,
+    };
+    Ok(scope.eval(&condition, item))
+}
+
+pub(crate) fn old_values(return_values: &Option<String>, item: Option<Item>) -> Option<Item> {
+    match return_values.as_deref() {
+        Some("ALL_OLD") => item,
+        _ => None,
+    }
+}
+
+// The bounds a key condition puts on the partition and sort keys, which a
+// backend can use to narrow the candidates it reads before select runs. Each
+// bound is a value and whether it is inclusive.
+pub(crate) struct KeyRange {
+    pub pk: String,
+    pub low: Option<(String, bool)>,
+    pub high: Option<(String, bool)>,
+    pub prefix: Option<String>,
+}
+
+fn conjuncts<'a>(expr: &'a Expr, out: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::And(a, b) => {
+            conjuncts(a, out);
+            conjuncts(b, out);
+        }
+        x => out.push(x),
+    }
+}
+
+pub(crate) fn key_range(input: &QueryInput) -> Result<KeyRange, String> {
+    let names = input.expression_attribute_names.clone().unwrap_or_default();
+    let values = input
+        .expression_attribute_values
+        .clone()
+        .unwrap_or_default();
+    let (pk_name, sk_name) = match &input.index_name {
+        Some(x) => (format!("{}pk", x), format!("{}sk", x)),
+        None => ("pk".to_owned(), "sk".to_owned()),
+    };
+    let name = |x: &str| names.get(x).cloned().unwrap_or_else(|| x.to_owned());
+    let value = |x: &str| {
+        values
+            .get(x)
+            .and_then(|x| x.s.clone())
+            .ok_or_else(|| format!("no string value for {}", x))
+    };
+
+    let key_condition = parse(
+        input
+            .key_condition_expression
+            .as_deref()
+            .unwrap_or_default(),
+    )?;
+    let mut terms = Vec::new();
+    conjuncts(&key_condition, &mut terms);
+
+    let mut pk = None;
+    let mut range = (None, None, None);
+    for term in terms {
+        match term {
+            Expr::Compare(a, op, b) if name(a) == pk_name && op == "=" => pk = Some(value(b)?),
+            Expr::Compare(a, op, b) if name(a) == sk_name => match op.as_str() {
+                "=" => {
+                    range.0 = Some((value(b)?, true));
+                    range.1 = Some((value(b)?, true));
+                }
+                ">=" => range.0 = Some((value(b)?, true)),
+                ">" => range.0 = Some((value(b)?, false)),
+                "<=" => range.1 = Some((value(b)?, true)),
+                "<" => range.1 = Some((value(b)?, false)),
+                _ => return Err(format!("unsupported key condition operator: {}", op)),
+            },
+            Expr::Between(a, low, high) if name(a) == sk_name => {
+                range.0 = Some((value(low)?, true));
+                range.1 = Some((value(high)?, true));
+            }
+            Expr::Function(f, args) if f == "begins_with" && args.len() == 2 => {
+                if name(&args[0]) != sk_name {
+                    return Err(format!("begins_with on {} in a key condition", args[0]));
+                }
+                range.2 = Some(value(&args[1])?);
+            }
+            _ => return Err("unsupported key condition".to_owned()),
+        }
+    }
+
+    Ok(KeyRange {
+        pk: pk.ok_or_else(|| format!("no {} in the key condition", pk_name))?,
+        low: range.0,
+        high: range.1,
+        prefix: range.2,
+    })
+}
+
+// Runs a query over candidate items in any order, which must include every
+// item the key condition can match.
+pub(crate) fn select<'a, I>(input: QueryInput, items: I) -> Result<QueryOutput, String>
+where
+    I: IntoIterator<Item = &'a Item>,
+{
+    let names = input.expression_attribute_names.unwrap_or_default();
+    let values = input.expression_attribute_values.unwrap_or_default();
+    let key_condition = parse(
+        input
+            .key_condition_expression
+            .as_deref()
+            .unwrap_or_default(),
+    )?;
+    let filter = input.filter_expression.as_deref().map(parse).transpose()?;
+    let scope = Scope {
+        names: &names,
+        values: &values,
+    };
+
+    let (index_pk, index_sk) = match &input.index_name {
+        Some(x) => (format!("{}pk", x), format!("{}sk", x)),
+        None => ("pk".to_owned(), "sk".to_owned()),
+    };
+
+    let mut items = items
+        .into_iter()
+        .filter(|x| scope.eval(&key_condition, x))
+        .collect::<Vec<_>>();
+    let sort_key = |x: &Item| x.get(&index_sk).and_then(|x| x.s.clone());
+    items.sort_by(|a, b| (sort_key(a), key(a)).cmp(&(sort_key(b), key(b))));
+    let forward = input.scan_index_forward != Some(false);
+    if !forward {
+        items.reverse();
+    }
+    // Like DynamoDB, resume after the start key's position even if that item
+    // has been deleted since the previous page.
+    if let Some(start) = &input.exclusive_start_key {
+        let start = (sort_key(start), key(start));
+        let after = if forward {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        };
+        items.retain(|x| (sort_key(x), key(x)).cmp(&start) == after);
+    }
+    let more = input.limit.map_or(false, |x| items.len() > x as usize);
+    if let Some(limit) = input.limit {
+        items.truncate(limit as usize);
+    }
+    let last_evaluated_key = if more {
+        items.last().map(|x| {
+            ["pk", "sk", index_pk.as_str(), index_sk.as_str()]
+                .iter()
+                .filter_map(|k| x.get(*k).map(|v| (k.to_string(), v.clone())))
+                .collect::<Item>()
+        })
+    } else {
+        None
+    };
+    let scanned_count = items.len() as i64;
+
+    let mut items = items
+        .into_iter()
+        .filter(|x| filter.as_ref().map_or(true, |f| scope.eval(f, x)))
+        .cloned()
+        .collect::<Vec<_>>();
+    if let Some(projection) = &input.projection_expression {
+        let keep = projection
+            .split(',')
+            .map(|x| x.trim())
+            .map(|x| names.get(x).map_or(x, String::as_str).to_owned())
+            .collect::<Vec<_>>();
+        for item in items.iter_mut() {
+            item.retain(|k, _| keep.contains(k));
+        }
+    }
+
+    let count = items.len() as i64;
+    let items = match input.select.as_deref() {
+        Some("COUNT") => None,
+        _ => Some(items),
+    };
+
+    Ok(QueryOutput {
+        count: Some(count),
+        items,
+        last_evaluated_key,
+        scanned_count: Some(scanned_count),
+        ..Default::default()
+    })
+}
+
+// Applies a SET-only update expression.
+pub(crate) fn update(item: &mut Item, input: &UpdateItemInput) -> Result<(), String> {
+    let update = input.update_expression.clone().unwrap_or_default();
+    let assignments = match update.trim().get(..4) {
+        Some(x) if x.eq_ignore_ascii_case("SET ") => &update.trim()[4..],
+        _ => return Err(format!("unsupported: {}", update)),
+    };
+    let names = input.expression_attribute_names.clone().unwrap_or_default();
+    let values = input
+        .expression_attribute_values
+        .clone()
+        .unwrap_or_default();
+    for assignment in assignments.split(',') {
+        let (name, value) = assignment
+            .split_once('=')
+            .map(|(a, b)| (a.trim(), b.trim()))
+            .ok_or_else(|| format!("unsupported: {}", update))?;
+        let name = names.get(name).map_or(name, String::as_str);
+        let value = values
+            .get(value)
+            .ok_or_else(|| format!("no value for {}", value))?;
+        item.insert(name.to_owned(), value.clone());
+    }
+
+    Ok(())
+}
+
+pub(crate) const CONDITION_FAILED: &str = "The conditional request failed";
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get_item(
+        &self,
+        input: GetItemInput,
+    ) -> Result<GetItemOutput, RusotoError<GetItemError>> {
+        let key = key(&input.key).ok_or_else(|| RusotoError::Validation("no key".to_owned()))?;
+        let tables = self.tables.lock().unwrap();
+        let item = tables
+            .get(&input.table_name)
+            .and_then(|x| x.get(&key))
+            .cloned();
+
+        Ok(GetItemOutput {
+            item,
+            ..Default::default()
+        })
+    }
+
+    async fn batch_get_item(
+        &self,
+        input: BatchGetItemInput,
+    ) -> Result<BatchGetItemOutput, RusotoError<BatchGetItemError>> {
+        let tables = self.tables.lock().unwrap();
+        let mut responses = HashMap::new();
+
+        for (name, request) in input.request_items {
+            let items = request
+                .keys
+                .iter()
+                .filter_map(key)
+                .filter_map(|x| tables.get(&name).and_then(|table| table.get(&x)))
+                .cloned()
+                .collect();
+            responses.insert(name, items);
+        }
+
+        Ok(BatchGetItemOutput {
+            responses: Some(responses),
+            ..Default::default()
+        })
+    }
+
+    async fn query(&self, input: QueryInput) -> Result<QueryOutput, RusotoError<QueryError>> {
+        let tables = self.tables.lock().unwrap();
+        let items = tables
+            .get(&input.table_name)
+            .into_iter()
+            .flat_map(|x| x.values());
+        select(input, items).map_err(RusotoError::Validation)
+    }
+
+    async fn put_item(
+        &self,
+        input: PutItemInput,
+    ) -> Result<PutItemOutput, RusotoError<PutItemError>> {
+        let key = key(&input.item).ok_or_else(|| RusotoError::Validation("no key".to_owned()))?;
+        let mut tables = self.tables.lock().unwrap();
+        let table = tables.entry(input.table_name).or_default();
+
+        let empty = Item::new();
+        let current = table.get(&key).unwrap_or(&empty);
+        let ok = check(
+            &input.condition_expression,
+            current,
+            &input.expression_attribute_names,
+            &input.expression_attribute_values,
+        )
+        .map_err(RusotoError::Validation)?;
+        if !ok {
+            return Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(
+                CONDITION_FAILED.to_owned(),
+            )));
+        }
+
+        let old = table.insert(key, input.item);
+        Ok(PutItemOutput {
+            attributes: old_values(&input.return_values, old),
+            ..Default::default()
+        })
+    }
+
+    async fn update_item(
+        &self,
+        input: UpdateItemInput,
+    ) -> Result<UpdateItemOutput, RusotoError<UpdateItemError>> {
+        let key = key(&input.key).ok_or_else(|| RusotoError::Validation("no key".to_owned()))?;
+        let mut tables = self.tables.lock().unwrap();
+        let table = tables.entry(input.table_name.clone()).or_default();
+
+        let mut item = table
+            .get(&key)
+            .cloned()
+            .unwrap_or_else(|| input.key.clone());
+        let ok = check(
+            &input.condition_expression,
+            &item,
+            &input.expression_attribute_names,
+            &input.expression_attribute_values,
+        )
+        .map_err(RusotoError::Validation)?;
+        if !ok {
+            return Err(RusotoError::Service(
+                UpdateItemError::ConditionalCheckFailed(CONDITION_FAILED.to_owned()),
+            ));
+        }
+
+        update(&mut item, &input).map_err(RusotoError::Validation)?;
+
+        table.insert(key, item);
+        Ok(UpdateItemOutput::default())
+    }
+
+    async fn delete_item(
+        &self,
+        input: DeleteItemInput,
+    ) -> Result<DeleteItemOutput, RusotoError<DeleteItemError>> {
+        let key = key(&input.key).ok_or_else(|| RusotoError::Validation("no key".to_owned()))?;
+        let mut tables = self.tables.lock().unwrap();
+        let table = tables.entry(input.table_name).or_default();
+
+        let empty = Item::new();
+        let ok = check(
+            &input.condition_expression,
+            table.get(&key).unwrap_or(&empty),
+            &input.expression_attribute_names,
+            &input.expression_attribute_values,
+        )
+        .map_err(RusotoError::Validation)?;
+        if !ok {
+            return Err(RusotoError::Service(
+                DeleteItemError::ConditionalCheckFailed(CONDITION_FAILED.to_owned()),
+            ));
+        }
+
+        let old = table.remove(&key);
+        Ok(DeleteItemOutput {
+            attributes: old_values(&input.return_values, old),
+            ..Default::default()
+        })
+    }
+
+    async fn batch_write_item(
+        &self,
+        input: BatchWriteItemInput,
+    ) -> Result<BatchWriteItemOutput, RusotoError<BatchWriteItemError>> {
+        let mut tables = self.tables.lock().unwrap();
+
+        for (name, requests) in input.request_items {
+            let table = tables.entry(name).or_default();
+            for request in requests {
+                if let Some(put) = request.put_request {
+                    if let Some(key) = key(&put.item) {
+                        table.insert(key, put.item);
+                    }
+                }
+                if let Some(delete) = request.delete_request {
+                    if let Some(key) = key(&delete.key) {
+                        table.remove(&key);
+                    }
+                }
+            }
+        }
+
+        Ok(BatchWriteItemOutput::default())
+    }
+
+    async fn create_backup(
+        &self,
+        _: CreateBackupInput,
+    ) -> Result<CreateBackupOutput, RusotoError<CreateBackupError>> {
+        Err(RusotoError::Validation(
+            "backups are not supported by in-memory storage".to_owned(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(x: &str) -> AttributeValue {
+        AttributeValue {
+            s: Some(x.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn n(x: i64) -> AttributeValue {
+        AttributeValue {
+            n: Some(x.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn item(pk: &str, sk: &str, attrs: &[(&str, AttributeValue)]) -> Item {
+        let mut item = Item::new();
+        item.insert("pk".to_owned(), s(pk));
+        item.insert("sk".to_owned(), s(sk));
+        for (k, v) in attrs {
+            item.insert(k.to_string(), v.clone());
+        }
+        item
+    }
+
+    fn query(condition: &str, values: &[(&str, &str)]) -> QueryInput {
+        QueryInput {
+            table_name: "t".to_owned(),
+            key_condition_expression: Some(condition.to_owned()),
+            expression_attribute_values: Some(
+                values.iter().map(|(k, v)| (k.to_string(), s(v))).collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn put(x: Item) -> PutItemInput {
+        PutItemInput {
+            table_name: "t".to_owned(),
+            item: x,
+            ..Default::default()
+        }
+    }
+
+    fn sks(output: &QueryOutput) -> Vec<String> {
+        output
+            .items
+            .iter()
+            .flatten()
+            .filter_map(|x| x.get("sk").and_then(|x| x.s.clone()))
+            .collect()
+    }
+
+    async fn storage(pk: &str, sks: &[&str]) -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        for sk in sks {
+            storage.put_item(put(item(pk, sk, &[]))).await.unwrap();
+        }
+        storage
+            .put_item(put(item("other", "b", &[])))
+            .await
+            .unwrap();
+        storage
+    }
+
+    #[test]
+    fn key_range_of_sk_conditions() {
+        let range = key_range(&query(
+            "pk = :pk AND sk BETWEEN :from AND :to",
+            &[(":pk", "p"), (":from", "a"), (":to", "c")],
+        ))
+        .unwrap();
+        assert_eq!(range.pk, "p");
+        assert_eq!(range.low, Some(("a".to_owned(), true)));
+        assert_eq!(range.high, Some(("c".to_owned(), true)));
+
+        let mut input = query(
+            "gsi1pk = :pk AND BEGINS_WITH(gsi1sk, :a)",
+            &[(":pk", "p"), (":a", "TS#")],
+        );
+        input.index_name = Some("gsi1".to_owned());
+        let range = key_range(&input).unwrap();
+        assert_eq!(range.prefix.as_deref(), Some("TS#"));
+        assert_eq!(range.low, None);
+
+        assert!(key_range(&query("sk > :a", &[(":a", "a")])).is_err());
+        assert!(key_range(&query("pk = :pk OR sk > :a", &[(":pk", "p"), (":a", "a")])).is_err());
+    }
+
+    #[tokio::test]
+    async fn query_by_key_condition() {
+        let storage = storage("p", &["c", "a", "ab", "b"]).await;
+
+        let output = storage
+            .query(query(
+                "pk = :pk AND begins_with(sk, :prefix)",
+                &[(":pk", "p"), (":prefix", "a")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(sks(&output), ["a", "ab"]);
+
+        let mut input = query(
+            "pk = :pk AND sk BETWEEN :from AND :to",
+            &[(":pk", "p"), (":from", "ab"), (":to", "c")],
+        );
+        input.scan_index_forward = Some(false);
+        let output = storage.query(input).await.unwrap();
+        assert_eq!(sks(&output), ["c", "b", "ab"]);
+        assert_eq!(output.last_evaluated_key, None);
+    }
+
+    #[tokio::test]
+    async fn query_filter_counts_scanned_items() {
+        let storage = MemoryStorage::new();
+        for (sk, w) in &[("a", 10), ("b", 200), ("c", 30)] {
+            storage
+                .put_item(put(item("p", sk, &[("w", n(*w))])))
+                .await
+                .unwrap();
+        }
+
+        let mut input = query("pk = :pk", &[(":pk", "p")]);
+        input.filter_expression = Some("w < :w".to_owned());
+        input
+            .expression_attribute_values
+            .as_mut()
+            .unwrap()
+            .insert(":w".to_owned(), n(100));
+        let output = storage.query(input).await.unwrap();
+        assert_eq!(sks(&output), ["a", "c"]);
+        assert_eq!(output.count, Some(2));
+        assert_eq!(output.scanned_count, Some(3));
+    }
+
+    #[tokio::test]
+    async fn query_index_sorts_by_index_key() {
+        let storage = MemoryStorage::new();
+        for (sk, at) in &[("a", "3"), ("b", "1"), ("c", "2")] {
+            let x = item("p", sk, &[("gsi1pk", s("x")), ("gsi1sk", s(at))]);
+            storage.put_item(put(x)).await.unwrap();
+        }
+
+        let mut input = query("gsi1pk = :pk", &[(":pk", "x")]);
+        input.index_name = Some("gsi1".to_owned());
+        let output = storage.query(input).await.unwrap();
+        assert_eq!(sks(&output), ["b", "c", "a"]);
+    }
+
+    #[tokio::test]
+    async fn query_pages() {
+        let storage = storage("p", &["a", "b", "c", "d", "e"]).await;
+
+        for (forward, expected) in &[
+            (true, ["a", "b", "c", "d", "e"]),
+            (false, ["e", "d", "c", "b", "a"]),
+        ] {
+            let mut pages = Vec::new();
+            let mut start = None;
+            loop {
+                let mut input = query("pk = :pk", &[(":pk", "p")]);
+                input.limit = Some(2);
+                input.scan_index_forward = Some(*forward);
+                input.exclusive_start_key = start;
+                let output = storage.query(input).await.unwrap();
+                pages.push(sks(&output));
+                start = output.last_evaluated_key;
+                if start.is_none() {
+                    break;
+                }
+            }
+            assert_eq!(pages.concat(), expected);
+            assert_eq!(pages.len(), 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn query_resumes_after_deleted_start_key() {
+        let storage = storage("p", &["a", "b", "c", "d"]).await;
+
+        let mut input = query("pk = :pk", &[(":pk", "p")]);
+        input.limit = Some(2);
+        let output = storage.query(input).await.unwrap();
+        assert_eq!(sks(&output), ["a", "b"]);
+        let start = output.last_evaluated_key.unwrap();
+
+        storage
+            .delete_item(DeleteItemInput {
+                table_name: "t".to_owned(),
+                key: start.clone(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let mut input = query("pk = :pk", &[(":pk", "p")]);
+        input.exclusive_start_key = Some(start);
+        let output = storage.query(input).await.unwrap();
+        assert_eq!(sks(&output), ["c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn put_if_absent() {
+        let storage = MemoryStorage::new();
+        let mut input = put(item("p", "a", &[("v", n(1))]));
+        input.condition_expression = Some("attribute_not_exists(sk)".to_owned());
+
+        storage.put_item(input.clone()).await.unwrap();
+        let err = storage.put_item(input).await.unwrap_err();
+        assert!(matches!(
+            err,
+            RusotoError::Service(PutItemError::ConditionalCheckFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn put_versioned() {
+        let storage = MemoryStorage::new();
+        storage
+            .put_item(put(item("p", "a", &[("version", n(1))])))
+            .await
+            .unwrap();
+
+        let versioned = |current: i64, version: i64| {
+            let mut input = put(item("p", "a", &[("version", n(version))]));
+            input.condition_expression = Some("#version = :version".to_owned());
+            input.expression_attribute_names = Some(
+                vec![("#version".to_owned(), "version".to_owned())]
+                    .into_iter()
+                    .collect(),
+            );
+            input.expression_attribute_values = Some(
+                vec![(":version".to_owned(), n(current))]
+                    .into_iter()
+                    .collect(),
+            );
+            input
+        };
+
+        storage.put_item(versioned(1, 2)).await.unwrap();
+        let err = storage.put_item(versioned(1, 3)).await.unwrap_err();
+        assert!(matches!(
+            err,
+            RusotoError::Service(PutItemError::ConditionalCheckFailed(_))
+        ));
+
+        let output = storage
+            .get_item(GetItemInput {
+                table_name: "t".to_owned(),
+                key: item("p", "a", &[]),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(output.item.unwrap().get("version"), Some(&n(2)));
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    BatchGetItemError, BatchGetItemInput, BatchGetItemOutput, BatchWriteItemError,
+    BatchWriteItemInput, BatchWriteItemOutput, ConsumedCapacity, CreateBackupError,
+    CreateBackupInput, CreateBackupOutput, DeleteItemError, DeleteItemInput, DeleteItemOutput,
+    GetItemError, GetItemInput, GetItemOutput, PutItemError, PutItemInput, PutItemOutput,
+    QueryError, QueryInput, QueryOutput, UpdateItemError, UpdateItemInput, UpdateItemOutput,
+};
+
+use crate::storage::Storage;
+
+// Upper bounds of the latency histogram, in seconds.
+const BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+#[derive(Default)]
+struct Operation {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+    errors: u64,
+    retries: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    operations: BTreeMap<&'static str, Operation>,
+    // (operation, table) -> capacity units
+    capacity: BTreeMap<(&'static str, String), f64>,
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(|| Mutex::new(Registry::default()));
+
+fn record(operation: &'static str, seconds: f64, ok: bool) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let x = registry.operations.entry(operation).or_default();
+    if x.buckets.is_empty() {
+        x.buckets = vec![0; BUCKETS.len()];
+    }
+
+    for (i, le) in BUCKETS.iter().enumerate() {
+        if seconds <= *le {
+            x.buckets[i] += 1;
+        }
+    }
+    x.count += 1;
+    x.sum += seconds;
+    if !ok {
+        x.errors += 1;
+    }
+}
+
+fn consumed<'a, I>(operation: &'static str, capacity: I)
+where
+    I: IntoIterator<Item = &'a ConsumedCapacity>,
+{
+    let mut registry = REGISTRY.lock().unwrap();
+    for x in capacity {
+        let table = x.table_name.clone().unwrap_or_default();
+        *registry.capacity.entry((operation, table)).or_default() +=
+            x.capacity_units.unwrap_or(0.0);
+    }
+}
+
+// Counts a repeated request, e.g. for keys DynamoDB left unprocessed.
+pub fn retry(operation: &'static str) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.operations.entry(operation).or_default().retries += 1;
+}
+
+async fn timed<T, E, F>(operation: &'static str, f: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let at = Instant::now();
+    let result = f.await;
+    record(operation, at.elapsed().as_secs_f64(), result.is_ok());
+    result
+}
+
+fn total() -> String {
+    "TOTAL".to_owned()
+}
+
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    out += "# HELP homeapi_dynamodb_request_duration_seconds Storage request latency.\n";
+    out += "# TYPE homeapi_dynamodb_request_duration_seconds histogram\n";
+    for (operation, x) in registry.operations.iter().filter(|(_, x)| x.count > 0) {
+        let name = "homeapi_dynamodb_request_duration_seconds";
+        for (le, n) in BUCKETS.iter().zip(x.buckets.iter()) {
+            out += &format!(
+                "{}_bucket{{operation=\"{}\",le=\"{}\"}} {}\n",
+                name, operation, le, n
+            );
+        }
+        out += &format!(
+            "{}_bucket{{operation=\"{}\",le=\"+Inf\"}} {}\n",
+            name, operation, x.count
+        );
+        out += &format!("{}_sum{{operation=\"{}\"}} {}\n", name, operation, x.sum);
+        out += &format!(
+            "{}_count{{operation=\"{}\"}} {}\n",
+            name, operation, x.count
+        );
+    }
+
+    out += "# HELP homeapi_dynamodb_errors_total Storage requests that failed.\n";
+    out += "# TYPE homeapi_dynamodb_errors_total counter\n";
+    for (operation, x) in registry.operations.iter() {
+        out += &format!(
+            "homeapi_dynamodb_errors_total{{operation=\"{}\"}} {}\n",
+            operation, x.errors
+        );
+    }
+
+    out += "# HELP homeapi_dynamodb_retries_total Storage requests repeated by Client.\n";
+    out += "# TYPE homeapi_dynamodb_retries_total counter\n";
+    for (operation, x) in registry.operations.iter() {
+        out += &format!(
+            "homeapi_dynamodb_retries_total{{operation=\"{}\"}} {}\n",
+            operation, x.retries
+        );
+    }
+
+    out += "# HELP homeapi_dynamodb_consumed_capacity_units_total Capacity units reported by DynamoDB.\n";
+    out += "# TYPE homeapi_dynamodb_consumed_capacity_units_total counter\n";
+    for ((operation, table), units) in registry.capacity.iter() {
+        out += &format!(
+            "homeapi_dynamodb_consumed_capacity_units_total{{operation=\"{}\",table=\"{}\"}} {}\n",
+            operation, table, units
+        );
+    }
+
+    out
+}
+
+// Wraps a Storage to time every request and collect the consumed capacity
+// DynamoDB reports. Other backends report none.
+pub struct MeteredStorage(pub Arc<dyn Storage>);
+
+#[async_trait]
+impl Storage for MeteredStorage {
+    async fn get_item(
+        &self,
+        mut input: GetItemInput,
+    ) -> Result<GetItemOutput, RusotoError<GetItemError>> {
+        input.return_consumed_capacity.get_or_insert_with(total);
+        let output = timed("get_item", self.0.get_item(input)).await?;
+        consumed("get_item", output.consumed_capacity.iter());
+        Ok(output)
+    }
+
+    async fn batch_get_item(
+        &self,
+        mut input: BatchGetItemInput,
+    ) -> Result<BatchGetItemOutput, RusotoError<BatchGetItemError>> {
+        input.return_consumed_capacity.get_or_insert_with(total);
+        let output = timed("batch_get_item", self.0.batch_get_item(input)).await?;
+        consumed("batch_get_item", output.consumed_capacity.iter().flatten());
+        Ok(output)
+    }
+
+    async fn query(&self, mut input: QueryInput) -> Result<QueryOutput, RusotoError<QueryError>> {
+        input.return_consumed_capacity.get_or_insert_with(total);
+        let output = timed("query", self.0.query(input)).await?;
+        consumed("query", output.consumed_capacity.iter());
+        Ok(output)
+    }
+
+    async fn put_item(
+        &self,
+        mut input: PutItemInput,
+    ) -> Result<PutItemOutput, RusotoError<PutItemError>> {
+        input.return_consumed_capacity.get_or_insert_with(total);
+        let output = timed("put_item", self.0.put_item(input)).await?;
+        consumed("put_item", output.consumed_capacity.iter());
+        Ok(output)
+    }
+
+    async fn update_item(
+        &self,
+        mut input: UpdateItemInput,
+    ) -> Result<UpdateItemOutput, RusotoError<UpdateItemError>> {
+        input.return_consumed_capacity.get_or_insert_with(total);
+        let output = timed("update_item", self.0.update_item(input)).await?;
+        consumed("update_item", output.consumed_capacity.iter());
+        Ok(output)
+    }
+
+    async fn delete_item(
+        &self,
+        mut input: DeleteItemInput,
+    ) -> Result<DeleteItemOutput, RusotoError<DeleteItemError>> {
+        input.return_consumed_capacity.get_or_insert_with(total);
+        let output = timed("delete_item", self.0.delete_item(input)).await?;
+        consumed("delete_item", output.consumed_capacity.iter());
+        Ok(output)
+    }
+
+    async fn batch_write_item(
+        &self,
+        mut input: BatchWriteItemInput,
+    ) -> Result<BatchWriteItemOutput, RusotoError<BatchWriteItemError>> {
+        input.return_consumed_capacity.get_or_insert_with(total);
+        let output = timed("batch_write_item", self.0.batch_write_item(input)).await?;
+        consumed(
+            "batch_write_item",
+            output.consumed_capacity.iter().flatten(),
+        );
+        Ok(output)
+    }
+
+    async fn create_backup(
+        &self,
+        input: CreateBackupInput,
+    ) -> Result<CreateBackupOutput, RusotoError<CreateBackupError>> {
+        timed("create_backup", self.0.create_backup(input)).await
+    }
+}
@@ -1,8 +1,14 @@
+use std::collections::HashMap;
+
 use async_graphql::*;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
+use crate::automation;
+use crate::comfort;
+
 pub trait DynamoItem {
     fn sk_prefix() -> String {
         "".to_owned()
@@ -17,6 +23,90 @@ pub trait DynamoItem {
     }
 }
 
+// Devices and places are deleted by writing a tombstone. Tombstoned items are
+// left out of queries until they are restored or purged. Burying and restoring
+// bump the version, so they are written with put_item_versioned.
+pub trait Tombstone {
+    fn deleted_at(&self) -> Option<DateTime<Utc>>;
+
+    fn version(&self) -> i64;
+
+    fn bury(&mut self, by: Option<String>);
+
+    fn restore(&mut self);
+}
+
+// Leaves out tombstoned items, for anything that lists devices or places.
+pub fn live<D: Tombstone>(items: Vec<D>) -> Vec<D> {
+    items
+        .into_iter()
+        .filter(|x| x.deleted_at().is_none())
+        .collect()
+}
+
+// Webhook URLs carry their credentials in the path or query, so only the
+// scheme and host are shown.
+pub fn redact_url(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let authority = rest.split(&['/', '?', '#'][..]).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    format!("{}://{}/...", scheme, host)
+}
+
+// Returns the timestamp part of a telemetry sk such as "TS#..." or
+// "STATE#TS#...".
+pub fn telemetry_timestamp(sk: &str) -> Option<&str> {
+    let prefixes = [
+        Electricity::sk_prefix(),
+        ApplianceState::sk_prefix(),
+        SolarProduction::sk_prefix(),
+        BatteryState::sk_prefix(),
+    ];
+
+    prefixes.iter().find_map(|x| sk.strip_prefix(x.as_str()))
+}
+
+// Telemetry items are also indexed by place on the "gsi1" GSI so that all
+// sensors in a room can be read for a time range with a single query.
+// Compaction summaries keep their place and are indexed under their own sk,
+// so a place's history stays in the index after its raw readings are gone.
+pub fn place_index(pk: &str, sk: &str, place: &str) -> Option<(String, String)> {
+    let reading = ["AGG5M#", "AGG1H#"]
+        .iter()
+        .find_map(|x| sk.strip_prefix(x))
+        .unwrap_or(sk);
+    if place.is_empty() || telemetry_timestamp(reading).is_none() {
+        return None;
+    }
+
+    Some((format!("PLACE#{}", place), format!("{}#{}", sk, pk)))
+}
+
+// Devices that need attention are the only items carrying gsi2 keys, which
+// keeps that index sparse.
+pub fn attention_index(pk: &str, sk: &str, needs_attention: &str) -> Option<(String, String)> {
+    if pk != "DEVICE" || needs_attention.is_empty() {
+        return None;
+    }
+
+    Some(("ATTENTION".to_owned(), sk.to_owned()))
+}
+
+// Devices and places carry a lowercased "search" attribute so that search can
+// do case-insensitive substring matching with a contains() filter.
+pub fn search_text(pk: &str, fields: &[String]) -> Option<String> {
+    if pk != "DEVICE" && pk != "PLACE" {
+        return None;
+    }
+
+    let text = fields
+        .iter()
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect::<Vec<_>>();
+    Some(text.join(" "))
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct RawData {
     pk: String,
@@ -25,6 +115,9 @@ pub struct RawData {
     pub id: String,
 
     pub body: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<i64>,
 }
 
 impl RawData {
@@ -35,9 +128,21 @@ impl RawData {
             ..Default::default()
         }
     }
+
+    pub fn capture(
+        source: &str,
+        timestamp: DateTime<Utc>,
+        body: String,
+        ttl: Option<Duration>,
+    ) -> Self {
+        let mut raw = Self::new(format!("{}#TS#{:?}", source, timestamp));
+        raw.body = body;
+        raw.ttl = ttl.map(|x| (timestamp + x).timestamp());
+        raw
+    }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Device {
     pk: String,
 
@@ -45,6 +150,110 @@ pub struct Device {
     pub id: String,
 
     pub place: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tariff: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub billing_anchor: Option<BillingAnchor>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub needs_attention: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub version: i64,
+
+    // Shared secret for signed ingestion; never exposed through GraphQL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+
+    #[serde(default)]
+    pub state: DeviceState,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+}
+
+// Devices created by provisionDevice stay pending until the firmware claims
+// them. Devices registered any other way are active from the start.
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DeviceState {
+    Pending,
+    Active,
+}
+
+impl Default for DeviceState {
+    fn default() -> Self {
+        DeviceState::Active
+    }
+}
+
+#[derive(InputObject)]
+pub struct DeviceInput {
+    pub place: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub firmware_version: Option<String>,
+    pub serial: Option<String>,
+    pub notes: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DeviceField {
+    Manufacturer,
+    Model,
+    FirmwareVersion,
+    Serial,
+    Notes,
+    Tags,
+}
+
+#[derive(InputObject)]
+pub struct DeviceFilter {
+    pub place: Option<String>,
+    pub tag: Option<String>,
+    pub id_prefix: Option<String>,
+    pub needs_attention: Option<bool>,
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, device: &Device) -> bool {
+        self.place.as_ref().map_or(true, |x| x == &device.place)
+            && self.tag.as_ref().map_or(true, |x| {
+                device.tags.as_ref().map_or(false, |tags| tags.contains(x))
+            })
+            && self
+                .id_prefix
+                .as_ref()
+                .map_or(true, |x| device.id.starts_with(x.as_str()))
+            && self
+                .needs_attention
+                .map_or(true, |x| x == device.needs_attention.is_some())
+    }
 }
 
 impl Device {
@@ -55,6 +264,33 @@ impl Device {
             ..Default::default()
         }
     }
+
+    pub fn update(&mut self, input: DeviceInput) {
+        if let Some(place) = input.place {
+            self.place = place;
+        }
+        self.manufacturer = input.manufacturer.or_else(|| self.manufacturer.take());
+        self.model = input.model.or_else(|| self.model.take());
+        self.firmware_version = input
+            .firmware_version
+            .or_else(|| self.firmware_version.take());
+        self.serial = input.serial.or_else(|| self.serial.take());
+        self.notes = input.notes.or_else(|| self.notes.take());
+        self.tags = input.tags.or_else(|| self.tags.take());
+    }
+
+    pub fn unset(&mut self, fields: &[DeviceField]) {
+        for field in fields {
+            match field {
+                DeviceField::Manufacturer => self.manufacturer = None,
+                DeviceField::Model => self.model = None,
+                DeviceField::FirmwareVersion => self.firmware_version = None,
+                DeviceField::Serial => self.serial = None,
+                DeviceField::Notes => self.notes = None,
+                DeviceField::Tags => self.tags = None,
+            }
+        }
+    }
 }
 
 impl DynamoItem for Device {
@@ -67,6 +303,28 @@ impl DynamoItem for Device {
     }
 }
 
+impl Tombstone for Device {
+    fn deleted_at(&self) -> Option<DateTime<Utc>> {
+        self.deleted_at
+    }
+
+    fn version(&self) -> i64 {
+        self.version
+    }
+
+    fn bury(&mut self, by: Option<String>) {
+        self.deleted_at = Some(Utc::now());
+        self.deleted_by = by;
+        self.version += 1;
+    }
+
+    fn restore(&mut self) {
+        self.deleted_at = None;
+        self.deleted_by = None;
+        self.version += 1;
+    }
+}
+
 #[Object]
 impl Device {
     async fn id(&self) -> &str {
@@ -76,9 +334,95 @@ impl Device {
     async fn place(&self) -> &str {
         self.place.as_str()
     }
+
+    async fn tariff(&self) -> Option<&str> {
+        self.tariff.as_deref()
+    }
+
+    async fn billing_anchor(&self) -> Option<&BillingAnchor> {
+        self.billing_anchor.as_ref()
+    }
+
+    async fn needs_attention(&self) -> Option<&str> {
+        self.needs_attention.as_deref()
+    }
+
+    async fn manufacturer(&self) -> Option<&str> {
+        self.manufacturer.as_deref()
+    }
+
+    async fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    async fn firmware_version(&self) -> Option<&str> {
+        self.firmware_version.as_deref()
+    }
+
+    async fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    async fn notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    async fn tags(&self) -> Option<&Vec<String>> {
+        self.tags.as_ref()
+    }
+
+    async fn last_seen_at(&self) -> Option<String> {
+        self.last_seen_at.map(|x| format!("{:?}", &x))
+    }
+
+    async fn version(&self) -> i64 {
+        self.version
+    }
+
+    async fn state(&self) -> DeviceState {
+        self.state
+    }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+// Where a device's billing periods start: `day` of the month (the last day
+// of shorter months) at `hour` in `timezone`.
+#[derive(Clone, Debug, Serialize, Deserialize, SimpleObject)]
+pub struct BillingAnchor {
+    pub day: u32,
+    pub hour: u32,
+    pub timezone: String,
+}
+
+impl BillingAnchor {
+    pub fn new(input: BillingAnchorInput) -> Result<Self, String> {
+        let anchor = Self {
+            day: input.day,
+            hour: input.hour.unwrap_or(0),
+            timezone: input.timezone.unwrap_or_else(|| "UTC".to_owned()),
+        };
+        if !(1..=31).contains(&anchor.day) {
+            return Err(format!("day must be 1-31: {}", anchor.day));
+        }
+        if anchor.hour > 23 {
+            return Err(format!("hour must be 0-23: {}", anchor.hour));
+        }
+        anchor.timezone.parse::<Tz>()?;
+        Ok(anchor)
+    }
+
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+}
+
+#[derive(InputObject)]
+pub struct BillingAnchorInput {
+    pub day: u32,
+    pub hour: Option<u32>,
+    pub timezone: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Place {
     pk: String,
 
@@ -86,6 +430,20 @@ pub struct Place {
     pub id: String,
 
     pub name: String,
+
+    #[serde(default)]
+    pub version: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+}
+
+#[derive(InputObject)]
+pub struct PlaceInput {
+    pub name: Option<String>,
 }
 
 impl Place {
@@ -96,6 +454,12 @@ impl Place {
             ..Default::default()
         }
     }
+
+    pub fn update(&mut self, input: PlaceInput) {
+        if let Some(name) = input.name {
+            self.name = name;
+        }
+    }
 }
 
 impl DynamoItem for Place {
@@ -108,13 +472,119 @@ impl DynamoItem for Place {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Tombstone for Place {
+    fn deleted_at(&self) -> Option<DateTime<Utc>> {
+        self.deleted_at
+    }
+
+    fn version(&self) -> i64 {
+        self.version
+    }
+
+    fn bury(&mut self, by: Option<String>) {
+        self.deleted_at = Some(Utc::now());
+        self.deleted_by = by;
+        self.version += 1;
+    }
+
+    fn restore(&mut self) {
+        self.deleted_at = None;
+        self.deleted_by = None;
+        self.version += 1;
+    }
+}
+
+#[Object]
+impl Place {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    async fn version(&self) -> i64 {
+        self.version
+    }
+}
+
+#[derive(Union)]
+pub enum SearchResult {
+    Device(Device),
+    Place(Place),
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WebhookRule {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub source: String,
+
+    pub kind: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items_path: Option<String>,
+
+    pub id_path: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_path: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub place_path: Option<String>,
+
+    #[serde(default)]
+    pub fields: HashMap<String, String>,
+
+    #[serde(default)]
+    pub signed: bool,
+}
+
+impl WebhookRule {
+    pub fn new(source: String) -> Self {
+        Self {
+            pk: "WEBHOOK_RULE".to_owned(),
+            source,
+            ..Default::default()
+        }
+    }
+}
+
+// The timestamp and power of an Electricity item, read with a projection for
+// charts that plot every reading. Power is negative while exporting.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ElectricityPoint {
+    #[serde(rename = "sk")]
+    #[serde(with = "dynamodb_timestamp")]
+    pub timestamp: DateTime<Utc>,
+
+    pub current_w: i64,
+}
+
+impl ElectricityPoint {
+    pub const ATTRIBUTES: [&'static str; 2] = ["sk", "current_w"];
+}
+
+#[Object]
+impl ElectricityPoint {
+    async fn timestamp(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+
+    async fn current_w(&self) -> String {
+        format!("{}", &self.current_w)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Electricity {
     #[serde(rename = "pk")]
     pub id: String,
 
     #[serde(rename = "sk")]
-    #[serde(with = "dynamodb_timestamp")]
+    #[serde(with = "dynamodb_reading_ts")]
     pub timestamp: DateTime<Utc>,
 
     #[serde(default)]
@@ -219,13 +689,13 @@ impl FinalElectricity {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PlaceCondition {
     #[serde(rename = "pk")]
     pub id: String,
 
     #[serde(rename = "sk")]
-    #[serde(with = "dynamodb_timestamp")]
+    #[serde(with = "dynamodb_reading_ts")]
     pub timestamp: DateTime<Utc>,
 
     pub place: String,
@@ -241,6 +711,9 @@ pub struct PlaceCondition {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub motion: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_percent: Option<f64>,
 }
 
 impl DynamoItem for PlaceCondition {
@@ -283,53 +756,1998 @@ impl PlaceCondition {
         self.illuminance.map(|x| format!("{}", &x))
     }
 
+    async fn battery_percent(&self) -> Option<String> {
+        self.battery_percent.map(|x| format!("{}", &x))
+    }
+
     async fn motion(&self) -> Option<String> {
         self.motion.map(|x| format!("{}", &x))
     }
-}
 
-mod dynamodb_timestamp {
-    use chrono::{DateTime, Utc};
-    use serde::{Deserialize, Deserializer, Serializer};
+    async fn dew_point(&self) -> Option<String> {
+        let (t, h) = (self.temperature?, self.humidity? as f64);
+        comfort::dew_point(t, h).map(|x| format!("{:.1}", x))
+    }
 
-    pub fn serialize<S>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(&format!("TS#{:?}", timestamp))
+    async fn heat_index(&self) -> Option<String> {
+        let (t, h) = (self.temperature?, self.humidity? as f64);
+        Some(format!("{:.1}", comfort::heat_index(t, h)))
     }
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        match s.strip_prefix("TS#") {
-            Some(prefix) => prefix.parse().map_err(serde::de::Error::custom),
-            None => Err(serde::de::Error::custom("Invalid prefix")),
+    async fn discomfort_index(&self) -> Option<String> {
+        let (t, h) = (self.temperature?, self.humidity? as f64);
+        Some(format!("{:.1}", comfort::discomfort_index(t, h)))
+    }
+}
+
+// A period when motion was seen in a place, inferred from motion pulses.
+// `to` is unset while the place is still occupied.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Occupancy {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub from: DateTime<Utc>,
+
+    pub place: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<DateTime<Utc>>,
+
+    pub pulses: u32,
+}
+
+impl Occupancy {
+    pub fn new(place: String, from: DateTime<Utc>) -> Self {
+        Self {
+            pk: format!("OCCUPANCY#{}", place),
+            from,
+            place,
+            to: None,
+            pulses: 0,
         }
     }
 }
 
-mod dynamodb_fin_ts {
-    use chrono::{DateTime, Utc};
-    use serde::{Deserialize, Deserializer, Serializer};
+impl DynamoItem for Occupancy {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
 
-    pub fn serialize<S>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(&format!("FIN#TS#{:?}", timestamp))
+    fn sk_value(&self) -> String {
+        format!("{:?}", self.from)
     }
+}
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        match s.strip_prefix("FIN#TS#") {
-            Some(prefix) => prefix.parse().map_err(serde::de::Error::custom),
-            None => Err(serde::de::Error::custom("Invalid prefix")),
-        }
+#[Object]
+impl Occupancy {
+    async fn place(&self) -> &str {
+        self.place.as_str()
+    }
+
+    async fn from(&self) -> String {
+        format!("{:?}", &self.from)
+    }
+
+    async fn to(&self) -> Option<String> {
+        self.to.map(|x| format!("{:?}", &x))
+    }
+
+    async fn pulses(&self) -> u32 {
+        self.pulses
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApplianceState {
+    #[serde(rename = "pk")]
+    pub id: String,
+
+    #[serde(rename = "sk")]
+    #[serde(with = "dynamodb_state_ts")]
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(default)]
+    pub place: String,
+
+    pub on: bool,
+}
+
+impl DynamoItem for ApplianceState {
+    fn sk_prefix() -> String {
+        "STATE#TS#".to_owned()
+    }
+
+    fn pk(&self) -> String {
+        self.id.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+}
+
+#[Object]
+impl ApplianceState {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn timestamp(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+
+    async fn place(&self) -> &str {
+        self.place.as_str()
+    }
+
+    async fn on(&self) -> bool {
+        self.on
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SolarProduction {
+    #[serde(rename = "pk")]
+    pub id: String,
+
+    #[serde(rename = "sk")]
+    #[serde(with = "dynamodb_solar_ts")]
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(default)]
+    pub place: String,
+
+    pub power_w: u32,
+    pub cumulative_kwh: Decimal,
+}
+
+impl DynamoItem for SolarProduction {
+    fn sk_prefix() -> String {
+        "SOLAR#TS#".to_owned()
+    }
+
+    fn pk(&self) -> String {
+        self.id.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+}
+
+#[Object]
+impl SolarProduction {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn timestamp(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+
+    async fn place(&self) -> &str {
+        self.place.as_str()
+    }
+
+    async fn power_w(&self) -> String {
+        format!("{}", &self.power_w)
+    }
+
+    async fn cumulative_kwh(&self) -> String {
+        format!("{}", &self.cumulative_kwh)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatteryState {
+    #[serde(rename = "pk")]
+    pub id: String,
+
+    #[serde(rename = "sk")]
+    #[serde(with = "dynamodb_battery_ts")]
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(default)]
+    pub place: String,
+
+    pub percent: f64,
+    pub power_w: i64,
+}
+
+impl DynamoItem for BatteryState {
+    fn sk_prefix() -> String {
+        "BATTERY#TS#".to_owned()
+    }
+
+    fn pk(&self) -> String {
+        self.id.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+}
+
+#[Object]
+impl BatteryState {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn timestamp(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+
+    async fn place(&self) -> &str {
+        self.place.as_str()
+    }
+
+    async fn percent(&self) -> String {
+        format!("{}", &self.percent)
+    }
+
+    async fn power_w(&self) -> String {
+        format!("{}", &self.power_w)
+    }
+}
+
+// Electricity and PlaceCondition share the TS# prefix, so they are deleted
+// together as Readings.
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TelemetryKind {
+    Readings,
+    ApplianceStates,
+    SolarProduction,
+    BatteryStates,
+}
+
+impl TelemetryKind {
+    pub fn sk_prefix(self) -> String {
+        match self {
+            TelemetryKind::Readings => Electricity::sk_prefix(),
+            TelemetryKind::ApplianceStates => ApplianceState::sk_prefix(),
+            TelemetryKind::SolarProduction => SolarProduction::sk_prefix(),
+            TelemetryKind::BatteryStates => BatteryState::sk_prefix(),
+        }
+    }
+}
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Metric {
+    Temperature,
+    Humidity,
+    Illuminance,
+    Motion,
+    CurrentW,
+    BatteryPercent,
+    Staleness,
+}
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Comparison {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+}
+
+impl Comparison {
+    pub fn test(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Gt => value > threshold,
+            Comparison::Ge => value >= threshold,
+            Comparison::Lt => value < threshold,
+            Comparison::Le => value <= threshold,
+            Comparison::Eq => (value - threshold).abs() < f64::EPSILON,
+        }
+    }
+}
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Mode {
+    Home,
+    Away,
+    Vacation,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Home
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModePeriod {
+    pub mode: Mode,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+#[Object]
+impl ModePeriod {
+    async fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    async fn from(&self) -> String {
+        format!("{:?}", &self.from)
+    }
+
+    async fn to(&self) -> String {
+        format!("{:?}", &self.to)
+    }
+}
+
+// The household's mode, stored as a single item. The manually set mode
+// applies except during scheduled periods, such as a vacation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HomeMode {
+    pk: String,
+    sk: String,
+
+    pub mode: Mode,
+
+    #[serde(default)]
+    pub schedule: Vec<ModePeriod>,
+
+    #[serde(default)]
+    pub version: i64,
+}
+
+impl Default for HomeMode {
+    fn default() -> Self {
+        Self {
+            pk: "HOME_MODE".to_owned(),
+            sk: "current".to_owned(),
+            mode: Mode::Home,
+            schedule: Vec::new(),
+            version: 0,
+        }
+    }
+}
+
+impl HomeMode {
+    pub fn at(&self, at: DateTime<Utc>) -> Mode {
+        self.schedule
+            .iter()
+            .find(|x| x.from <= at && at < x.to)
+            .map_or(self.mode, |x| x.mode)
+    }
+
+    // Adds a period, dropping periods that are over or that it overlaps.
+    pub fn add_period(&mut self, period: ModePeriod, now: DateTime<Utc>) {
+        self.schedule
+            .retain(|x| x.to > now && (x.to <= period.from || x.from >= period.to));
+        self.schedule.push(period);
+        self.schedule.sort_by_key(|x| x.from);
+    }
+}
+
+impl DynamoItem for HomeMode {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.sk.to_owned()
+    }
+}
+
+#[Object]
+impl HomeMode {
+    // The mode in effect now.
+    async fn mode(&self) -> Mode {
+        self.at(Utc::now())
+    }
+
+    async fn manual_mode(&self) -> Mode {
+        self.mode
+    }
+
+    async fn schedule(&self) -> Vec<ModePeriod> {
+        self.schedule.clone()
+    }
+
+    async fn version(&self) -> i64 {
+        self.version
+    }
+}
+
+// A person tracked by presence-import through their devices on the home
+// network.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Person {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub name: String,
+
+    pub home: bool,
+    pub since: DateTime<Utc>,
+}
+
+impl Person {
+    pub fn new(name: String, home: bool, since: DateTime<Utc>) -> Self {
+        Self {
+            pk: "PERSON".to_owned(),
+            name,
+            home,
+            since,
+        }
+    }
+}
+
+impl DynamoItem for Person {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.name.to_owned()
+    }
+}
+
+#[Object]
+impl Person {
+    async fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    async fn home(&self) -> bool {
+        self.home
+    }
+
+    async fn since(&self) -> String {
+        format!("{:?}", &self.since)
+    }
+}
+
+// An arrival or departure of a person.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresenceEvent {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    #[serde(with = "dynamodb_timestamp")]
+    pub timestamp: DateTime<Utc>,
+
+    pub person: String,
+    pub home: bool,
+}
+
+impl PresenceEvent {
+    pub fn new(person: String, timestamp: DateTime<Utc>, home: bool) -> Self {
+        Self {
+            pk: format!("PRESENCE#{}", person),
+            timestamp,
+            person,
+            home,
+        }
+    }
+}
+
+impl DynamoItem for PresenceEvent {
+    fn sk_prefix() -> String {
+        "TS#".to_owned()
+    }
+
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        format!("{:?}", self.timestamp)
+    }
+}
+
+#[Object]
+impl PresenceEvent {
+    async fn person(&self) -> &str {
+        self.person.as_str()
+    }
+
+    async fn timestamp(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+
+    async fn home(&self) -> bool {
+        self.home
+    }
+}
+
+#[derive(InputObject)]
+pub struct AlertRuleInput {
+    pub name: String,
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    pub duration_seconds: Option<i64>,
+    pub device: Option<String>,
+    pub place: Option<String>,
+    pub channel: Option<String>,
+    pub enabled: Option<bool>,
+    // Only evaluate the rule in these home modes.
+    pub modes: Option<Vec<Mode>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AlertRule {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub id: String,
+
+    pub name: String,
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    pub duration_seconds: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub place: Option<String>,
+
+    pub channel: String,
+    pub enabled: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modes: Option<Vec<Mode>>,
+
+    #[serde(default)]
+    pub firing: Vec<String>,
+
+    #[serde(default)]
+    pub version: i64,
+}
+
+impl AlertRule {
+    pub fn new(id: String, input: AlertRuleInput) -> Self {
+        let mut rule = Self {
+            pk: "ALERT_RULE".to_owned(),
+            id,
+            name: String::new(),
+            metric: input.metric,
+            comparison: input.comparison,
+            threshold: 0.0,
+            duration_seconds: 0,
+            device: None,
+            place: None,
+            channel: "log".to_owned(),
+            enabled: true,
+            modes: None,
+            firing: Vec::new(),
+            version: 0,
+        };
+        rule.update(input);
+        rule
+    }
+
+    pub fn update(&mut self, input: AlertRuleInput) {
+        self.name = input.name;
+        self.metric = input.metric;
+        self.comparison = input.comparison;
+        self.threshold = input.threshold;
+        self.duration_seconds = input.duration_seconds.unwrap_or(0);
+        self.device = input.device;
+        self.place = input.place;
+        self.modes = input.modes;
+        if let Some(channel) = input.channel {
+            self.channel = channel;
+        }
+        if let Some(enabled) = input.enabled {
+            self.enabled = enabled;
+        }
+    }
+}
+
+impl DynamoItem for AlertRule {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.id.to_owned()
+    }
+}
+
+#[Object]
+impl AlertRule {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    async fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    async fn comparison(&self) -> Comparison {
+        self.comparison
+    }
+
+    async fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    async fn duration_seconds(&self) -> i64 {
+        self.duration_seconds
+    }
+
+    async fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    async fn place(&self) -> Option<&str> {
+        self.place.as_deref()
+    }
+
+    // Slack and Discord channels embed the webhook URL, which is the secret.
+    async fn channel(&self) -> String {
+        match self.channel.split_once(':') {
+            Some((kind, url)) => format!("{}:{}", kind, redact_url(url)),
+            None => self.channel.clone(),
+        }
+    }
+
+    async fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    async fn modes(&self) -> Option<Vec<Mode>> {
+        self.modes.clone()
+    }
+
+    async fn firing(&self) -> Vec<String> {
+        self.firing.clone()
+    }
+
+    async fn version(&self) -> i64 {
+        self.version
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Alert {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub id: String,
+
+    pub rule_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub device: String,
+    pub metric: Metric,
+    pub value: f64,
+    pub message: String,
+}
+
+impl Alert {
+    pub fn new(
+        rule: &AlertRule,
+        timestamp: DateTime<Utc>,
+        device: String,
+        value: f64,
+        message: String,
+    ) -> Self {
+        Self {
+            pk: "ALERT".to_owned(),
+            id: format!("{:?}#{}#{}", timestamp, rule.id, device),
+            rule_id: rule.id.clone(),
+            timestamp,
+            device,
+            metric: rule.metric,
+            value,
+            message,
+        }
+    }
+}
+
+impl DynamoItem for Alert {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.id.to_owned()
+    }
+}
+
+#[Object]
+impl Alert {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn rule_id(&self) -> &str {
+        self.rule_id.as_str()
+    }
+
+    async fn timestamp(&self) -> String {
+        format!("{:?}", &self.timestamp)
+    }
+
+    async fn device(&self) -> &str {
+        self.device.as_str()
+    }
+
+    async fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    async fn value(&self) -> String {
+        format!("{}", &self.value)
+    }
+
+    async fn message(&self) -> &str {
+        self.message.as_str()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AutomationAction {
+    pub url: String,
+    pub method: String,
+    // Sent as is. Without a body, a JSON description of the trigger is sent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[Object]
+impl AutomationAction {
+    async fn url(&self) -> String {
+        redact_url(&self.url)
+    }
+
+    async fn method(&self) -> &str {
+        self.method.as_str()
+    }
+
+    async fn body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+}
+
+#[derive(InputObject)]
+pub struct AutomationActionInput {
+    pub url: String,
+    pub method: Option<String>,
+    pub body: Option<String>,
+}
+
+#[derive(InputObject)]
+pub struct AutomationInput {
+    pub name: String,
+    // Run when this alert rule starts firing for a device.
+    pub rule: Option<String>,
+    // Run daily at this UTC time, "HH:MM".
+    pub schedule: Option<String>,
+    pub actions: Vec<AutomationActionInput>,
+    pub enabled: Option<bool>,
+}
+
+// Actions run on a trigger. An automation without a trigger is a scene and
+// only runs through runAutomation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Automation {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub id: String,
+
+    pub name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+
+    pub actions: Vec<AutomationAction>,
+    pub enabled: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run_at: Option<DateTime<Utc>>,
+
+    // When the schedule was set. Scheduled times before it are not run, so
+    // that a new schedule doesn't fire for a time already past.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub armed_at: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub version: i64,
+}
+
+impl Automation {
+    pub fn new(id: String) -> Self {
+        Self {
+            pk: "AUTOMATION".to_owned(),
+            id,
+            name: String::new(),
+            rule: None,
+            schedule: None,
+            actions: Vec::new(),
+            enabled: true,
+            last_run_at: None,
+            armed_at: None,
+            version: 0,
+        }
+    }
+
+    pub fn update(&mut self, input: AutomationInput) -> Result<(), String> {
+        if let Some(schedule) = &input.schedule {
+            chrono::NaiveTime::parse_from_str(schedule, "%H:%M")
+                .map_err(|_| format!("schedule must be HH:MM: {}", schedule))?;
+        }
+        let actions = input
+            .actions
+            .into_iter()
+            .map(|x| {
+                let method = x.method.unwrap_or_else(|| "POST".to_owned()).to_uppercase();
+                automation::check_url(&x.url)?;
+                match method.as_str() {
+                    "GET" | "POST" | "PUT" => Ok(AutomationAction {
+                        url: x.url,
+                        method,
+                        body: x.body,
+                    }),
+                    _ => Err(format!("unsupported method: {}", method)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if input.schedule.is_some() && input.schedule != self.schedule {
+            self.armed_at = Some(Utc::now());
+        }
+        self.name = input.name;
+        self.rule = input.rule;
+        self.schedule = input.schedule;
+        self.actions = actions;
+        if let Some(enabled) = input.enabled {
+            self.enabled = enabled;
+        }
+        Ok(())
+    }
+
+    // The most recent scheduled time at or before `now`.
+    pub fn scheduled_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let time = chrono::NaiveTime::parse_from_str(self.schedule.as_ref()?, "%H:%M").ok()?;
+        let today = DateTime::from_utc(now.date().naive_utc().and_time(time), Utc);
+        Some(if today <= now {
+            today
+        } else {
+            today - Duration::days(1)
+        })
+    }
+}
+
+impl DynamoItem for Automation {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.id.to_owned()
+    }
+}
+
+#[Object]
+impl Automation {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    async fn rule(&self) -> Option<&str> {
+        self.rule.as_deref()
+    }
+
+    async fn schedule(&self) -> Option<&str> {
+        self.schedule.as_deref()
+    }
+
+    async fn actions(&self) -> Vec<AutomationAction> {
+        self.actions.clone()
+    }
+
+    async fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    async fn last_run_at(&self) -> Option<String> {
+        self.last_run_at.map(|x| format!("{:?}", &x))
+    }
+
+    async fn version(&self) -> i64 {
+        self.version
+    }
+}
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum EventKind {
+    Alert,
+    DeviceAttention,
+    AutomationRun,
+}
+
+#[derive(InputObject)]
+pub struct WebhookTargetInput {
+    pub name: String,
+    pub url: String,
+    // Event kinds to deliver; all kinds when omitted.
+    pub events: Option<Vec<EventKind>>,
+    pub enabled: Option<bool>,
+}
+
+// An external endpoint that events are pushed to. Requests are signed the
+// same way as signed ingestion, with the target's secret.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookTarget {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub id: String,
+
+    pub name: String,
+    pub url: String,
+
+    // Never exposed through GraphQL after creation.
+    pub secret: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<EventKind>>,
+
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub version: i64,
+}
+
+impl WebhookTarget {
+    pub fn new(id: String, secret: String, input: WebhookTargetInput) -> Self {
+        let mut target = Self {
+            pk: "WEBHOOK_TARGET".to_owned(),
+            id,
+            name: String::new(),
+            url: String::new(),
+            secret,
+            events: None,
+            enabled: true,
+            version: 0,
+        };
+        target.update(input);
+        target
+    }
+
+    pub fn update(&mut self, input: WebhookTargetInput) {
+        self.name = input.name;
+        self.url = input.url;
+        self.events = input.events;
+        if let Some(enabled) = input.enabled {
+            self.enabled = enabled;
+        }
+    }
+
+    pub fn accepts(&self, event: EventKind) -> bool {
+        self.enabled && self.events.as_ref().map_or(true, |x| x.contains(&event))
+    }
+}
+
+impl DynamoItem for WebhookTarget {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.id.to_owned()
+    }
+}
+
+#[Object]
+impl WebhookTarget {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    async fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    async fn events(&self) -> Option<Vec<EventKind>> {
+        self.events.clone()
+    }
+
+    async fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    async fn version(&self) -> i64 {
+        self.version
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct CreatedWebhookTarget {
+    pub target: WebhookTarget,
+    pub secret: String,
+}
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+// One event sent to one target. Pending deliveries carry next_attempt_at;
+// it is removed once the delivery succeeds or gives up.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub id: String,
+
+    pub target: String,
+    pub event: EventKind,
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub created_at: DateTime<Utc>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_attempt_at: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    // Epoch seconds, used as the table's TTL attribute.
+    pub ttl: i64,
+}
+
+impl WebhookDelivery {
+    pub fn new(
+        target: &WebhookTarget,
+        event: EventKind,
+        payload: String,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            pk: "WEBHOOK_DELIVERY".to_owned(),
+            id: format!("{:?}#{}", now, uuid::Uuid::new_v4()),
+            target: target.id.clone(),
+            event,
+            payload,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            created_at: now,
+            next_attempt_at: Some(now),
+            last_attempt_at: None,
+            response_status: None,
+            error: None,
+            ttl: expires_at.timestamp(),
+        }
+    }
+}
+
+impl DynamoItem for WebhookDelivery {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.id.to_owned()
+    }
+}
+
+#[Object]
+impl WebhookDelivery {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn target(&self) -> &str {
+        self.target.as_str()
+    }
+
+    async fn event(&self) -> EventKind {
+        self.event
+    }
+
+    async fn payload(&self) -> &str {
+        self.payload.as_str()
+    }
+
+    async fn status(&self) -> DeliveryStatus {
+        self.status
+    }
+
+    async fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    async fn created_at(&self) -> String {
+        format!("{:?}", &self.created_at)
+    }
+
+    async fn next_attempt_at(&self) -> Option<String> {
+        self.next_attempt_at.map(|x| format!("{:?}", &x))
+    }
+
+    async fn last_attempt_at(&self) -> Option<String> {
+        self.last_attempt_at.map(|x| format!("{:?}", &x))
+    }
+
+    async fn response_status(&self) -> Option<u16> {
+        self.response_status
+    }
+
+    async fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TariffBand {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up_to_kwh: Option<Decimal>,
+
+    pub price_per_kwh: Decimal,
+}
+
+#[Object]
+impl TariffBand {
+    async fn up_to_kwh(&self) -> Option<String> {
+        self.up_to_kwh.map(|x| format!("{}", &x))
+    }
+
+    async fn price_per_kwh(&self) -> String {
+        format!("{}", &self.price_per_kwh)
+    }
+}
+
+#[derive(InputObject)]
+pub struct TariffBandInput {
+    pub up_to_kwh: Option<String>,
+    pub price_per_kwh: String,
+}
+
+#[derive(InputObject)]
+pub struct TariffInput {
+    pub name: String,
+    pub base_charge: String,
+    pub bands: Vec<TariffBandInput>,
+    pub export_price_per_kwh: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Tariff {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub id: String,
+
+    pub name: String,
+    pub base_charge: Decimal,
+    pub bands: Vec<TariffBand>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub export_price_per_kwh: Option<Decimal>,
+
+    #[serde(default)]
+    pub version: i64,
+}
+
+impl Tariff {
+    pub fn new(id: String) -> Self {
+        Self {
+            pk: "TARIFF".to_owned(),
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn update(&mut self, input: TariffInput) -> Result<(), rust_decimal::Error> {
+        self.name = input.name;
+        self.base_charge = input.base_charge.parse()?;
+        self.bands = input
+            .bands
+            .into_iter()
+            .map(|x| {
+                Ok(TariffBand {
+                    up_to_kwh: x.up_to_kwh.map(|x| x.parse()).transpose()?,
+                    price_per_kwh: x.price_per_kwh.parse()?,
+                })
+            })
+            .collect::<Result<_, rust_decimal::Error>>()?;
+        self.export_price_per_kwh = input.export_price_per_kwh.map(|x| x.parse()).transpose()?;
+        Ok(())
+    }
+}
+
+impl DynamoItem for Tariff {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.id.to_owned()
+    }
+}
+
+#[Object]
+impl Tariff {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    async fn base_charge(&self) -> String {
+        format!("{}", &self.base_charge)
+    }
+
+    async fn bands(&self) -> &[TariffBand] {
+        &self.bands
+    }
+
+    async fn export_price_per_kwh(&self) -> Option<String> {
+        self.export_price_per_kwh.map(|x| format!("{}", &x))
+    }
+
+    async fn version(&self) -> i64 {
+        self.version
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BandCost {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up_to_kwh: Option<Decimal>,
+
+    pub kwh: Decimal,
+    pub price_per_kwh: Decimal,
+    pub cost: Decimal,
+}
+
+#[Object]
+impl BandCost {
+    async fn up_to_kwh(&self) -> Option<String> {
+        self.up_to_kwh.map(|x| format!("{}", &x))
+    }
+
+    async fn kwh(&self) -> String {
+        format!("{}", &self.kwh)
+    }
+
+    async fn price_per_kwh(&self) -> String {
+        format!("{}", &self.price_per_kwh)
+    }
+
+    async fn cost(&self) -> String {
+        format!("{}", &self.cost)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TariffScenario {
+    pub tariff: String,
+    pub name: String,
+    pub cost: Decimal,
+}
+
+#[Object]
+impl TariffScenario {
+    async fn tariff(&self) -> &str {
+        self.tariff.as_str()
+    }
+
+    async fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    async fn cost(&self) -> String {
+        format!("{}", &self.cost)
+    }
+}
+
+// Grid carbon intensity for a settlement period, in gCO2 per kWh.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CarbonIntensity {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    #[serde(with = "dynamodb_timestamp")]
+    pub from: DateTime<Utc>,
+
+    pub to: DateTime<Utc>,
+    pub g_per_kwh: f64,
+
+    // Forecasts are replaced once the actual value is published.
+    pub forecast: bool,
+}
+
+impl CarbonIntensity {
+    pub fn new(from: DateTime<Utc>, to: DateTime<Utc>, g_per_kwh: f64, forecast: bool) -> Self {
+        Self {
+            pk: "CARBON_INTENSITY".to_owned(),
+            from,
+            to,
+            g_per_kwh,
+            forecast,
+        }
+    }
+}
+
+impl DynamoItem for CarbonIntensity {
+    fn sk_prefix() -> String {
+        "TS#".to_owned()
+    }
+
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        format!("{:?}", self.from)
+    }
+}
+
+#[Object]
+impl CarbonIntensity {
+    async fn from(&self) -> String {
+        format!("{:?}", &self.from)
+    }
+
+    async fn to(&self) -> String {
+        format!("{:?}", &self.to)
+    }
+
+    async fn g_per_kwh(&self) -> String {
+        format!("{}", &self.g_per_kwh)
+    }
+
+    async fn forecast(&self) -> bool {
+        self.forecast
+    }
+}
+
+pub struct CarbonFootprint {
+    pub device: String,
+    pub imported_kwh: Decimal,
+    pub kg_co2: f64,
+    // Energy used while no intensity was known; it adds nothing to kg_co2.
+    pub uncovered_kwh: Decimal,
+    // Average intensity of the covered energy.
+    pub g_per_kwh: Option<f64>,
+}
+
+#[Object]
+impl CarbonFootprint {
+    async fn device(&self) -> &str {
+        self.device.as_str()
+    }
+
+    async fn imported_kwh(&self) -> String {
+        format!("{}", &self.imported_kwh)
+    }
+
+    async fn kg_co2(&self) -> String {
+        format!("{:.3}", &self.kg_co2)
+    }
+
+    async fn uncovered_kwh(&self) -> String {
+        format!("{}", &self.uncovered_kwh)
+    }
+
+    async fn g_per_kwh(&self) -> Option<String> {
+        self.g_per_kwh.map(|x| format!("{:.1}", &x))
+    }
+}
+
+// Energy of the metered devices in one place over a period, and the share of
+// the bill allocated to the place by that energy.
+pub struct PlaceCost {
+    pub place: String,
+    pub name: Option<String>,
+    pub devices: Vec<String>,
+    pub imported_kwh: Decimal,
+    pub exported_kwh: Decimal,
+    pub cost: Decimal,
+    pub share_percent: Option<Decimal>,
+}
+
+#[Object]
+impl PlaceCost {
+    async fn place(&self) -> &str {
+        self.place.as_str()
+    }
+
+    async fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    async fn devices(&self) -> Vec<String> {
+        self.devices.clone()
+    }
+
+    async fn imported_kwh(&self) -> String {
+        format!("{}", &self.imported_kwh)
+    }
+
+    async fn exported_kwh(&self) -> String {
+        format!("{}", &self.exported_kwh)
+    }
+
+    async fn cost(&self) -> String {
+        format!("{}", &self.cost)
+    }
+
+    async fn share_percent(&self) -> Option<String> {
+        self.share_percent.map(|x| format!("{}", &x))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BillingStatement {
+    #[serde(rename = "pk")]
+    pub id: String,
+
+    #[serde(rename = "sk")]
+    #[serde(with = "dynamodb_statement_ts")]
+    pub period_start: DateTime<Utc>,
+
+    pub period_end: DateTime<Utc>,
+
+    #[serde(default)]
+    pub place: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tariff: Option<String>,
+
+    pub imported_kwh: Decimal,
+    pub exported_kwh: Decimal,
+    pub base_charge: Decimal,
+    pub bands: Vec<BandCost>,
+    pub export_credit: Decimal,
+    pub cost: Decimal,
+
+    #[serde(default)]
+    pub scenarios: Vec<TariffScenario>,
+}
+
+impl DynamoItem for BillingStatement {
+    fn sk_prefix() -> String {
+        "STMT#TS#".to_owned()
+    }
+
+    fn pk(&self) -> String {
+        self.id.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        format!("{:?}", &self.period_start)
+    }
+}
+
+#[Object]
+impl BillingStatement {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn period_start(&self) -> String {
+        format!("{:?}", &self.period_start)
+    }
+
+    async fn period_end(&self) -> String {
+        format!("{:?}", &self.period_end)
+    }
+
+    async fn place(&self) -> &str {
+        self.place.as_str()
+    }
+
+    async fn tariff(&self) -> Option<&str> {
+        self.tariff.as_deref()
+    }
+
+    async fn imported_kwh(&self) -> String {
+        format!("{}", &self.imported_kwh)
+    }
+
+    async fn exported_kwh(&self) -> String {
+        format!("{}", &self.exported_kwh)
+    }
+
+    async fn base_charge(&self) -> String {
+        format!("{}", &self.base_charge)
+    }
+
+    async fn bands(&self) -> &[BandCost] {
+        &self.bands
+    }
+
+    async fn export_credit(&self) -> String {
+        format!("{}", &self.export_credit)
+    }
+
+    async fn cost(&self) -> String {
+        format!("{}", &self.cost)
+    }
+
+    async fn scenarios(&self) -> &[TariffScenario] {
+        &self.scenarios
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Backup {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub id: String,
+
+    pub name: String,
+    pub table: String,
+    pub arn: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Backup {
+    pub fn new(
+        name: String,
+        table: String,
+        arn: String,
+        status: String,
+        created_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            pk: "BACKUP".to_owned(),
+            id: format!("{:?}#{}", created_at, name),
+            name,
+            table,
+            arn,
+            status,
+            created_at,
+        }
+    }
+}
+
+impl DynamoItem for Backup {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.id.to_owned()
+    }
+}
+
+#[Object]
+impl Backup {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    async fn table(&self) -> &str {
+        self.table.as_str()
+    }
+
+    async fn arn(&self) -> &str {
+        self.arn.as_str()
+    }
+
+    async fn status(&self) -> &str {
+        self.status.as_str()
+    }
+
+    async fn created_at(&self) -> String {
+        format!("{:?}", &self.created_at)
+    }
+}
+
+macro_rules! dynamodb_prefixed_timestamp {
+    ($name:ident, $prefix:expr) => {
+        mod $name {
+            use chrono::{DateTime, Utc};
+            use serde::{Deserialize, Deserializer, Serializer};
+
+            pub fn serialize<S>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.serialize_str(&format!(concat!($prefix, "{:?}"), timestamp))
+            }
+
+            pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                let s = String::deserialize(deserializer)?;
+                match s.strip_prefix($prefix) {
+                    Some(prefix) => prefix.parse().map_err(serde::de::Error::custom),
+                    None => Err(serde::de::Error::custom("Invalid prefix")),
+                }
+            }
+        }
+    };
+}
+
+dynamodb_prefixed_timestamp!(dynamodb_timestamp, "TS#");
+dynamodb_prefixed_timestamp!(dynamodb_fin_ts, "FIN#TS#");
+dynamodb_prefixed_timestamp!(dynamodb_state_ts, "STATE#TS#");
+dynamodb_prefixed_timestamp!(dynamodb_solar_ts, "SOLAR#TS#");
+dynamodb_prefixed_timestamp!(dynamodb_battery_ts, "BATTERY#TS#");
+dynamodb_prefixed_timestamp!(dynamodb_statement_ts, "STMT#TS#");
+
+// Readings are written under TS#, and the compaction job copies summaries of
+// them under the tier prefixes, so they are read back from either.
+mod dynamodb_reading_ts {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const PREFIXES: [&str; 3] = ["TS#", "AGG5M#TS#", "AGG1H#TS#"];
+
+    pub fn serialize<S>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format!("TS#{:?}", timestamp))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match PREFIXES.iter().find_map(|x| s.strip_prefix(x)) {
+            Some(timestamp) => timestamp.parse().map_err(serde::de::Error::custom),
+            None => Err(serde::de::Error::custom("Invalid prefix")),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub key: String,
+
+    // Unset while the request holding the key is still running.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+
+    // Epoch seconds, used as the table's TTL attribute.
+    pub ttl: i64,
+}
+
+impl IdempotencyRecord {
+    pub fn new(key: String, response: Option<String>, expires_at: DateTime<Utc>) -> Self {
+        Self {
+            pk: "IDEMPOTENCY".to_owned(),
+            key,
+            response,
+            ttl: expires_at.timestamp(),
+        }
+    }
+}
+
+impl DynamoItem for IdempotencyRecord {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.key.to_owned()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UsedSignature {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub signature: String,
+
+    // Epoch seconds, used as the table's TTL attribute.
+    pub ttl: i64,
+}
+
+impl UsedSignature {
+    pub fn new(signature: String, expires_at: DateTime<Utc>) -> Self {
+        Self {
+            pk: "SIGNATURE".to_owned(),
+            signature,
+            ttl: expires_at.timestamp(),
+        }
+    }
+}
+
+impl DynamoItem for UsedSignature {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.signature.to_owned()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ClaimCode {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub code: String,
+
+    pub device: String,
+
+    // Epoch seconds, used as the table's TTL attribute.
+    pub ttl: i64,
+}
+
+impl ClaimCode {
+    pub fn new(code: String, device: String, expires_at: DateTime<Utc>) -> Self {
+        Self {
+            pk: "CLAIM".to_owned(),
+            code,
+            device,
+            ttl: expires_at.timestamp(),
+        }
+    }
+}
+
+impl DynamoItem for ClaimCode {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.code.to_owned()
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct ProvisionedDevice {
+    pub device: Device,
+    pub claim_code: String,
+}
+
+#[derive(SimpleObject)]
+pub struct DeviceCredentials {
+    pub device: String,
+    pub secret: String,
+}
+
+// The highest average import of a device over a month, kept up to date as
+// readings are written. `month` is "YYYY-MM".
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeakDemand {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub month: String,
+
+    pub device: String,
+    pub window_minutes: i64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub average_w: f64,
+}
+
+impl PeakDemand {
+    pub fn new(
+        device: String,
+        month: String,
+        window_minutes: i64,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        average_w: f64,
+    ) -> Self {
+        Self {
+            pk: format!("PEAK_DEMAND#{}", device),
+            month,
+            device,
+            window_minutes,
+            start,
+            end,
+            average_w,
+        }
+    }
+}
+
+impl DynamoItem for PeakDemand {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.month.to_owned()
+    }
+}
+
+#[Object]
+impl PeakDemand {
+    async fn device(&self) -> &str {
+        self.device.as_str()
+    }
+
+    async fn month(&self) -> &str {
+        self.month.as_str()
+    }
+
+    async fn window_minutes(&self) -> i64 {
+        self.window_minutes
+    }
+
+    async fn start(&self) -> String {
+        format!("{:?}", &self.start)
+    }
+
+    async fn end(&self) -> String {
+        format!("{:?}", &self.end)
+    }
+
+    async fn average_w(&self) -> String {
+        format!("{:.1}", &self.average_w)
+    }
+}
+
+// How far the compaction job has summarized and deleted a device's raw
+// readings. Readings before `until` only exist in the summary tiers.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Compaction {
+    pk: String,
+
+    #[serde(rename = "sk")]
+    pub device: String,
+
+    pub until: DateTime<Utc>,
+}
+
+impl Compaction {
+    pub fn new(device: String, until: DateTime<Utc>) -> Self {
+        Self {
+            pk: "COMPACTION".to_owned(),
+            device,
+            until,
+        }
+    }
+}
+
+impl DynamoItem for Compaction {
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        self.device.to_owned()
+    }
+}
+
+// One changed attribute of a configuration item. Values are JSON, so that a
+// change can be reverted by writing `before` back.
+#[derive(Clone, Debug, Serialize, Deserialize, SimpleObject)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+// A recorded update of a device, place or tariff. Revisions of an item are
+// numbered from 1 under "<KIND>#<id>", with sk "V#<n>".
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Revision {
+    pk: String,
+    sk: String,
+
+    pub kind: String,
+    pub id: String,
+    pub revision: i64,
+    pub changed_at: DateTime<Utc>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed_by: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+
+    pub changes: Vec<FieldChange>,
+}
+
+impl Revision {
+    pub fn new(
+        kind: String,
+        id: String,
+        revision: i64,
+        changed_by: Option<String>,
+        request_id: Option<String>,
+        changes: Vec<FieldChange>,
+    ) -> Self {
+        Self {
+            pk: format!("{}#{}", kind, id),
+            sk: format!("{}{:010}", Self::sk_prefix(), revision),
+            kind,
+            id,
+            revision,
+            changed_at: Utc::now(),
+            changed_by,
+            request_id,
+            changes,
+        }
+    }
+}
+
+impl DynamoItem for Revision {
+    fn sk_prefix() -> String {
+        "V#".to_owned()
+    }
+
+    fn pk(&self) -> String {
+        self.pk.to_owned()
+    }
+
+    fn sk_value(&self) -> String {
+        format!("{:010}", self.revision)
+    }
+}
+
+#[Object]
+impl Revision {
+    async fn kind(&self) -> &str {
+        self.kind.as_str()
+    }
+
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    async fn changed_at(&self) -> String {
+        format!("{:?}", &self.changed_at)
+    }
+
+    async fn changed_by(&self) -> Option<&str> {
+        self.changed_by.as_deref()
+    }
+
+    async fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    async fn changes(&self) -> &Vec<FieldChange> {
+        &self.changes
     }
 }
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use serde_json::json;
+
+use crate::models::Alert;
+
+static REQWEST: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::ClientBuilder::new()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap()
+});
+static DEVICE_URL_BASE: Lazy<Option<String>> = Lazy::new(|| std::env::var("DEVICE_URL_BASE").ok());
+
+pub enum Channel {
+    Log,
+    Slack(String),
+    Discord(String),
+}
+
+impl Channel {
+    pub fn parse(channel: &str) -> Result<Self> {
+        match channel.split_once(':') {
+            Some(("slack", url)) => Ok(Channel::Slack(url.to_owned())),
+            Some(("discord", url)) => Ok(Channel::Discord(url.to_owned())),
+            _ if channel.is_empty() || channel == "log" => Ok(Channel::Log),
+            _ => Err(anyhow!("unknown notification channel: {}", channel)),
+        }
+    }
+}
+
+fn device_url(device: &str) -> Option<String> {
+    DEVICE_URL_BASE
+        .as_ref()
+        .map(|base| format!("{}/devices/{}", base.trim_end_matches('/'), device))
+}
+
+async fn post(url: &str, body: &serde_json::Value) -> Result<()> {
+    REQWEST
+        .post(url)
+        .json(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+// Sends a plain message such as a report.
+pub async fn send(channel: &str, title: &str, text: &str) -> Result<()> {
+    match Channel::parse(channel)? {
+        Channel::Log => println!("{}\n{}", title, text),
+        Channel::Slack(url) => {
+            let text = format!("*{}*\n```\n{}\n```", title, text);
+            post(&url, &json!({ "text": text })).await?;
+        }
+        Channel::Discord(url) => {
+            let content = format!("**{}**\n```\n{}\n```", title, text);
+            post(&url, &json!({ "content": content })).await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn notify(channel: &str, alert: &Alert) -> Result<()> {
+    match Channel::parse(channel)? {
+        Channel::Log => println!(
+            "[{:?}] {}: {} ({})",
+            alert.timestamp, alert.device, alert.message, alert.value
+        ),
+        Channel::Slack(url) => {
+            let device = match device_url(&alert.device) {
+                Some(link) => format!("<{}|{}>", link, alert.device),
+                None => format!("`{}`", alert.device),
+            };
+            let text = format!(
+                ":rotating_light: *{}*\nDevice: {}\nValue: {}\nAt: {:?}",
+                alert.message, device, alert.value, alert.timestamp
+            );
+            post(&url, &json!({ "text": text })).await?;
+        }
+        Channel::Discord(url) => {
+            let device = match device_url(&alert.device) {
+                Some(link) => format!("[{}]({})", alert.device, link),
+                None => format!("`{}`", alert.device),
+            };
+            let content = format!(
+                ":rotating_light: **{}**\nDevice: {}\nValue: {}\nAt: {:?}",
+                alert.message, device, alert.value, alert.timestamp
+            );
+            post(&url, &json!({ "content": content })).await?;
+        }
+    }
+
+    Ok(())
+}
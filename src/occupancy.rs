@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::dynamodb::{attr_number, Client, Condition, Filter};
+use crate::models::{live, Device, Occupancy};
+
+// A place stays occupied until no motion is seen for this long.
+static TIMEOUT_MINUTES: Lazy<i64> = Lazy::new(|| {
+    std::env::var("OCCUPANCY_TIMEOUT_MINUTES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(15)
+});
+// Runs with fewer pulses are treated as noise, e.g. a pet or a sensor
+// glitch.
+static MIN_PULSES: Lazy<u32> = Lazy::new(|| {
+    std::env::var("OCCUPANCY_MIN_PULSES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(2)
+});
+// How far back a place without recent occupancy is read.
+const LOOKBACK_HOURS: i64 = 24;
+
+#[derive(Debug, Deserialize)]
+struct Pulse {
+    sk: String,
+}
+
+impl Pulse {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.sk.strip_prefix("TS#").and_then(|x| x.parse().ok())
+    }
+}
+
+// Groups sorted pulses into runs. A run ends TIMEOUT_MINUTES after its
+// last pulse, and a run that has not ended yet is left open.
+fn intervals(place: &str, pulses: &[DateTime<Utc>], now: DateTime<Utc>) -> Vec<Occupancy> {
+    let timeout = Duration::minutes(*TIMEOUT_MINUTES);
+    let mut runs: Vec<Occupancy> = Vec::new();
+
+    for &t in pulses {
+        match runs.last_mut() {
+            Some(x) if x.to.map_or(false, |end| t <= end) => {
+                x.pulses += 1;
+                x.to = Some(t + timeout);
+            }
+            _ => {
+                let mut x = Occupancy::new(place.to_owned(), t);
+                x.pulses = 1;
+                x.to = Some(t + timeout);
+                runs.push(x);
+            }
+        }
+    }
+
+    for x in runs.iter_mut() {
+        x.to = x.to.filter(|end| *end <= now);
+    }
+    runs.retain(|x| x.pulses >= *MIN_PULSES);
+    runs
+}
+
+async fn pulses(
+    dynamodb: &Client,
+    devices: &[&Device],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<DateTime<Utc>>> {
+    let mut pulses = Vec::new();
+
+    for device in devices {
+        let sk = Condition::Between(format!("TS#{:?}", from), format!("TS#{:?}", to));
+        let filter = vec![Filter::Gt("motion".to_owned(), attr_number(0))];
+        let items: Vec<Pulse> = dynamodb
+            .get_filtered_range(&device.id, Some(sk), filter)
+            .await?;
+        pulses.extend(items.iter().filter_map(|x| x.timestamp()));
+    }
+
+    pulses.sort();
+    Ok(pulses)
+}
+
+// Turns motion readings of every place into Occupancy items. Each run picks
+// up from the place's last interval: an open one is recomputed with the
+// pulses seen since, and a closed one is left as is.
+pub async fn analyze(dynamodb: &Client, now: DateTime<Utc>) -> Result<usize> {
+    let devices: Vec<Device> = live(dynamodb.get_range("DEVICE", None).await?);
+    let mut places: BTreeMap<&str, Vec<&Device>> = BTreeMap::new();
+    for device in devices.iter().filter(|x| !x.place.is_empty()) {
+        places.entry(&device.place).or_default().push(device);
+    }
+    let mut count = 0;
+
+    for (place, devices) in places {
+        let pk = format!("OCCUPANCY#{}", place);
+        let last: Option<Occupancy> = dynamodb
+            .get_last_item(&pk, Condition::Le(format!("{:?}", now)))
+            .await?;
+        let lookback = now - Duration::hours(LOOKBACK_HOURS);
+        let from = match last {
+            Some(Occupancy { from, to: None, .. }) => from,
+            Some(Occupancy { to: Some(to), .. }) => to.max(lookback),
+            None => lookback,
+        };
+
+        let pulses = pulses(dynamodb, &devices, from, now).await?;
+        for x in intervals(place, &pulses, now) {
+            dynamodb.put_item(&x).await?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,244 @@
+use serde_json::{json, Value};
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": { "schema": { "$ref": "#/components/schemas/Errors" } }
+        },
+    })
+}
+
+fn text_response(description: &str, content_type: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { content_type: { "schema": { "type": "string" } } },
+    })
+}
+
+fn parameter(name: &str, location: &str, description: Option<&str>, schema: Value) -> Value {
+    let mut x = json!({ "name": name, "in": location, "schema": schema });
+    if location == "path" {
+        x["required"] = json!(true);
+    }
+    if let Some(description) = description {
+        x["description"] = json!(description);
+    }
+    x
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn device_id() -> Value {
+    parameter("x-device-id", "header", None, string())
+}
+
+fn timestamp() -> Value {
+    parameter(
+        "x-timestamp",
+        "header",
+        Some("Unix time in seconds"),
+        json!({ "type": "integer", "format": "int64" }),
+    )
+}
+
+fn signature() -> Value {
+    parameter(
+        "x-signature",
+        "header",
+        Some("Hex-encoded HMAC-SHA256"),
+        string(),
+    )
+}
+
+fn ingest_webhook() -> Value {
+    let written = json!({
+        "type": "object",
+        "properties": { "written": { "type": "integer" } },
+        "required": ["written"],
+    });
+    json!({
+        "operationId": "ingestWebhook",
+        "summary": "Ingest readings through the webhook rule for `source`",
+        "description": "The body is mapped to readings by the rule. Rules that require \
+                        signatures take the device, the timestamp and an HMAC-SHA256 of \
+                        `<timestamp>.<body>` with the device secret in headers.",
+        "parameters": [
+            parameter("source", "path", None, string()),
+            device_id(),
+            timestamp(),
+            signature(),
+        ],
+        "requestBody": {
+            "required": true,
+            "content": { "application/json": { "schema": {} } },
+        },
+        "responses": {
+            "200": {
+                "description": "Readings written",
+                "content": { "application/json": { "schema": written } },
+            },
+            "400": error_response("The body or a reading is invalid"),
+            "401": error_response("Signature missing or wrong"),
+            "404": text_response("No rule for the source", "text/plain"),
+        },
+    })
+}
+
+fn write_line_protocol() -> Value {
+    let precision = json!({
+        "type": "string",
+        "enum": ["ns", "n", "us", "u", "ms", "s"],
+        "default": "ns",
+    });
+    json!({
+        "operationId": "writeLineProtocol",
+        "summary": "Ingest readings in InfluxDB line protocol",
+        "description": "The measurement picks the reading kind. The `device` tag is the \
+                        device and the optional `place` tag its place. Devices with a \
+                        secret only accept bodies signed as for webhooks, and a signed \
+                        body may only carry readings for the signing device.",
+        "parameters": [
+            parameter("precision", "query", None, precision),
+            device_id(),
+            timestamp(),
+            signature(),
+        ],
+        "requestBody": {
+            "required": true,
+            "content": { "text/plain": { "schema": string() } },
+        },
+        "responses": {
+            "204": { "description": "Readings written" },
+            "400": error_response("A line is invalid"),
+            "401": error_response("Signature missing or wrong"),
+        },
+    })
+}
+
+fn list_sensors() -> Value {
+    let sensors = json!({
+        "type": "array",
+        "items": { "$ref": "#/components/schemas/Sensor" },
+    });
+    json!({
+        "operationId": "listSensors",
+        "summary": "Latest values of every sensor, for Home Assistant",
+        "responses": {
+            "200": {
+                "description": "Sensors",
+                "content": { "application/json": { "schema": sensors } },
+            }
+        },
+    })
+}
+
+fn get_sensor_state() -> Value {
+    json!({
+        "operationId": "getSensorState",
+        "summary": "Latest value of one metric of a device",
+        "parameters": [
+            parameter("device", "path", None, string()),
+            parameter("metric", "path", None, string()),
+        ],
+        "responses": {
+            "200": text_response("The state", "text/plain"),
+            "404": text_response("Unknown device or metric", "text/plain"),
+        },
+    })
+}
+
+fn prometheus(id: &str, summary: &str) -> Value {
+    json!({
+        "operationId": id,
+        "summary": summary,
+        "responses": { "200": text_response("Metrics", "text/plain; version=0.0.4") },
+    })
+}
+
+fn errors_schema() -> Value {
+    let error = json!({
+        "type": "object",
+        "properties": {
+            "message": string(),
+            "extensions": {
+                "type": "object",
+                "properties": {
+                    "code": string(),
+                    "field": { "type": "string", "nullable": true },
+                },
+            },
+        },
+        "required": ["message"],
+    });
+    json!({
+        "type": "object",
+        "properties": { "errors": { "type": "array", "items": error } },
+        "required": ["errors"],
+    })
+}
+
+fn sensor_schema() -> Value {
+    let nullable = json!({ "type": "string", "nullable": true });
+    json!({
+        "type": "object",
+        "properties": {
+            "unique_id": string(),
+            "name": string(),
+            "device_id": string(),
+            "place": string(),
+            "metric": string(),
+            "state": string(),
+            "unit_of_measurement": nullable,
+            "device_class": nullable,
+            "state_class": string(),
+            "last_updated": { "type": "string", "format": "date-time" },
+        },
+        "required": [
+            "unique_id",
+            "name",
+            "device_id",
+            "place",
+            "metric",
+            "state",
+            "state_class",
+            "last_updated",
+        ],
+    })
+}
+
+// OpenAPI 3 description of the routes served next to GraphQL. It is written
+// by hand, so a new route in the homeapi binary needs an entry here too.
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "homeapi",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "The routes outside GraphQL. The GraphQL endpoint is POST /.",
+        },
+        "paths": {
+            "/webhook/{source}": { "post": ingest_webhook() },
+            "/write": { "post": write_line_protocol() },
+            "/ha/sensors": { "get": list_sensors() },
+            "/ha/sensors/{device}/{metric}": { "get": get_sensor_state() },
+            "/metrics/sensors": {
+                "get": prometheus(
+                    "sensorMetrics",
+                    "Latest sensor values in the Prometheus text format",
+                )
+            },
+            "/metrics/dynamodb": {
+                "get": prometheus(
+                    "dynamodbMetrics",
+                    "DynamoDB request counters in the Prometheus text format",
+                )
+            },
+        },
+        "components": {
+            "schemas": { "Errors": errors_schema(), "Sensor": sensor_schema() }
+        },
+    })
+}
@@ -0,0 +1,70 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+
+use crate::dynamodb::Client;
+use crate::homeassistant::{self, Sensor};
+
+// Scrapers poll frequently, and each scrape reads the last item of every
+// device, so the rendered page is reused for a short while.
+const CACHE_SECONDS: u64 = 15;
+
+static CACHE: Lazy<Mutex<Option<(Instant, String)>>> = Lazy::new(|| Mutex::new(None));
+
+// (sensor metric, gauge name, help)
+const GAUGES: &[(&str, &str, &str)] = &[
+    (
+        "temperature",
+        "homeapi_temperature_celsius",
+        "Latest temperature reading.",
+    ),
+    (
+        "humidity",
+        "homeapi_humidity_percent",
+        "Latest relative humidity reading.",
+    ),
+    ("power", "homeapi_current_watts", "Latest power reading."),
+];
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub fn render(sensors: &[Sensor]) -> String {
+    let mut out = String::new();
+
+    for (metric, name, help) in GAUGES {
+        out += &format!("# HELP {} {}\n# TYPE {} gauge\n", name, help, name);
+        for x in sensors.iter().filter(|x| x.metric == *metric) {
+            if let Ok(value) = x.state.parse::<f64>() {
+                out += &format!(
+                    "{}{{device=\"{}\",place=\"{}\"}} {}\n",
+                    name,
+                    escape(&x.device_id),
+                    escape(&x.place),
+                    value
+                );
+            }
+        }
+    }
+
+    out
+}
+
+pub async fn sensors(dynamodb: &Client) -> Result<String> {
+    let ttl = Duration::from_secs(CACHE_SECONDS);
+    if let Some((at, page)) = CACHE.lock().unwrap().as_ref() {
+        if at.elapsed() < ttl {
+            return Ok(page.clone());
+        }
+    }
+
+    let page = render(&homeassistant::sensors(dynamodb).await?);
+    *CACHE.lock().unwrap() = Some((Instant::now(), page.clone()));
+
+    Ok(page)
+}
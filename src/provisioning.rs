@@ -0,0 +1,66 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+
+use crate::dynamodb::Client;
+use crate::error::{ApiError, Code};
+use crate::models::{ClaimCode, Device, DeviceCredentials, DeviceState, Place, ProvisionedDevice};
+use crate::signature;
+
+// Unclaimed codes expire so that a leaked label is only useful for a while.
+const CLAIM_CODE_HOURS: i64 = 72;
+
+pub async fn provision(dynamodb: &Client, id: String, place: String) -> Result<ProvisionedDevice> {
+    dynamodb.get_item::<Place>("PLACE", &place).await?;
+
+    let mut device = Device::new(id);
+    device.place = place;
+    device.state = DeviceState::Pending;
+    if !dynamodb.put_item_if_absent(&device).await? {
+        return Err(ApiError::new(Code::Conflict, "device already exists").into());
+    }
+
+    let claim = ClaimCode::new(
+        signature::new_secret(),
+        device.id.clone(),
+        Utc::now() + Duration::hours(CLAIM_CODE_HOURS),
+    );
+    dynamodb.put_item(&claim).await?;
+
+    Ok(ProvisionedDevice {
+        device,
+        claim_code: claim.code,
+    })
+}
+
+// The code is deleted only once the device holds its secret, so a failed
+// update leaves it usable. It works once because only a pending device can be
+// claimed, and the versioned write lets one of two concurrent claims win.
+pub async fn claim(dynamodb: &Client, code: &str) -> Result<DeviceCredentials> {
+    let invalid = || ApiError::new(Code::Unauthorized, "invalid claim code");
+    let claim = dynamodb
+        .find_item::<ClaimCode>("CLAIM", code)
+        .await?
+        .filter(|x| x.ttl > Utc::now().timestamp())
+        .ok_or_else(invalid)?;
+
+    let mut device: Device = dynamodb.get_item("DEVICE", &claim.device).await?;
+    if device.state != DeviceState::Pending || device.deleted_at.is_some() {
+        return Err(invalid().into());
+    }
+    let version = device.version;
+    let secret = signature::new_secret();
+    device.secret = Some(secret.clone());
+    device.state = DeviceState::Active;
+    device.version += 1;
+    dynamodb.put_item_versioned(&device, version).await?;
+
+    // The device is claimed, so the code can't be used again even if it stays.
+    if let Err(e) = dynamodb.delete_item("CLAIM", code).await {
+        println!("claim code for {}: {:?}", device.id, e);
+    }
+
+    Ok(DeviceCredentials {
+        device: device.id,
+        secret,
+    })
+}
@@ -0,0 +1,229 @@
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+
+use crate::aggregation;
+use crate::billing;
+use crate::dynamodb::{Client, Condition};
+use crate::models::{live, Device, Tariff};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Period {
+    Daily,
+    Weekly,
+}
+
+impl Period {
+    pub fn parse(period: &str) -> Result<Self> {
+        match period {
+            "daily" => Ok(Period::Daily),
+            "weekly" => Ok(Period::Weekly),
+            _ => Err(anyhow!("unknown report period: {}", period)),
+        }
+    }
+
+    pub fn duration(self) -> Duration {
+        match self {
+            Period::Daily => Duration::days(1),
+            Period::Weekly => Duration::weeks(1),
+        }
+    }
+}
+
+// Meters and environment sensors share the TS# prefix, so both kinds of
+// fields are optional.
+#[derive(Debug, Deserialize)]
+struct Reading {
+    sk: String,
+    cumulative_kwh_p: Option<Decimal>,
+    cumulative_kwh_n: Option<Decimal>,
+    temperature: Option<f64>,
+}
+
+impl Reading {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.sk.strip_prefix("TS#").and_then(|x| x.parse().ok())
+    }
+}
+
+pub struct Energy {
+    pub device: String,
+    pub imported_kwh: Decimal,
+    pub exported_kwh: Decimal,
+    // Band prices prorated from the billing month, less export credit; the
+    // fixed base charge is left out since it is billed per month.
+    pub cost: Option<Decimal>,
+}
+
+pub struct Temperature {
+    pub device: String,
+    pub place: String,
+    pub min: (f64, DateTime<Utc>),
+    pub max: (f64, DateTime<Utc>),
+}
+
+pub struct Report {
+    pub period: Period,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub energy: Vec<Energy>,
+    pub temperatures: Vec<Temperature>,
+    pub offline: Vec<Device>,
+}
+
+// Tiers are per billing month, so the period's energy is priced as if it went
+// on at the same rate for the whole month and the cost is scaled back.
+fn prorated_cost(
+    tariff: &Tariff,
+    month: Duration,
+    period: Duration,
+    imported_kwh: Decimal,
+) -> Decimal {
+    let factor = Decimal::from(month.num_seconds()) / Decimal::from(period.num_seconds());
+    let bands: Decimal = billing::band_costs(tariff, imported_kwh * factor)
+        .iter()
+        .map(|x| x.cost)
+        .sum();
+    bands / factor
+}
+
+// Energy from the meter readings at the start and the end of the period.
+fn energy(
+    device: &Device,
+    tariffs: &[Tariff],
+    report: &Report,
+    first: &Reading,
+    last: &Reading,
+) -> Option<Energy> {
+    let imported_kwh =
+        aggregation::delta(first.cumulative_kwh_p?, last.cumulative_kwh_p?)?.round_dp(3);
+    let exported_kwh =
+        aggregation::delta(first.cumulative_kwh_n?, last.cumulative_kwh_n?)?.round_dp(3);
+
+    let (start, end) = billing::billing_period(&billing::anchor(device), report.to);
+    let cost = device
+        .tariff
+        .as_ref()
+        .and_then(|id| tariffs.iter().find(|x| &x.id == id))
+        .map(|tariff| {
+            let period = report.to - report.from;
+            let bands = prorated_cost(tariff, end - start, period, imported_kwh);
+            (bands - billing::export_credit(tariff, exported_kwh)).round_dp(2)
+        });
+
+    Some(Energy {
+        device: device.id.clone(),
+        imported_kwh,
+        exported_kwh,
+        cost,
+    })
+}
+
+fn temperature(device: &Device, readings: &[Reading]) -> Option<Temperature> {
+    let values = readings
+        .iter()
+        .filter_map(|x| Some((x.temperature?, x.timestamp()?)))
+        .collect::<Vec<_>>();
+    let min = values
+        .iter()
+        .copied()
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))?;
+    let max = values
+        .iter()
+        .copied()
+        .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))?;
+
+    Some(Temperature {
+        device: device.id.clone(),
+        place: device.place.clone(),
+        min,
+        max,
+    })
+}
+
+pub async fn compose(dynamodb: &Client, period: Period, to: DateTime<Utc>) -> Result<Report> {
+    let from = to - period.duration();
+    let devices: Vec<Device> = live(dynamodb.get_range("DEVICE", None).await?);
+    let tariffs: Vec<Tariff> = dynamodb.get_range("TARIFF", None).await?;
+    let mut report = Report {
+        period,
+        from,
+        to,
+        energy: Vec::new(),
+        temperatures: Vec::new(),
+        offline: Vec::new(),
+    };
+
+    // Meters are read only at both ends of the period; the other sensors are
+    // read in full for their temperatures.
+    for device in devices {
+        let last: Option<Reading> = aggregation::reading_at(dynamodb, &device.id, to).await?;
+        if let Some(last) = last.filter(|x| x.cumulative_kwh_p.is_some()) {
+            let first: Option<Reading> =
+                aggregation::reading_at(dynamodb, &device.id, from).await?;
+            if let Some(x) = first.and_then(|x| energy(&device, &tariffs, &report, &x, &last)) {
+                report.energy.push(x);
+            }
+        } else {
+            let sk = Condition::Between(format!("TS#{:?}", from), format!("TS#{:?}", to));
+            let readings: Vec<Reading> = dynamodb.get_range(&device.id, Some(sk)).await?;
+            if let Some(x) = temperature(&device, &readings) {
+                report.temperatures.push(x);
+            }
+        }
+        if device.needs_attention.as_deref() == Some("offline") {
+            report.offline.push(device);
+        }
+    }
+
+    Ok(report)
+}
+
+impl Report {
+    pub fn title(&self) -> String {
+        let period = match self.period {
+            Period::Daily => "Daily",
+            Period::Weekly => "Weekly",
+        };
+        format!("{} report {:?} - {:?}", period, self.from, self.to)
+    }
+
+    pub fn text(&self) -> String {
+        let mut lines = Vec::new();
+
+        lines.push("Electricity:".to_owned());
+        for x in self.energy.iter() {
+            let cost = x
+                .cost
+                .map_or_else(String::new, |x| format!(", about {}", x));
+            lines.push(format!(
+                "  {}: {} kWh in, {} kWh out{}",
+                x.device, x.imported_kwh, x.exported_kwh, cost
+            ));
+        }
+
+        lines.push("Temperature:".to_owned());
+        for x in self.temperatures.iter() {
+            lines.push(format!(
+                "  {} ({}): min {:.1} at {:?}, max {:.1} at {:?}",
+                x.place, x.device, x.min.0, x.min.1, x.max.0, x.max.1
+            ));
+        }
+
+        lines.push("Offline:".to_owned());
+        if self.offline.is_empty() {
+            lines.push("  none".to_owned());
+        }
+        for x in self.offline.iter() {
+            let seen = x
+                .last_seen_at
+                .map_or_else(|| "never seen".to_owned(), |x| format!("last seen {:?}", x));
+            lines.push(format!("  {} ({}): {}", x.id, x.place, seen));
+        }
+
+        lines.join("\n")
+    }
+}
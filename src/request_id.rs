@@ -0,0 +1,73 @@
+use async_graphql::parser::{parse_query, types::OperationType};
+use async_graphql::{BatchRequest, BatchResponse, Request};
+use futures::future;
+
+use crate::graphql::HomeAPI;
+
+tokio::task_local! {
+    static CURRENT: String;
+}
+
+const MAX_LENGTH: usize = 128;
+
+fn valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+}
+
+// An X-Request-Id from the client is kept when it looks like an id, so that
+// it can't inject anything into logs or headers. Otherwise a new one is made.
+pub fn from_header(value: Option<&str>) -> String {
+    match value.filter(|x| valid(x)) {
+        Some(x) => x.to_owned(),
+        None => uuid::Uuid::new_v4().to_string(),
+    }
+}
+
+// The id of the request being executed, if any.
+pub fn current() -> Option<String> {
+    CURRENT.try_with(|x| x.clone()).ok()
+}
+
+// A request that doesn't parse fails on its own, so it isn't a mutation here.
+fn mutates(request: &Request) -> bool {
+    parse_query(&request.query).map_or(false, |x| {
+        x.operations
+            .iter()
+            .any(|(_, op)| op.node.ty == OperationType::Mutation)
+    })
+}
+
+// Mutations in a batch may depend on the ones before them, so a batch with
+// any mutation runs in order. Other batches run concurrently.
+async fn execute_batch(schema: &HomeAPI, request: BatchRequest) -> BatchResponse {
+    match request {
+        BatchRequest::Batch(requests) if !requests.iter().any(mutates) => {
+            let responses = requests.into_iter().map(|x| schema.execute(x));
+            BatchResponse::Batch(future::join_all(responses).await)
+        }
+        request => schema.execute_batch(request).await,
+    }
+}
+
+// Executes the request with `id` as the current request id and adds it to
+// the extensions of every error in the response.
+pub async fn execute(schema: &HomeAPI, request: BatchRequest, id: &str) -> BatchResponse {
+    let mut response = CURRENT
+        .scope(id.to_owned(), execute_batch(schema, request))
+        .await;
+    let responses = match &mut response {
+        BatchResponse::Single(x) => std::slice::from_mut(x),
+        BatchResponse::Batch(x) => x.as_mut_slice(),
+    };
+    for error in responses.iter_mut().flat_map(|x| x.errors.iter_mut()) {
+        error
+            .extensions
+            .get_or_insert_with(Default::default)
+            .set("requestId", id);
+    }
+    response
+}
@@ -0,0 +1,71 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+use crate::dynamodb::Client;
+use crate::error::{ApiError, Code};
+use crate::models::{Device, UsedSignature};
+
+// Signed requests carry the device id, a Unix timestamp and
+// hex(HMAC-SHA256(secret, "<timestamp>.<body>")). Requests outside this
+// window are rejected, and signatures inside it are remembered so that a
+// captured request cannot be replayed.
+const MAX_SKEW_SECONDS: i64 = 300;
+
+pub struct Signature {
+    pub device: String,
+    pub timestamp: i64,
+    pub signature: String,
+}
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    hex::encode(mac(secret, timestamp, body).finalize().into_bytes())
+}
+
+pub fn new_secret() -> String {
+    uuid::Uuid::new_v4().to_simple().to_string()
+}
+
+fn unauthorized(message: &str) -> anyhow::Error {
+    ApiError::new(Code::Unauthorized, message).into()
+}
+
+pub async fn verify(dynamodb: &Client, signature: &Signature, body: &[u8]) -> Result<()> {
+    let now = Utc::now();
+    if (now.timestamp() - signature.timestamp).abs() > MAX_SKEW_SECONDS {
+        return Err(unauthorized("signature expired"));
+    }
+
+    // Not from the item cache, so that a rotated secret stops working at once
+    // in every process.
+    let device: Option<Device> = dynamodb
+        .find_fresh_item("DEVICE", &signature.device)
+        .await?;
+    let secret = device
+        .filter(|x| x.deleted_at.is_none())
+        .and_then(|x| x.secret)
+        .ok_or_else(|| unauthorized("invalid signature"))?;
+    let tag = hex::decode(&signature.signature).map_err(|_| unauthorized("invalid signature"))?;
+    mac(&secret, signature.timestamp, body)
+        .verify(&tag)
+        .map_err(|_| unauthorized("invalid signature"))?;
+
+    let used = UsedSignature::new(
+        hex::encode(tag),
+        now + Duration::seconds(MAX_SKEW_SECONDS * 2),
+    );
+    if !dynamodb.put_item_if_absent(&used).await? {
+        return Err(unauthorized("signature already used"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    BatchGetItemError, BatchGetItemInput, BatchGetItemOutput, BatchWriteItemError,
+    BatchWriteItemInput, BatchWriteItemOutput, CreateBackupError, CreateBackupInput,
+    CreateBackupOutput, DeleteItemError, DeleteItemInput, DeleteItemOutput, GetItemError,
+    GetItemInput, GetItemOutput, PutItemError, PutItemInput, PutItemOutput, QueryError, QueryInput,
+    QueryOutput, UpdateItemError, UpdateItemInput, UpdateItemOutput,
+};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+
+use crate::memory::{self, check, key, old_values, Item, CONDITION_FAILED};
+use crate::storage::Storage;
+
+// A single-file Storage for running without AWS. Items are stored as JSON
+// under (table, pk, sk), and expressions are evaluated the same way as in
+// MemoryStorage. Calls block on SQLite, which is fine at home scale.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+fn invalid<E: Display, T>(e: E) -> RusotoError<T> {
+    RusotoError::Validation(e.to_string())
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS items (
+                 tbl TEXT NOT NULL,
+                 pk TEXT NOT NULL,
+                 sk TEXT NOT NULL,
+                 item TEXT NOT NULL,
+                 PRIMARY KEY (tbl, pk, sk)
+             );
+             CREATE INDEX IF NOT EXISTS items_gsi1 ON items (
+                 tbl,
+                 json_extract(item, '$.gsi1pk.S'),
+                 json_extract(item, '$.gsi1sk.S')
+             );
+             CREATE INDEX IF NOT EXISTS items_gsi2 ON items (
+                 tbl,
+                 json_extract(item, '$.gsi2pk.S'),
+                 json_extract(item, '$.gsi2sk.S')
+             );",
+        )?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+fn get(
+    connection: &Connection,
+    table: &str,
+    key: &(String, String),
+) -> anyhow::Result<Option<Item>> {
+    let item: Option<String> = connection
+        .query_row(
+            "SELECT item FROM items WHERE tbl = ?1 AND pk = ?2 AND sk = ?3",
+            params![table, key.0, key.1],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    Ok(item.map(|x| serde_json::from_str(&x)).transpose()?)
+}
+
+fn put(
+    connection: &Connection,
+    table: &str,
+    key: &(String, String),
+    item: &Item,
+) -> anyhow::Result<()> {
+    connection.execute(
+        "INSERT OR REPLACE INTO items (tbl, pk, sk, item) VALUES (?1, ?2, ?3, ?4)",
+        params![table, key.0, key.1, serde_json::to_string(item)?],
+    )?;
+    Ok(())
+}
+
+fn delete(connection: &Connection, table: &str, key: &(String, String)) -> anyhow::Result<()> {
+    connection.execute(
+        "DELETE FROM items WHERE tbl = ?1 AND pk = ?2 AND sk = ?3",
+        params![table, key.0, key.1],
+    )?;
+    Ok(())
+}
+
+// Reads only the rows in the query's key range, in its order and up to its
+// limit, so that memory::select runs on a small set. Index keys are read with
+// json_extract, which the expression indexes created in open cover.
+fn candidates(connection: &Connection, input: &QueryInput) -> anyhow::Result<Vec<Item>> {
+    let (pk_column, sk_column, sk_name) = match &input.index_name {
+        None => ("pk".to_owned(), "sk".to_owned(), "sk".to_owned()),
+        Some(x) if x.chars().all(|c| c.is_ascii_alphanumeric()) => (
+            format!("json_extract(item, '$.{}pk.S')", x),
+            format!("json_extract(item, '$.{}sk.S')", x),
+            format!("{}sk", x),
+        ),
+        Some(x) => return Err(anyhow!("invalid index name: {}", x)),
+    };
+    let range = memory::key_range(input).map_err(|e| anyhow!(e))?;
+    let forward = input.scan_index_forward != Some(false);
+
+    let mut sql = format!("SELECT item FROM items WHERE tbl = ? AND {} = ?", pk_column);
+    let mut values = vec![input.table_name.clone(), range.pk];
+    if let Some((x, inclusive)) = range.low {
+        let op = if inclusive { ">=" } else { ">" };
+        sql += &format!(" AND {} {} ?", sk_column, op);
+        values.push(x);
+    }
+    if let Some((x, inclusive)) = range.high {
+        let op = if inclusive { "<=" } else { "<" };
+        sql += &format!(" AND {} {} ?", sk_column, op);
+        values.push(x);
+    }
+    if let Some(x) = range.prefix {
+        sql += &format!(" AND {0} >= ? AND substr({0}, 1, length(?)) = ?", sk_column);
+        values.extend(vec![x.clone(), x.clone(), x]);
+    }
+
+    // Rows are ordered as select orders them, by the sort key and then the
+    // table key.
+    let mut exact = true;
+    if let Some(start) = &input.exclusive_start_key {
+        let get = |k: &str| start.get(k).and_then(|x| x.s.clone());
+        match (get(&sk_name), get("pk"), get("sk")) {
+            (Some(a), Some(b), Some(c)) => {
+                let op = if forward { ">" } else { "<" };
+                sql += &format!(" AND ({}, pk, sk) {} (?, ?, ?)", sk_column, op);
+                values.extend(vec![a, b, c]);
+            }
+            // Without the start key's sort key, rows before it can't be left
+            // out here, so neither can rows past the limit.
+            _ => exact = false,
+        }
+    }
+    let direction = if forward { "ASC" } else { "DESC" };
+    sql += &format!(" ORDER BY {0} {1}, pk {1}, sk {1}", sk_column, direction);
+    // One more row than the limit tells select that there is another page.
+    if let Some(limit) = input.limit.filter(|_| exact) {
+        sql += &format!(" LIMIT {}", limit + 1);
+    }
+
+    let mut statement = connection.prepare(&sql)?;
+    let rows = statement
+        .query_map(params_from_iter(values.iter()), |row| {
+            row.get::<_, String>(0)
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(rows
+        .iter()
+        .map(|x| serde_json::from_str(x))
+        .collect::<Result<Vec<Item>, _>>()?)
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn get_item(
+        &self,
+        input: GetItemInput,
+    ) -> Result<GetItemOutput, RusotoError<GetItemError>> {
+        let key = key(&input.key).ok_or_else(|| invalid("no key"))?;
+        let connection = self.connection.lock().unwrap();
+        let item = get(&connection, &input.table_name, &key).map_err(invalid)?;
+
+        Ok(GetItemOutput {
+            item,
+            ..Default::default()
+        })
+    }
+
+    async fn batch_get_item(
+        &self,
+        input: BatchGetItemInput,
+    ) -> Result<BatchGetItemOutput, RusotoError<BatchGetItemError>> {
+        let connection = self.connection.lock().unwrap();
+        let mut responses = HashMap::new();
+
+        for (table, request) in input.request_items {
+            let mut items = Vec::new();
+            for key in request.keys.iter().filter_map(key) {
+                if let Some(item) = get(&connection, &table, &key).map_err(invalid)? {
+                    items.push(item);
+                }
+            }
+            responses.insert(table, items);
+        }
+
+        Ok(BatchGetItemOutput {
+            responses: Some(responses),
+            ..Default::default()
+        })
+    }
+
+    async fn query(&self, input: QueryInput) -> Result<QueryOutput, RusotoError<QueryError>> {
+        let connection = self.connection.lock().unwrap();
+        let items = candidates(&connection, &input).map_err(invalid)?;
+        memory::select(input, items.iter()).map_err(RusotoError::Validation)
+    }
+
+    async fn put_item(
+        &self,
+        input: PutItemInput,
+    ) -> Result<PutItemOutput, RusotoError<PutItemError>> {
+        let key = key(&input.item).ok_or_else(|| invalid("no key"))?;
+        let connection = self.connection.lock().unwrap();
+        let current = get(&connection, &input.table_name, &key).map_err(invalid)?;
+
+        let ok = check(
+            &input.condition_expression,
+            current.as_ref().unwrap_or(&Item::new()),
+            &input.expression_attribute_names,
+            &input.expression_attribute_values,
+        )
+        .map_err(RusotoError::Validation)?;
+        if !ok {
+            return Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(
+                CONDITION_FAILED.to_owned(),
+            )));
+        }
+
+        put(&connection, &input.table_name, &key, &input.item).map_err(invalid)?;
+        Ok(PutItemOutput {
+            attributes: old_values(&input.return_values, current),
+            ..Default::default()
+        })
+    }
+
+    async fn update_item(
+        &self,
+        input: UpdateItemInput,
+    ) -> Result<UpdateItemOutput, RusotoError<UpdateItemError>> {
+        let key = key(&input.key).ok_or_else(|| invalid("no key"))?;
+        let connection = self.connection.lock().unwrap();
+        let mut item = get(&connection, &input.table_name, &key)
+            .map_err(invalid)?
+            .unwrap_or_else(|| input.key.clone());
+
+        let ok = check(
+            &input.condition_expression,
+            &item,
+            &input.expression_attribute_names,
+            &input.expression_attribute_values,
+        )
+        .map_err(RusotoError::Validation)?;
+        if !ok {
+            return Err(RusotoError::Service(
+                UpdateItemError::ConditionalCheckFailed(CONDITION_FAILED.to_owned()),
+            ));
+        }
+
+        memory::update(&mut item, &input).map_err(RusotoError::Validation)?;
+        put(&connection, &input.table_name, &key, &item).map_err(invalid)?;
+        Ok(UpdateItemOutput::default())
+    }
+
+    async fn delete_item(
+        &self,
+        input: DeleteItemInput,
+    ) -> Result<DeleteItemOutput, RusotoError<DeleteItemError>> {
+        let key = key(&input.key).ok_or_else(|| invalid("no key"))?;
+        let connection = self.connection.lock().unwrap();
+        let current = get(&connection, &input.table_name, &key).map_err(invalid)?;
+
+        let ok = check(
+            &input.condition_expression,
+            current.as_ref().unwrap_or(&Item::new()),
+            &input.expression_attribute_names,
+            &input.expression_attribute_values,
+        )
+        .map_err(RusotoError::Validation)?;
+        if !ok {
+            return Err(RusotoError::Service(
+                DeleteItemError::ConditionalCheckFailed(CONDITION_FAILED.to_owned()),
+            ));
+        }
+
+        delete(&connection, &input.table_name, &key).map_err(invalid)?;
+        Ok(DeleteItemOutput {
+            attributes: old_values(&input.return_values, current),
+            ..Default::default()
+        })
+    }
+
+    async fn batch_write_item(
+        &self,
+        input: BatchWriteItemInput,
+    ) -> Result<BatchWriteItemOutput, RusotoError<BatchWriteItemError>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(invalid)?;
+
+        for (table, requests) in input.request_items {
+            for request in requests {
+                if let Some(x) = request.put_request {
+                    if let Some(key) = key(&x.item) {
+                        put(&transaction, &table, &key, &x.item).map_err(invalid)?;
+                    }
+                }
+                if let Some(x) = request.delete_request {
+                    if let Some(key) = key(&x.key) {
+                        delete(&transaction, &table, &key).map_err(invalid)?;
+                    }
+                }
+            }
+        }
+
+        transaction.commit().map_err(invalid)?;
+        Ok(BatchWriteItemOutput::default())
+    }
+
+    async fn create_backup(
+        &self,
+        _: CreateBackupInput,
+    ) -> Result<CreateBackupOutput, RusotoError<CreateBackupError>> {
+        Err(invalid("copy the database file to back up SQLite storage"))
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use rusoto_core::Region;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    BatchGetItemError, BatchGetItemInput, BatchGetItemOutput, BatchWriteItemError,
+    BatchWriteItemInput, BatchWriteItemOutput, CreateBackupError, CreateBackupInput,
+    CreateBackupOutput, DeleteItemError, DeleteItemInput, DeleteItemOutput, DynamoDb,
+    DynamoDbClient, GetItemError, GetItemInput, GetItemOutput, PutItemError, PutItemInput,
+    PutItemOutput, QueryError, QueryInput, QueryOutput, UpdateItemError, UpdateItemInput,
+    UpdateItemOutput,
+};
+
+use crate::memory::MemoryStorage;
+use crate::sqlite::SqliteStorage;
+
+// The DynamoDB operations Client is built on. Keeping DynamoDB's request and
+// error types lets Client keep its conditional-write and throttling handling
+// whatever the backend is.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn get_item(
+        &self,
+        input: GetItemInput,
+    ) -> Result<GetItemOutput, RusotoError<GetItemError>>;
+
+    async fn batch_get_item(
+        &self,
+        input: BatchGetItemInput,
+    ) -> Result<BatchGetItemOutput, RusotoError<BatchGetItemError>>;
+
+    async fn query(&self, input: QueryInput) -> Result<QueryOutput, RusotoError<QueryError>>;
+
+    async fn put_item(
+        &self,
+        input: PutItemInput,
+    ) -> Result<PutItemOutput, RusotoError<PutItemError>>;
+
+    async fn update_item(
+        &self,
+        input: UpdateItemInput,
+    ) -> Result<UpdateItemOutput, RusotoError<UpdateItemError>>;
+
+    async fn delete_item(
+        &self,
+        input: DeleteItemInput,
+    ) -> Result<DeleteItemOutput, RusotoError<DeleteItemError>>;
+
+    async fn batch_write_item(
+        &self,
+        input: BatchWriteItemInput,
+    ) -> Result<BatchWriteItemOutput, RusotoError<BatchWriteItemError>>;
+
+    async fn create_backup(
+        &self,
+        input: CreateBackupInput,
+    ) -> Result<CreateBackupOutput, RusotoError<CreateBackupError>>;
+}
+
+#[async_trait]
+impl Storage for DynamoDbClient {
+    async fn get_item(
+        &self,
+        input: GetItemInput,
+    ) -> Result<GetItemOutput, RusotoError<GetItemError>> {
+        DynamoDb::get_item(self, input).await
+    }
+
+    async fn batch_get_item(
+        &self,
+        input: BatchGetItemInput,
+    ) -> Result<BatchGetItemOutput, RusotoError<BatchGetItemError>> {
+        DynamoDb::batch_get_item(self, input).await
+    }
+
+    async fn query(&self, input: QueryInput) -> Result<QueryOutput, RusotoError<QueryError>> {
+        DynamoDb::query(self, input).await
+    }
+
+    async fn put_item(
+        &self,
+        input: PutItemInput,
+    ) -> Result<PutItemOutput, RusotoError<PutItemError>> {
+        DynamoDb::put_item(self, input).await
+    }
+
+    async fn update_item(
+        &self,
+        input: UpdateItemInput,
+    ) -> Result<UpdateItemOutput, RusotoError<UpdateItemError>> {
+        DynamoDb::update_item(self, input).await
+    }
+
+    async fn delete_item(
+        &self,
+        input: DeleteItemInput,
+    ) -> Result<DeleteItemOutput, RusotoError<DeleteItemError>> {
+        DynamoDb::delete_item(self, input).await
+    }
+
+    async fn batch_write_item(
+        &self,
+        input: BatchWriteItemInput,
+    ) -> Result<BatchWriteItemOutput, RusotoError<BatchWriteItemError>> {
+        DynamoDb::batch_write_item(self, input).await
+    }
+
+    async fn create_backup(
+        &self,
+        input: CreateBackupInput,
+    ) -> Result<CreateBackupOutput, RusotoError<CreateBackupError>> {
+        DynamoDb::create_backup(self, input).await
+    }
+}
+
+// Opens the backend named by a URL: "dynamodb", "memory" or
+// "sqlite://<path>".
+pub fn open(url: &str) -> Result<Arc<dyn Storage>> {
+    match url {
+        "dynamodb" => Ok(Arc::new(DynamoDbClient::new(Region::default()))),
+        "memory" => Ok(Arc::new(MemoryStorage::new())),
+        url => match url.strip_prefix("sqlite://") {
+            Some(path) => Ok(Arc::new(SqliteStorage::open(path)?)),
+            None => Err(anyhow!("unknown storage: {}", url)),
+        },
+    }
+}
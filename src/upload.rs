@@ -0,0 +1,198 @@
+use std::io::{BufRead, BufReader, Read};
+
+use anyhow::{anyhow, Result};
+use async_graphql::{Enum, SimpleObject};
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+
+use crate::dynamodb::Client;
+use crate::error::ApiError;
+use crate::webhook;
+
+// Rows are checked and written in batches of this size as the file is read.
+const BATCH_SIZE: usize = 500;
+// Only the first errors are reported; the rest are counted.
+const MAX_ERRORS: usize = 100;
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UploadFormat {
+    // A header row, then one row per item. Needs "device" and "timestamp"
+    // columns; other columns are attributes of the item.
+    Csv,
+    // One JSON object per line with the same keys as the CSV columns.
+    Ndjson,
+}
+
+#[derive(SimpleObject)]
+pub struct RowError {
+    pub row: usize,
+    pub message: String,
+}
+
+#[derive(Default, SimpleObject)]
+pub struct UploadSummary {
+    pub rows: usize,
+    pub written: usize,
+    // Valid rows dropped as duplicates or as too frequent.
+    pub skipped: usize,
+    pub failed: usize,
+    pub errors: Vec<RowError>,
+}
+
+impl UploadSummary {
+    fn fail(&mut self, row: usize, message: String) {
+        self.failed += 1;
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(RowError { row, message });
+        }
+    }
+}
+
+fn cell(s: &str) -> Value {
+    if let Ok(x) = s.parse::<i64>() {
+        return Value::from(x);
+    }
+    if let Ok(x) = s.parse::<f64>() {
+        return Value::from(x);
+    }
+    match s {
+        "true" => Value::from(true),
+        "false" => Value::from(false),
+        s => Value::from(s),
+    }
+}
+
+fn item(prefix: &str, mut fields: Map<String, Value>) -> Result<Map<String, Value>> {
+    let device = match fields.remove("device") {
+        Some(Value::String(x)) if !x.is_empty() => x,
+        _ => return Err(anyhow!("missing device")),
+    };
+    let timestamp = fields
+        .remove("timestamp")
+        .ok_or_else(|| anyhow!("missing timestamp"))?;
+    let timestamp = webhook::parse_timestamp(&timestamp)?;
+
+    fields.insert("pk".to_owned(), Value::from(device));
+    fields.insert(
+        "sk".to_owned(),
+        Value::from(format!("{}{:?}", prefix, timestamp)),
+    );
+    Ok(fields)
+}
+
+// Parses rows lazily so that the file is never held in memory as a whole.
+fn rows<'a, R: Read + Send + 'a>(
+    format: UploadFormat,
+    reader: R,
+) -> Box<dyn Iterator<Item = (usize, Result<Map<String, Value>>)> + Send + 'a> {
+    match format {
+        UploadFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            let headers = match reader.headers() {
+                Ok(x) => x.clone(),
+                Err(e) => return Box::new(std::iter::once((1, Err(e.into())))),
+            };
+            Box::new(reader.into_records().enumerate().map(move |(i, record)| {
+                let row = i + 2;
+                let record = match record {
+                    Ok(x) => x,
+                    Err(e) => return (row, Err(e.into())),
+                };
+                let fields = headers
+                    .iter()
+                    .zip(record.iter())
+                    .filter(|(_, v)| !v.is_empty())
+                    .map(|(k, v)| {
+                        let v = match k {
+                            "device" | "timestamp" => Value::from(v),
+                            _ => cell(v),
+                        };
+                        (k.to_owned(), v)
+                    })
+                    .collect();
+                (row, Ok(fields))
+            }))
+        }
+        UploadFormat::Ndjson => Box::new(
+            BufReader::new(reader)
+                .lines()
+                .enumerate()
+                .map(|(i, line)| (i + 1, line))
+                .filter(|(_, line)| line.as_ref().map_or(true, |x| !x.trim().is_empty()))
+                .map(|(row, line)| {
+                    let fields = line.map_err(Into::into).and_then(|x| {
+                        serde_json::from_str::<Map<String, Value>>(&x).map_err(Into::into)
+                    });
+                    let fields = fields.map(|x| {
+                        x.into_iter()
+                            .map(|(k, v)| (k, webhook::normalize(&v)))
+                            .collect()
+                    });
+                    (row, fields)
+                }),
+        ),
+    }
+}
+
+async fn flush(
+    dynamodb: &Client,
+    kind: &str,
+    batch: Vec<(usize, Result<Map<String, Value>>)>,
+    summary: &mut UploadSummary,
+) -> Result<()> {
+    let mut items = Vec::new();
+    for (row, checked) in batch {
+        summary.rows += 1;
+        match checked {
+            Ok(x) => items.push(x),
+            Err(e) => summary.fail(row, e.to_string()),
+        }
+    }
+
+    if !items.is_empty() {
+        let count = items.len();
+        let written = webhook::write(dynamodb, "upload", kind, items).await?;
+        summary.written += written;
+        summary.skipped += count - written;
+    }
+    Ok(())
+}
+
+// Rows that fail to parse or validate are reported and skipped. A storage
+// error stops the upload; rows written before it stay written.
+pub async fn upload<R: Read + Send + 'static>(
+    dynamodb: &Client,
+    kind: &str,
+    format: UploadFormat,
+    reader: R,
+) -> Result<UploadSummary> {
+    let prefix = webhook::sk_prefix(kind).map_err(|e| ApiError::invalid("kind", e.to_string()))?;
+    let mut summary = UploadSummary::default();
+
+    // Reading the file blocks, so rows are read and checked on a blocking
+    // thread and handed over a batch at a time.
+    let (tx, mut rx) = mpsc::channel(1);
+    let kind_name = kind.to_owned();
+    let reading = tokio::task::spawn_blocking(move || {
+        let mut batch = Vec::new();
+        for (row, fields) in rows(format, reader) {
+            let checked = fields
+                .and_then(|x| item(&prefix, x))
+                .and_then(|x| webhook::check(&kind_name, &x).map(|_| x));
+            batch.push((row, checked));
+
+            // The receiver is gone when a write has failed.
+            if batch.len() >= BATCH_SIZE && tx.blocking_send(std::mem::take(&mut batch)).is_err() {
+                return;
+            }
+        }
+        let _ = tx.blocking_send(batch);
+    });
+
+    while let Some(batch) = rx.recv().await {
+        flush(dynamodb, kind, batch, &mut summary).await?;
+    }
+    reading.await?;
+
+    Ok(summary)
+}
@@ -0,0 +1,71 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+
+use crate::error::ApiError;
+use crate::models::{ApplianceState, BatteryState, Electricity, PlaceCondition, SolarProduction};
+
+// Allows for sensors whose clocks run a little ahead.
+const MAX_FUTURE_MINUTES: i64 = 10;
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), ApiError>;
+}
+
+fn timestamp(timestamp: DateTime<Utc>) -> Result<(), ApiError> {
+    if timestamp > Utc::now() + Duration::minutes(MAX_FUTURE_MINUTES) {
+        return Err(ApiError::invalid("timestamp", "is in the future"));
+    }
+    Ok(())
+}
+
+fn non_negative(field: &str, value: Decimal) -> Result<(), ApiError> {
+    if value.is_sign_negative() {
+        return Err(ApiError::invalid(field, "must not be negative"));
+    }
+    Ok(())
+}
+
+fn percent(field: &str, value: Option<f64>) -> Result<(), ApiError> {
+    match value {
+        Some(x) if !(0.0..=100.0).contains(&x) => {
+            Err(ApiError::invalid(field, "must be between 0 and 100"))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl Validate for Electricity {
+    fn validate(&self) -> Result<(), ApiError> {
+        timestamp(self.timestamp)?;
+        non_negative("cumulative_kwh_p", self.cumulative_kwh_p)?;
+        non_negative("cumulative_kwh_n", self.cumulative_kwh_n)
+    }
+}
+
+impl Validate for PlaceCondition {
+    fn validate(&self) -> Result<(), ApiError> {
+        timestamp(self.timestamp)?;
+        percent("humidity", self.humidity.map(|x| x as f64))?;
+        percent("battery_percent", self.battery_percent)
+    }
+}
+
+impl Validate for ApplianceState {
+    fn validate(&self) -> Result<(), ApiError> {
+        timestamp(self.timestamp)
+    }
+}
+
+impl Validate for SolarProduction {
+    fn validate(&self) -> Result<(), ApiError> {
+        timestamp(self.timestamp)?;
+        non_negative("cumulative_kwh", self.cumulative_kwh)
+    }
+}
+
+impl Validate for BatteryState {
+    fn validate(&self) -> Result<(), ApiError> {
+        timestamp(self.timestamp)?;
+        percent("percent", Some(self.percent))
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::demand;
+use crate::dynamodb::Client;
+use crate::error::{ApiError, Code};
+use crate::firehose;
+use crate::guard;
+use crate::models::{
+    ApplianceState, BatteryState, Device, DynamoItem, Electricity, PlaceCondition, SolarProduction,
+    WebhookRule,
+};
+use crate::signature::{self, Signature};
+use crate::validate::Validate;
+
+// Unknown device ids are normally registered with an "unknown" place. Setting
+// WEBHOOK_REJECT_UNKNOWN_DEVICES rejects them instead.
+static REJECT_UNKNOWN_DEVICES: Lazy<bool> =
+    Lazy::new(|| std::env::var("WEBHOOK_REJECT_UNKNOWN_DEVICES").is_ok());
+
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+fn parse_path(path: &str) -> Result<Vec<Segment>> {
+    let mut rest = path
+        .strip_prefix('$')
+        .ok_or_else(|| anyhow!("path must start with $: {}", path))?;
+    let mut segments = Vec::new();
+
+    while !rest.is_empty() {
+        if let Some(x) = rest.strip_prefix("['") {
+            let end = x
+                .find("']")
+                .ok_or_else(|| anyhow!("unterminated key: {}", path))?;
+            segments.push(Segment::Key(x[..end].to_owned()));
+            rest = &x[end + 2..];
+        } else if let Some(x) = rest.strip_prefix('[') {
+            let end = x
+                .find(']')
+                .ok_or_else(|| anyhow!("unterminated index: {}", path))?;
+            segments.push(Segment::Index(x[..end].parse()?));
+            rest = &x[end + 1..];
+        } else if let Some(x) = rest.strip_prefix('.') {
+            let end = x
+                .find(|c: char| c == '.' || c == '[')
+                .unwrap_or_else(|| x.len());
+            segments.push(Segment::Key(x[..end].to_owned()));
+            rest = &x[end..];
+        } else {
+            return Err(anyhow!("invalid path: {}", path));
+        }
+    }
+
+    Ok(segments)
+}
+
+pub fn select<'a>(value: &'a Value, path: &str) -> Result<Option<&'a Value>> {
+    let mut value = value;
+
+    for segment in parse_path(path)? {
+        let next = match segment {
+            Segment::Key(key) => value.get(key.as_str()),
+            Segment::Index(i) => value.get(i),
+        };
+        match next {
+            Some(x) => value = x,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(value))
+}
+
+fn select_string(value: &Value, path: &str) -> Result<Option<String>> {
+    Ok(select(value, path)?.map(|x| match x {
+        Value::String(s) => s.clone(),
+        x => x.to_string(),
+    }))
+}
+
+pub fn parse_timestamp(value: &Value) -> Result<DateTime<Utc>> {
+    match value {
+        Value::String(s) => Ok(s.parse()?),
+        Value::Number(n) => n
+            .as_i64()
+            .and_then(|x| Utc.timestamp_opt(x, 0).single())
+            .ok_or_else(|| anyhow!("invalid timestamp: {}", n)),
+        x => Err(anyhow!("invalid timestamp: {}", x)),
+    }
+}
+
+pub fn normalize(value: &Value) -> Value {
+    match value.as_f64() {
+        Some(x) if value.is_f64() && x.fract() == 0.0 && x.abs() < i64::MAX as f64 => {
+            Value::from(x as i64)
+        }
+        _ => value.clone(),
+    }
+}
+
+pub fn sk_prefix(kind: &str) -> Result<String> {
+    match kind {
+        "electricity" => Ok(Electricity::sk_prefix()),
+        "place_condition" => Ok(PlaceCondition::sk_prefix()),
+        "appliance_state" => Ok(ApplianceState::sk_prefix()),
+        "solar_production" => Ok(SolarProduction::sk_prefix()),
+        "battery_state" => Ok(BatteryState::sk_prefix()),
+        _ => Err(anyhow!("unknown kind: {}", kind)),
+    }
+}
+
+pub fn apply(rule: &WebhookRule, payload: &Value) -> Result<Vec<Map<String, Value>>> {
+    let prefix = sk_prefix(&rule.kind)?;
+    let entries = match &rule.items_path {
+        Some(path) => match select(payload, path)? {
+            Some(Value::Array(xs)) => xs.iter().collect(),
+            Some(x) => vec![x],
+            None => Vec::new(),
+        },
+        None => vec![payload],
+    };
+
+    entries
+        .into_iter()
+        .map(|entry| {
+            let id = select_string(entry, &rule.id_path)?
+                .ok_or_else(|| anyhow!("missing id at {}", rule.id_path))?;
+            let timestamp = match &rule.timestamp_path {
+                Some(path) => match select(entry, path)? {
+                    Some(x) => parse_timestamp(x)?,
+                    None => return Err(anyhow!("missing timestamp at {}", path)),
+                },
+                None => Utc::now(),
+            };
+
+            let mut item = Map::new();
+            item.insert("pk".to_owned(), Value::from(id));
+            item.insert(
+                "sk".to_owned(),
+                Value::from(format!("{}{:?}", prefix, timestamp)),
+            );
+            if let Some(path) = &rule.place_path {
+                if let Some(place) = select_string(entry, path)? {
+                    item.insert("place".to_owned(), Value::from(place));
+                }
+            }
+            for (field, path) in rule.fields.iter() {
+                if let Some(x) = select(entry, path)? {
+                    item.insert(field.to_owned(), normalize(x));
+                }
+            }
+
+            Ok(item)
+        })
+        .collect()
+}
+
+// Looks up the devices of all items in one batch, rejecting deleted ones, and
+// returns the places of those whose items carry no place.
+async fn places(
+    dynamodb: &Client,
+    items: &[Map<String, Value>],
+) -> Result<HashMap<String, String>> {
+    let mut ids = items
+        .iter()
+        .map(|x| x["pk"].as_str().unwrap_or_default().to_owned())
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    let unplaced = items
+        .iter()
+        .filter(|x| !x.contains_key("place"))
+        .map(|x| x["pk"].as_str().unwrap_or_default())
+        .collect::<Vec<_>>();
+
+    let keys = ids
+        .iter()
+        .map(|x| ("DEVICE".to_owned(), x.clone()))
+        .collect::<Vec<_>>();
+    let devices: Vec<Option<Device>> = dynamodb.batch_get_items(&keys).await?;
+    let mut places = HashMap::new();
+
+    for (id, device) in ids.into_iter().zip(devices) {
+        let place = match device {
+            Some(device) if device.deleted_at.is_some() => {
+                return Err(ApiError::invalid("id", format!("deleted device: {}", id)).into())
+            }
+            Some(device) => device.place,
+            None if !unplaced.contains(&id.as_str()) => continue,
+            None if *REJECT_UNKNOWN_DEVICES => {
+                return Err(ApiError::invalid("id", format!("unknown device: {}", id)).into())
+            }
+            None => {
+                let mut device = Device::new(id.clone());
+                device.place = "unknown".to_owned();
+                if dynamodb.put_item_if_absent(&device).await? {
+                    device.place
+                } else {
+                    // Created by a concurrent request, which may have set the place.
+                    let device: Device = dynamodb.get_item("DEVICE", &id).await?;
+                    device.place
+                }
+            }
+        };
+        places.insert(id, place);
+    }
+
+    Ok(places)
+}
+
+async fn put<D>(dynamodb: &Client, kind: &str, items: Vec<Map<String, Value>>) -> Result<Vec<D>>
+where
+    D: DeserializeOwned + Serialize + Validate,
+{
+    // Devices are created while looking up places, so nothing is written
+    // until every item is known to be valid.
+    for item in &items {
+        check_as::<D>(item)?;
+    }
+    let places = places(dynamodb, &items).await?;
+    let mut records = Vec::new();
+
+    for mut item in items {
+        if !item.contains_key("place") {
+            let id = item["pk"].as_str().unwrap_or_default();
+            let place = places.get(id).cloned().unwrap_or_default();
+            item.insert("place".to_owned(), Value::from(place));
+        }
+        records.push(serde_json::from_value::<D>(Value::Object(item))?);
+    }
+
+    dynamodb.put_items(records.iter().collect()).await?;
+    firehose::forward(kind, &records);
+    Ok(records)
+}
+
+pub async fn receive(
+    dynamodb: &Client,
+    source: &str,
+    signature: Option<Signature>,
+    body: &[u8],
+) -> Result<Option<usize>> {
+    let device = match signature {
+        Some(x) => {
+            signature::verify(dynamodb, &x, body).await?;
+            Some(x.device)
+        }
+        None => None,
+    };
+    let payload: Value = serde_json::from_slice(body)
+        .map_err(|e| ApiError::new(Code::ValidationFailed, e.to_string()))?;
+
+    ingest(dynamodb, source, &payload, device.as_deref()).await
+}
+
+// A signed request may only carry readings for the device that signed it, and
+// an unsigned one only for devices without a secret.
+pub async fn ingest(
+    dynamodb: &Client,
+    source: &str,
+    payload: &Value,
+    device: Option<&str>,
+) -> Result<Option<usize>> {
+    let rule: WebhookRule = match dynamodb.find_item("WEBHOOK_RULE", source).await? {
+        Some(rule) => rule,
+        None => return Ok(None),
+    };
+    if rule.signed && device.is_none() {
+        return Err(ApiError::new(Code::Unauthorized, "signature required").into());
+    }
+    let items = apply(&rule, payload)?;
+    match device {
+        Some(device) => {
+            if items
+                .iter()
+                .any(|x| x.get("pk").and_then(Value::as_str) != Some(device))
+            {
+                return Err(
+                    ApiError::new(Code::Unauthorized, "readings for another device").into(),
+                );
+            }
+        }
+        None => {
+            // Devices with a secret only accept signed readings, whatever the
+            // rule says.
+            let keys = items
+                .iter()
+                .filter_map(|x| x.get("pk").and_then(Value::as_str))
+                .map(|x| ("DEVICE".to_owned(), x.to_owned()))
+                .collect::<Vec<_>>();
+            let devices: Vec<Option<Device>> = dynamodb.batch_get_items(&keys).await?;
+            if devices.iter().flatten().any(|x| x.secret.is_some()) {
+                return Err(ApiError::new(Code::Unauthorized, "signature required").into());
+            }
+        }
+    }
+
+    Ok(Some(write(dynamodb, source, &rule.kind, items).await?))
+}
+
+fn check_as<D>(item: &Map<String, Value>) -> Result<()>
+where
+    D: DeserializeOwned + Validate,
+{
+    let mut item = item.clone();
+    item.entry("place").or_insert_with(|| Value::from(""));
+    serde_json::from_value::<D>(Value::Object(item))?.validate()?;
+    Ok(())
+}
+
+// Checks that write would accept the item, without writing it.
+pub fn check(kind: &str, item: &Map<String, Value>) -> Result<()> {
+    match kind {
+        "electricity" => check_as::<Electricity>(item),
+        "place_condition" => check_as::<PlaceCondition>(item),
+        "appliance_state" => check_as::<ApplianceState>(item),
+        "solar_production" => check_as::<SolarProduction>(item),
+        "battery_state" => check_as::<BatteryState>(item),
+        kind => Err(anyhow!("unknown kind: {}", kind)),
+    }
+}
+
+// Writes items already mapped to a model's attributes, as produced by apply
+// or by the line protocol parser.
+pub async fn write(
+    dynamodb: &Client,
+    source: &str,
+    kind: &str,
+    mut items: Vec<Map<String, Value>>,
+) -> Result<usize> {
+    let (dropped, admitted) = guard::filter(&mut items, &sk_prefix(kind)?);
+    if dropped > 0 {
+        println!(
+            "{}: dropped {} duplicate or too frequent items",
+            source, dropped
+        );
+    }
+
+    let written = match kind {
+        "electricity" => {
+            let records = put::<Electricity>(dynamodb, kind, items).await?;
+            // The readings are stored; a failed peak update must not fail
+            // the write and make the client send them again.
+            if let Err(e) = demand::track(dynamodb, &records).await {
+                println!("{}: peak demand: {:?}", source, e);
+            }
+            records.len()
+        }
+        "place_condition" => put::<PlaceCondition>(dynamodb, kind, items).await?.len(),
+        "appliance_state" => put::<ApplianceState>(dynamodb, kind, items).await?.len(),
+        "solar_production" => put::<SolarProduction>(dynamodb, kind, items).await?.len(),
+        "battery_state" => put::<BatteryState>(dynamodb, kind, items).await?.len(),
+        kind => return Err(anyhow!("unknown kind: {}", kind)),
+    };
+    guard::commit(admitted);
+    Ok(written)
+}